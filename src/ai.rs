@@ -0,0 +1,237 @@
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+
+use crate::book::OpeningBook;
+use crate::eval::EvalWeights;
+use crate::piece::PieceColour;
+use crate::position::{Move, Position};
+use crate::search::{SearchConfig, SearchLimits, Searcher};
+use crate::square::{CurrentPosition, PlayMoveEvent};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Difficulty {
+    Beginner,
+    #[default]
+    Casual,
+    Club,
+    Expert,
+}
+
+pub const DIFFICULTIES: [Difficulty; 4] = [
+    Difficulty::Beginner, Difficulty::Casual, Difficulty::Club, Difficulty::Expert,
+];
+
+impl Difficulty {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "beginner",
+            Difficulty::Casual => "casual",
+            Difficulty::Club => "club",
+            Difficulty::Expert => "expert",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        DIFFICULTIES.into_iter().find(|difficulty| difficulty.name() == name)
+    }
+
+    pub fn limits(&self) -> SearchLimits {
+        match self {
+            Difficulty::Beginner => SearchLimits { depth: 1, nodes: Some(500), time: None },
+            Difficulty::Casual => SearchLimits { depth: 2, nodes: Some(5_000), time: None },
+            Difficulty::Club => SearchLimits { depth: 3, nodes: Some(50_000), time: None },
+            // the only level that can run into the clock, which makes it a little less repeatable
+            Difficulty::Expert => SearchLimits { depth: 5, nodes: Some(200_000), time: Some(Duration::from_secs(5)) },
+        }
+    }
+
+    // centipawns of noise on every evaluation, a beginner can miss a hanging knight
+    pub fn eval_noise(&self) -> i32 {
+        match self {
+            Difficulty::Beginner => 150,
+            Difficulty::Casual => 60,
+            Difficulty::Club => 20,
+            Difficulty::Expert => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Personality {
+    #[default]
+    Balanced,
+    // growing is worth a lot more than usual, so it goes for captures that make its pieces big
+    Glutton,
+    // thinks big pieces are a liability and would rather keep them (and its king) small
+    Turtle,
+}
+
+pub const PERSONALITIES: [Personality; 3] = [Personality::Balanced, Personality::Glutton, Personality::Turtle];
+
+impl Personality {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Personality::Balanced => "balanced",
+            Personality::Glutton => "glutton",
+            Personality::Turtle => "turtle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PERSONALITIES.into_iter().find(|personality| personality.name() == name)
+    }
+
+    pub fn apply(&self, weights: &mut EvalWeights) {
+        match self {
+            Personality::Balanced => {}
+            Personality::Glutton => {
+                for weight in weights.footprint.iter_mut() {
+                    *weight *= 3;
+                }
+            }
+            Personality::Turtle => {
+                for weight in weights.footprint.iter_mut() {
+                    *weight = -*weight / 2;
+                }
+                weights.king_footprint *= 2;
+            }
+        }
+    }
+}
+
+/// How a computer player plays.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct AiSettings {
+    pub difficulty: Difficulty,
+    pub personality: Personality,
+}
+
+impl AiSettings {
+    /// The search config for one move. `seed` picks the evaluation noise, so the same seed
+    /// plays the same move.
+    pub fn search_config(&self, seed: u64) -> SearchConfig {
+        let mut weights = EvalWeights::default();
+        self.personality.apply(&mut weights);
+        SearchConfig {
+            weights,
+            eval_noise: self.difficulty.eval_noise(),
+            seed,
+            ..default()
+        }
+    }
+
+    /// Plays from the opening book while it can, and searches after that.
+    pub fn choose_move(&self, position: &Position, seed: u64) -> Option<Move> {
+        if let Some(mv) = OpeningBook::standard().pick(position, seed) {
+            return Some(mv);
+        }
+        Searcher::new(self.search_config(seed))
+            .search(position, self.difficulty.limits())
+            .best_move
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlayerKind {
+    Human,
+    Computer(AiSettings),
+    // moves come from somewhere other than the board or the engine, like a puzzle's replies
+    Scripted,
+}
+
+/// Who is playing each colour, picked on the new game screen.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Players {
+    pub white: PlayerKind,
+    pub black: PlayerKind,
+}
+
+impl Default for Players {
+    fn default() -> Self {
+        Self { white: PlayerKind::Human, black: PlayerKind::Human }
+    }
+}
+
+impl Players {
+    pub fn get(&self, colour: PieceColour) -> PlayerKind {
+        match colour {
+            PieceColour::White => self.white,
+            PieceColour::Black => self.black,
+        }
+    }
+
+    pub fn get_mut(&mut self, colour: PieceColour) -> &mut PlayerKind {
+        match colour {
+            PieceColour::White => &mut self.white,
+            PieceColour::Black => &mut self.black,
+        }
+    }
+
+    pub fn is_human(&self, colour: PieceColour) -> bool {
+        self.get(colour) == PlayerKind::Human
+    }
+}
+
+/// The search a computer player is running, if any.
+#[derive(Resource, Default)]
+pub struct AiTask {
+    task: Option<Task<Option<Move>>>,
+    // hash of the position the last search was started on, so a side with no moves left isn't
+    // searched again every frame
+    started_on: Option<u64>,
+}
+
+impl AiTask {
+    /// Drops the running search (its move is never played), e.g. when a new game starts.
+    pub fn cancel(&mut self) {
+        self.task = None;
+        self.started_on = None;
+    }
+}
+
+fn start_ai_move(
+    time: Res<Time>,
+    players: Res<Players>,
+    position: Res<CurrentPosition>,
+    mut ai_task: ResMut<AiTask>,
+) {
+    if ai_task.task.is_some() || ai_task.started_on == Some(position.0.hash) {
+        return;
+    }
+    let PlayerKind::Computer(settings) = players.get(position.0.turn) else {
+        return;
+    };
+    ai_task.started_on = Some(position.0.hash);
+
+    let position = position.0.clone();
+    let seed = time.elapsed().as_nanos() as u64 ^ position.hash;
+    ai_task.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        settings.choose_move(&position, seed)
+    }));
+}
+
+fn finish_ai_move(
+    mut ai_task: ResMut<AiTask>,
+    mut play_move_writer: EventWriter<PlayMoveEvent>,
+) {
+    let Some(task) = ai_task.task.as_mut() else {
+        return;
+    };
+    if let Some(best_move) = block_on(future::poll_once(task)) {
+        ai_task.task = None;
+        if let Some(mv) = best_move {
+            play_move_writer.send(PlayMoveEvent(mv));
+        }
+    }
+}
+
+pub struct AiPlugin;
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Players::default())
+            .insert_resource(AiTask::default())
+            .add_systems(Update, (start_ai_move, finish_ai_move).chain());
+    }
+}
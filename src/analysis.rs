@@ -0,0 +1,426 @@
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use bevy::utils::Duration;
+use gmtk2024::ai::{AiTask, PlayerKind, Players};
+use gmtk2024::history::GameHistory;
+use gmtk2024::piece::PieceColour;
+use gmtk2024::record::{first_ply, line_text, move_text, GameRecord, MoveSymbol};
+use gmtk2024::search::{SearchConfig, SearchLimits, SearchResult, Searcher, MATE_SCORE};
+use gmtk2024::square::{CurrentPosition, SetPositionEvent};
+
+use crate::new_game::{setup_new_game_screen, spawn_button, NewGameScreen};
+use crate::Ui;
+
+/// Going back over a finished game: stepping through its moves, trying other ones, and the
+/// engine's opinion of every position on the way.
+#[derive(Resource, Default)]
+pub struct Analysis {
+    pub active: bool,
+    // who was playing, put back when the analysis is done. Both sides are human meanwhile
+    players: Players,
+    task: Option<Task<AnalysisResult>>,
+    // hash of the position the last search was started on
+    searched: Option<u64>,
+    result: Option<AnalysisResult>,
+}
+
+// the engine's last word, and the ply and side to move it was for
+type AnalysisResult = (SearchResult, usize, PieceColour);
+
+/// On the game over screen, next to "Play again".
+#[derive(Component)]
+pub struct AnalyseButton;
+
+/// Starts analysing the game that just finished, at this many plies into it.
+#[derive(Event)]
+pub struct AnalyseEvent(pub usize);
+
+#[derive(Component)]
+struct AnalysisPanel;
+
+#[derive(Component)]
+struct EvalBarFill;
+
+#[derive(Component)]
+struct EvalText;
+
+#[derive(Component)]
+struct LeaveAnalysisButton;
+
+// the moves up to the position being shown, then the ones after it in another colour
+#[derive(Component)]
+struct MoveList;
+
+#[derive(Component)]
+struct CommentEntryText;
+
+/// The comment being typed for the position being shown, if one is.
+#[derive(Resource, Default)]
+struct CommentEntry(Option<String>);
+
+pub fn analysing(analysis: Res<Analysis>) -> bool {
+    analysis.active
+}
+
+// from white's point of view, in pawns, or moves to mate
+fn score_text(score: i32) -> String {
+    if score.abs() > MATE_SCORE - 1000 {
+        let moves = (MATE_SCORE - score.abs() + 1) / 2;
+        if score > 0 { format!("#{moves}") } else { format!("#-{moves}") }
+    } else {
+        format!("{:+.2}", score as f32 / 100.0)
+    }
+}
+
+// how much of the bar is white's, squashed so a few pawns up is most of it
+fn bar_fraction(score: i32) -> f32 {
+    1.0 / (1.0 + 10f32.powf(-score as f32 / 400.0))
+}
+
+fn setup_analysis_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 20.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        AnalysisPanel,
+    )).with_children(|parent| {
+        // the evaluation bar, black's share on top
+        parent.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                top: Val::Percent(15.0),
+                width: Val::Px(20.0),
+                height: Val::Percent(60.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::FlexEnd,
+                ..default()
+            },
+            background_color: Color::srgb(0.1, 0.1, 0.0).into(),
+            ..default()
+        }).with_children(|bar| {
+            bar.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(50.0),
+                        ..default()
+                    },
+                    background_color: Color::srgb(0.9, 0.9, 1.0).into(),
+                    ..default()
+                },
+                EvalBarFill,
+            ));
+        });
+
+        parent.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                right: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(5.0),
+                ..default()
+            },
+            ..default()
+        }).with_children(|column| {
+            column.spawn((TextBundle::from_section("", text_style.clone()), EvalText));
+            column.spawn(TextBundle::from_section(
+                "Left/right: step through the moves. Home/end: start and end. \
+                Up/down: switch variation. P: make it the main line. 1-6: mark the move ! ? !! ?? !? ?!, \
+                0: unmark it. C: comment on the position. S/L: save to or load from analysis.txt.",
+                TextStyle { font_size: 16.0, ..text_style.clone() },
+            ));
+            spawn_button(column, &asset_server, 160.0, "New game", LeaveAnalysisButton);
+        });
+
+        parent.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.0),
+                top: Val::Px(70.0),
+                width: Val::Px(320.0),
+                max_height: Val::Percent(60.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.0),
+                overflow: Overflow::clip(),
+                ..default()
+            },
+            background_color: Color::srgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..default()
+        }).with_children(|column| {
+            let move_style = TextStyle { font_size: 18.0, ..text_style.clone() };
+            column.spawn((
+                TextBundle::from_sections([
+                    TextSection::new("", move_style.clone()),
+                    TextSection::new("", TextStyle { color: Color::srgb(0.5, 0.5, 0.5), ..move_style }),
+                ]),
+                MoveList,
+            ));
+            column.spawn((
+                TextBundle::from_section("", TextStyle { font_size: 18.0, color: Color::srgb(0.9, 0.8, 0.5), ..text_style }),
+                CommentEntryText,
+            ));
+        });
+    });
+}
+
+fn analyse_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<AnalyseButton>)>,
+    history: Res<GameHistory>,
+    mut analyse_writer: EventWriter<AnalyseEvent>,
+) {
+    if interaction_query.iter().any(|interaction| *interaction == Interaction::Pressed) {
+        analyse_writer.send(AnalyseEvent(history.ply()));
+    }
+}
+
+fn start_analysis(
+    mut analyse_events: EventReader<AnalyseEvent>,
+    mut analysis: ResMut<Analysis>,
+    (mut players, mut ai_task): (ResMut<Players>, ResMut<AiTask>),
+    mut history: ResMut<GameHistory>,
+    mut set_position_writer: EventWriter<SetPositionEvent>,
+    mut ui_visibility: Query<&mut Visibility, With<Ui>>,
+    mut panel_visibility: Query<&mut Visibility, (With<AnalysisPanel>, Without<Ui>)>,
+) {
+    let Some(&AnalyseEvent(ply)) = analyse_events.read().last() else {
+        return;
+    };
+    if history.ply() != ply {
+        history.to_start();
+        for _ in 0..ply {
+            history.forward();
+        }
+        set_position_writer.send(SetPositionEvent(history.position()));
+    }
+    *ui_visibility.single_mut() = Visibility::Hidden;
+    *panel_visibility.single_mut() = Visibility::Visible;
+    analysis.active = true;
+    analysis.searched = None;
+    // both sides are moved by hand while exploring
+    analysis.players = *players;
+    *players = Players { white: PlayerKind::Human, black: PlayerKind::Human };
+    ai_task.cancel();
+}
+
+fn leave_analysis_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<LeaveAnalysisButton>)>,
+    mut analysis: ResMut<Analysis>,
+    mut comment_entry: ResMut<CommentEntry>,
+    mut players: ResMut<Players>,
+    mut panel_visibility: Query<&mut Visibility, With<AnalysisPanel>>,
+    mut new_game_visibility: Query<&mut Visibility, (With<NewGameScreen>, Without<AnalysisPanel>)>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    *panel_visibility.single_mut() = Visibility::Hidden;
+    *new_game_visibility.single_mut() = Visibility::Visible;
+    *players = analysis.players;
+    *analysis = Analysis::default();
+    comment_entry.0 = None;
+}
+
+fn save_analysis(history: &GameHistory) {
+    let mut record = history.to_record();
+    record.set_tag("Event", "Analysis");
+    match std::fs::write("analysis.txt", record.to_string()) {
+        Ok(()) => info!("saved the game and its variations to analysis.txt"),
+        Err(err) => warn!("can't write analysis.txt: {err}"),
+    }
+}
+
+fn load_analysis() -> Result<GameHistory, String> {
+    let text = std::fs::read_to_string("analysis.txt").map_err(|err| format!("can't read analysis.txt: {err}"))?;
+    GameHistory::from_record(&GameRecord::parse(&text)?)
+}
+
+fn symbol_key(key: KeyCode) -> Option<usize> {
+    [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4, KeyCode::Digit5, KeyCode::Digit6]
+        .into_iter()
+        .position(|digit| digit == key)
+}
+
+fn step_through_history(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<GameHistory>,
+    mut comment_entry: ResMut<CommentEntry>,
+    mut set_position_writer: EventWriter<SetPositionEvent>,
+) {
+    // the keys are letters of the comment meanwhile
+    if comment_entry.0.is_some() {
+        return;
+    }
+    let line = history.line();
+    let mut loaded = false;
+    for &key in keys.get_just_pressed() {
+        if let Some(i) = symbol_key(key) {
+            // pressing it again takes it off
+            let symbol = Some(MoveSymbol::ALL[i]).filter(|&symbol| history.symbol() != Some(symbol));
+            history.set_symbol(symbol);
+            continue;
+        }
+        match key {
+            KeyCode::ArrowLeft => { history.back(); },
+            KeyCode::ArrowRight => { history.forward(); },
+            KeyCode::Home => history.to_start(),
+            KeyCode::End => history.to_end(),
+            KeyCode::ArrowUp => { history.switch_variation(-1); },
+            KeyCode::ArrowDown => { history.switch_variation(1); },
+            KeyCode::KeyP => history.promote_variation(),
+            KeyCode::Digit0 => history.set_symbol(None),
+            KeyCode::KeyC => comment_entry.0 = Some(history.comment().to_string()),
+            KeyCode::KeyS => save_analysis(&history),
+            KeyCode::KeyL => match load_analysis() {
+                Ok(loaded_history) => {
+                    *history = loaded_history;
+                    loaded = true;
+                    info!("loaded analysis.txt");
+                }
+                Err(err) => warn!("{err}"),
+            },
+            _ => {},
+        }
+    }
+    if loaded || history.line() != line {
+        set_position_writer.send(SetPositionEvent(history.position()));
+    }
+}
+
+// runs before step_through_history, so the key that starts a comment isn't typed into it
+fn type_comment(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut comment_entry: ResMut<CommentEntry>,
+    mut history: ResMut<GameHistory>,
+) {
+    let Some(comment) = comment_entry.0.as_mut() else {
+        keyboard_events.clear();
+        return;
+    };
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Character(text) => comment.push_str(text),
+            Key::Space => comment.push(' '),
+            Key::Backspace => { comment.pop(); },
+            Key::Enter => {
+                let comment = comment.trim().to_string();
+                history.set_comment(comment);
+                comment_entry.0 = None;
+                return;
+            }
+            Key::Escape => {
+                comment_entry.0 = None;
+                return;
+            }
+            _ => {},
+        }
+    }
+}
+
+fn update_move_list(
+    history: Res<GameHistory>,
+    comment_entry: Res<CommentEntry>,
+    mut move_list: Query<&mut Text, With<MoveList>>,
+    mut comment_entry_text: Query<&mut Text, (With<CommentEntryText>, Without<MoveList>)>,
+) {
+    let mut text = move_list.single_mut();
+    let first_ply = first_ply(&history.start_position());
+    text.sections[0].value = line_text(first_ply, &history.line_played());
+    text.sections[1].value = format!(" {}", line_text(first_ply + history.ply(), &history.line_ahead()));
+    comment_entry_text.single_mut().sections[0].value = match &comment_entry.0 {
+        Some(comment) => format!("Comment: {comment}_\nEnter to keep it, Esc to leave it"),
+        None => String::new(),
+    };
+}
+
+fn start_analysis_search(position: Res<CurrentPosition>, history: Res<GameHistory>, mut analysis: ResMut<Analysis>) {
+    if analysis.searched == Some(position.0.hash) {
+        return;
+    }
+    analysis.searched = Some(position.0.hash);
+    let position = position.0.clone();
+    // for numbering the best line
+    let ply = first_ply(&history.start_position()) + history.ply();
+    // whatever was being searched is out of date, so it's dropped
+    analysis.result = None;
+    analysis.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        let limits = SearchLimits { depth: 5, nodes: Some(300_000), time: Some(Duration::from_secs(3)) };
+        let result = Searcher::new(SearchConfig::default()).search(&position, limits);
+        (result, ply, position.turn)
+    }));
+}
+
+fn finish_analysis_search(mut analysis: ResMut<Analysis>) {
+    let Some(task) = analysis.task.as_mut() else {
+        return;
+    };
+    if let Some(result) = block_on(future::poll_once(task)) {
+        analysis.task = None;
+        analysis.result = Some(result);
+    }
+}
+
+fn update_analysis_panel(
+    analysis: Res<Analysis>,
+    mut fill_query: Query<&mut Style, With<EvalBarFill>>,
+    mut eval_text: Query<&mut Text, With<EvalText>>,
+) {
+    let mut text = eval_text.single_mut();
+    let Some((result, ply, turn)) = &analysis.result else {
+        text.sections[0].value = "Thinking...".to_string();
+        return;
+    };
+    let score = match turn {
+        PieceColour::White => result.score,
+        PieceColour::Black => -result.score,
+    };
+    fill_query.single_mut().height = Val::Percent(bar_fraction(score) * 100.0);
+    text.sections[0].value = format!(
+        "{}  depth {}  {}",
+        score_text(score),
+        result.depth,
+        move_text(*ply, &result.pv),
+    );
+}
+
+pub struct AnalysisPlugin;
+impl Plugin for AnalysisPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Analysis::default())
+            .insert_resource(CommentEntry::default())
+            .add_event::<AnalyseEvent>()
+            // under the new game screen
+            .add_systems(Startup, setup_analysis_panel.before(setup_new_game_screen))
+            .add_systems(Update, ((analyse_button, start_analysis).chain(), leave_analysis_button))
+            .add_systems(Update, (
+                type_comment,
+                step_through_history,
+                start_analysis_search,
+                finish_analysis_search,
+                update_analysis_panel.run_if(resource_changed::<Analysis>),
+                update_move_list.run_if(resource_changed::<GameHistory>.or_else(resource_changed::<CommentEntry>)),
+            ).chain().run_if(analysing));
+    }
+}
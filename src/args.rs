@@ -0,0 +1,24 @@
+/// Takes `name` and the value after it out of `args`, if it's there.
+pub fn take_option<'a>(args: &mut Vec<&'a str>, name: &str) -> Result<Option<&'a str>, String> {
+    let Some(i) = args.iter().position(|&arg| arg == name) else {
+        return Ok(None);
+    };
+    let value = *args.get(i + 1).ok_or(format!("{name} needs a value"))?;
+    args.drain(i..i + 2);
+    Ok(Some(value))
+}
+
+/// Like `take_option`, reading the value as a `T`.
+pub fn parse_option<T: std::str::FromStr>(args: &mut Vec<&str>, name: &str) -> Result<Option<T>, String> {
+    match take_option(args, name)? {
+        Some(value) => value.parse().map(Some).map_err(|_| format!("bad value for {name}: {value}")),
+        None => Ok(None),
+    }
+}
+
+/// Takes `name` out of `args`, returning whether it was there.
+pub fn take_flag(args: &mut Vec<&str>, name: &str) -> bool {
+    let found = args.contains(&name);
+    args.retain(|&arg| arg != name);
+    found
+}
@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+use gmtk2024::ai::{AiTask, Players};
+use gmtk2024::clock::{ChessClock, TimeoutEvent};
+use gmtk2024::history::GameHistory;
+use gmtk2024::record::GameResult;
+use gmtk2024::save::SavedGame;
+use gmtk2024::square::{MoveEvent, SetPositionEvent};
+
+use crate::analysis::analysing;
+use crate::correspondence_mode::in_correspondence;
+use crate::game_clock::ClockSettings;
+use crate::new_game::{NewGameEvent, NewGameScreen};
+use crate::puzzle_mode::in_puzzle;
+use crate::replay::in_replay;
+use crate::resign::AgreedResultEvent;
+
+const SAVE_FILE: &str = "autosave.txt";
+
+/// On the new game screen, under "Start", while there's a game to carry on.
+#[derive(Component)]
+pub struct ResumeButton;
+
+/// The game left unfinished last time, until it's resumed or a new one is started.
+#[derive(Resource, Default)]
+struct ResumableGame(Option<SavedGame>);
+
+impl ResumableGame {
+    fn load() -> Self {
+        let text = match std::fs::read_to_string(SAVE_FILE) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Self(None),
+            Err(err) => {
+                warn!("can't read {SAVE_FILE}: {err}");
+                return Self(None);
+            }
+        };
+        match SavedGame::parse(&text) {
+            Ok(saved) => Self(Some(saved)),
+            Err(err) => {
+                warn!("can't resume the game in {SAVE_FILE}: {err}");
+                Self(None)
+            }
+        }
+    }
+}
+
+// after every move of a game played on this computer, and gone once it's over
+fn autosave(
+    mut move_events: EventReader<MoveEvent>,
+    mut timeout_events: EventReader<TimeoutEvent>,
+    mut agreed_result_events: EventReader<AgreedResultEvent>,
+    (history, players, clock): (Res<GameHistory>, Res<Players>, Res<ChessClock>),
+) {
+    let moved = move_events.read().count() > 0;
+    let ended = timeout_events.read().count() + agreed_result_events.read().count() > 0;
+    if !moved && !ended {
+        return;
+    }
+    // someone on another computer plays the game, so it isn't this one's to save
+    let Some(saved) = SavedGame::new(&history, *players, &clock) else {
+        return;
+    };
+    if ended || history.result() != GameResult::Unfinished {
+        match std::fs::remove_file(SAVE_FILE) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => warn!("can't remove {SAVE_FILE}: {err}"),
+            _ => {}
+        }
+        return;
+    }
+    if let Err(err) = std::fs::write(SAVE_FILE, saved.to_text()) {
+        warn!("can't write {SAVE_FILE}: {err}");
+    }
+}
+
+fn resume_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<ResumeButton>)>,
+    mut resumable: ResMut<ResumableGame>,
+    (mut players, mut ai_task): (ResMut<Players>, ResMut<AiTask>),
+    (mut history, mut clock, mut clock_settings): (ResMut<GameHistory>, ResMut<ChessClock>, ResMut<ClockSettings>),
+    mut set_position_writer: EventWriter<SetPositionEvent>,
+    mut new_game_visibility: Query<&mut Visibility, With<NewGameScreen>>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    let Some(saved) = resumable.0.take() else {
+        return;
+    };
+    *new_game_visibility.single_mut() = Visibility::Hidden;
+    ai_task.cancel();
+    *players = saved.players;
+    // so playing again afterwards keeps the same clock
+    clock_settings.0 = saved.control;
+    *clock = saved.clock();
+    set_position_writer.send(SetPositionEvent(saved.history.position()));
+    *history = saved.history;
+}
+
+// the old game is given up on once another's started
+fn forget_resumable_game(mut new_game_events: EventReader<NewGameEvent>, mut resumable: ResMut<ResumableGame>) {
+    if new_game_events.read().count() > 0 && resumable.0.is_some() {
+        resumable.0 = None;
+    }
+}
+
+fn update_resume_button(resumable: Res<ResumableGame>, mut button_style: Query<&mut Style, With<ResumeButton>>) {
+    button_style.single_mut().display = match resumable.0 {
+        Some(_) => Display::Flex,
+        None => Display::None,
+    };
+}
+
+pub struct AutosavePlugin;
+impl Plugin for AutosavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ResumableGame::load())
+            .add_systems(Update, (
+                autosave.run_if(not(analysing).and_then(not(in_puzzle)).and_then(not(in_correspondence)).and_then(not(in_replay))),
+                resume_button,
+                forget_resumable_game,
+                update_resume_button.run_if(resource_changed::<ResumableGame>),
+            ).chain());
+    }
+}
@@ -0,0 +1,101 @@
+// Builds and looks into opening books.
+//
+//   book build [--plies <n>] [--min-weight <n>] --out <file> <games...>
+//       adds the first --plies moves (default 12) of every game in the game files, which are
+//       in the format the tournament writes with --pgn, then drops moves lighter than
+//       --min-weight (default 1)
+//   book show [--book <file>] [moves...]
+//       the book moves after the given moves, from the built in book unless --book is given
+//
+// the built in book is assets/opening_book.txt, made from assets/opening_lines.txt with
+//
+//   book build --out assets/opening_book.txt assets/opening_lines.txt
+
+use gmtk2024::args::take_option;
+use gmtk2024::book::OpeningBook;
+use gmtk2024::position::Position;
+use gmtk2024::record::{parse_moves, GameRecord};
+use std::process::ExitCode;
+
+fn build(games_paths: &[&str], out_path: &str, plies: usize, min_weight: u32) -> Result<(), String> {
+    let mut book = OpeningBook::default();
+    let mut games = 0;
+    for path in games_paths {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("can't read {path}: {err}"))?;
+        let records = GameRecord::parse_all(&text).map_err(|err| format!("{path}: {err}"))?;
+        for (i, record) in records.iter().enumerate() {
+            book.add_game(record, plies).map_err(|err| format!("{path} game {}: {err}", i + 1))?;
+        }
+        games += records.len();
+    }
+    book.prune(min_weight);
+
+    let header = format!(
+        "# Opening book: position hash, move and weight on each line.\n\
+        # Built by `book build` from the first {plies} plies of {games} games in {}.\n",
+        games_paths.join(", "),
+    );
+    std::fs::write(out_path, header + &book.to_text()).map_err(|err| format!("can't write {out_path}: {err}"))?;
+    println!("{} positions from {games} games", book.len());
+    Ok(())
+}
+
+fn show(book: &OpeningBook, moves: &[&str]) -> Result<(), String> {
+    let mut position = Position::default();
+    for mv in parse_moves(&moves.join(" "))? {
+        if !position.is_legal(mv) {
+            return Err(format!("illegal move {mv}"));
+        }
+        position.make_move(mv);
+    }
+    let book_moves = book.moves(&position);
+    if book_moves.is_empty() {
+        println!("no book moves");
+    }
+    let total: u32 = book_moves.iter().map(|&(_, weight)| weight).sum();
+    for (mv, weight) in book_moves {
+        println!("{mv} {weight:>5} {:>5.1}%", weight as f64 * 100.0 / total as f64);
+    }
+    Ok(())
+}
+
+fn run(mut args: Vec<&str>) -> Result<(), String> {
+    let usage = "usage: book build [--plies <n>] [--min-weight <n>] --out <file> <games...> \
+        | book show [--book <file>] [moves...]".to_string();
+    match args.first() {
+        Some(&"build") => {
+            let plies = match take_option(&mut args, "--plies")? {
+                Some(plies) => plies.parse().map_err(|_| format!("bad ply count {plies}"))?,
+                None => 12,
+            };
+            let min_weight = match take_option(&mut args, "--min-weight")? {
+                Some(weight) => weight.parse().map_err(|_| format!("bad weight {weight}"))?,
+                None => 1,
+            };
+            let out_path = take_option(&mut args, "--out")?.ok_or(usage.clone())?;
+            if args.len() < 2 {
+                return Err(usage);
+            }
+            build(&args[1..], out_path, plies, min_weight)
+        }
+        Some(&"show") => {
+            let book = match take_option(&mut args, "--book")? {
+                Some(path) => OpeningBook::load(path)?,
+                None => OpeningBook::standard().clone(),
+            };
+            show(&book, &args[1..])
+        }
+        _ => Err(usage),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args.iter().map(String::as_str).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
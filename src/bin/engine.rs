@@ -0,0 +1,127 @@
+// Headless engine, for poking at the search without opening the game.
+//
+//   engine go <depth> [moves...]   search the position after the given moves
+//   engine bench [depth]           node counts with and without the search improvements
+//
+// either can be given --threads <n> to search on more than one thread, and --weights <file>
+// to evaluate with different weights (see assets/eval_weights.txt). go plays from the opening
+// book when it has the position, unless given --no-book, and --tablebases <dir> loads the
+// endgame tables made by the tablebase tool.
+
+use bevy::utils::Instant;
+use gmtk2024::args::{parse_option, take_flag, take_option};
+use gmtk2024::book::OpeningBook;
+use gmtk2024::eval::EvalWeights;
+use gmtk2024::position::{Move, Position};
+use gmtk2024::search::{SearchConfig, SearchLimits, Searcher};
+use gmtk2024::tablebase::tablebases;
+use std::process::ExitCode;
+
+// a few positions to benchmark on, as moves from the starting position. the later ones have
+// grown pieces in them
+const BENCH_POSITIONS: [&str; 4] = [
+    "",
+    "e2e4 e7e5 g1f3 b8c6",
+    "e2e4 d7d5 e4d5 d8d5",
+    "d2d4 e7e5 d4e5 f7f6 e5f6 g8f6",
+];
+
+fn position_after(moves: &[&str]) -> Result<Position, String> {
+    let mut position = Position::default();
+    for notation in moves {
+        let mv = Move::from_notation(notation).ok_or(format!("can't read move {notation}"))?;
+        if !position.is_legal(mv) {
+            return Err(format!("illegal move {notation}"));
+        }
+        position.make_move(mv);
+    }
+    Ok(position)
+}
+
+fn go(depth: u8, moves: &[&str], config: SearchConfig, use_book: bool) -> Result<(), String> {
+    let position = position_after(moves)?;
+    if use_book {
+        if let Some(mv) = OpeningBook::standard().pick(&position, config.seed) {
+            println!("bookmove {mv}");
+            return Ok(());
+        }
+    }
+    let start = Instant::now();
+    let result = Searcher::new(config).search(&position, SearchLimits::depth(depth));
+    let pv: Vec<String> = result.pv.iter().map(|mv| mv.to_string()).collect();
+    println!("depth {} score {} nodes {} time {:.2?}", result.depth, result.score, result.nodes, start.elapsed());
+    println!("pv {}", pv.join(" "));
+    match result.best_move {
+        Some(mv) => println!("bestmove {mv}"),
+        None => println!("bestmove none"),
+    }
+    Ok(())
+}
+
+fn bench(depth: u8, config: SearchConfig) -> Result<(), String> {
+    let threads = config.threads;
+    let single = SearchConfig { threads: 1, ..config };
+    let mut configs = vec![
+        ("alpha-beta".to_string(), SearchConfig { transposition_table: false, move_ordering: false, ..single }),
+        ("+ ordering".to_string(), SearchConfig { transposition_table: false, move_ordering: true, ..single }),
+        ("+ tt".to_string(), single),
+    ];
+    if threads > 1 {
+        // more nodes in total, but it should get to the same depth sooner
+        configs.push((format!("+ {threads} threads"), config));
+    }
+
+    let mut baseline_nodes = None;
+    for (name, config) in configs {
+        let mut total_nodes = 0;
+        let start = Instant::now();
+        for moves in BENCH_POSITIONS {
+            let moves: Vec<&str> = moves.split_whitespace().collect();
+            let position = position_after(&moves)?;
+            let result = Searcher::new(config).search(&position, SearchLimits::depth(depth));
+            total_nodes += result.nodes;
+        }
+        let speedup = baseline_nodes.map(|baseline| baseline as f64 / total_nodes as f64).unwrap_or(1.0);
+        println!("{name:<12} {total_nodes:>10} nodes {:>10.2?} {speedup:>6.2}x", start.elapsed());
+        baseline_nodes.get_or_insert(total_nodes);
+    }
+    Ok(())
+}
+
+fn run(mut args: Vec<&str>) -> Result<(), String> {
+    let mut config = SearchConfig::default();
+    if let Some(threads) = parse_option(&mut args, "--threads")? {
+        config.threads = threads;
+    }
+    if let Some(path) = take_option(&mut args, "--weights")? {
+        config.weights = EvalWeights::load(path)?;
+    }
+    if let Some(dir) = take_option(&mut args, "--tablebases")? {
+        tablebases().load_dir(dir)?;
+    }
+    let use_book = !take_flag(&mut args, "--no-book");
+
+    match args.as_slice() {
+        ["go", depth, moves @ ..] => match depth.parse() {
+            Ok(depth) => go(depth, moves, config, use_book),
+            Err(_) => Err(format!("bad depth {depth}")),
+        },
+        ["bench"] => bench(3, config),
+        ["bench", depth] => match depth.parse() {
+            Ok(depth) => bench(depth, config),
+            Err(_) => Err(format!("bad depth {depth}")),
+        },
+        _ => Err("usage: engine go <depth> [moves...] [--no-book] | engine bench [depth] [--threads <n>] [--weights <file>] [--tablebases <dir>]".to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args.iter().map(String::as_str).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
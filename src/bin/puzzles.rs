@@ -0,0 +1,104 @@
+// Mines recorded games for puzzles: the moments where one side blundered and the other had a
+// single clearly winning move to punish it, checked with the engine and written out in the
+// format of assets/puzzles.txt.
+//
+//   puzzles [options] --games <file> --out <file>
+//
+// the games are game records, like the ones `tournament --pgn` writes. options:
+//
+//   --depth <n>        search depth every move in a solution is checked at (default 3)
+//   --nodes <n>        node limit for each search (default 10000)
+//   --margin <cp>      how much better than the next best move it has to be (default 300)
+//   --max-moves <n>    longest solution, in moves for the player (default 3)
+//   --min-ply <n>      skip the first plies of every game, where the book is (default 8)
+//   --growth-only      only keep puzzles that start with a capture that grows the piece
+//   --limit <n>        stop after finding this many puzzles
+//
+// puzzles are named after the game and their first move, e.g. `Round 3, 14. f3e5`. Search
+// is slow, so a few dozen games take minutes
+
+use bevy::utils::Instant;
+use gmtk2024::args::{parse_option, take_flag, take_option};
+use gmtk2024::puzzle::{find_puzzles, is_growth_capture, FinderSettings, Puzzle};
+use gmtk2024::record::{first_ply, move_text, GameRecord};
+use std::process::ExitCode;
+
+// the round, or the players' names if the game has no round
+fn game_name(record: &GameRecord, number: usize) -> String {
+    match (record.tag("Round"), record.tag("White"), record.tag("Black")) {
+        (Some(round), _, _) => format!("Round {round}"),
+        (None, Some(white), Some(black)) => format!("{white} v {black}, game {number}"),
+        _ => format!("Game {number}"),
+    }
+}
+
+fn run(mut args: Vec<&str>) -> Result<(), String> {
+    let defaults = FinderSettings::default();
+    let settings = FinderSettings {
+        depth: parse_option(&mut args, "--depth")?.unwrap_or(defaults.depth),
+        nodes: parse_option(&mut args, "--nodes")?.or(defaults.nodes),
+        margin: parse_option(&mut args, "--margin")?.unwrap_or(defaults.margin),
+        max_moves: parse_option(&mut args, "--max-moves")?.unwrap_or(defaults.max_moves),
+    };
+    let min_ply: usize = parse_option(&mut args, "--min-ply")?.unwrap_or(8);
+    let limit: Option<usize> = parse_option(&mut args, "--limit")?;
+    let growth_only = take_flag(&mut args, "--growth-only");
+    let games_path = take_option(&mut args, "--games")?;
+    let out_path = take_option(&mut args, "--out")?;
+
+    let (Some(games_path), Some(out_path), []) = (games_path, out_path, &args[..]) else {
+        return Err("usage: puzzles [--depth <n>] [--nodes <n>] [--margin <cp>] [--max-moves <n>] [--min-ply <n>] \
+            [--growth-only] [--limit <n>] --games <file> --out <file>".to_string());
+    };
+    if settings.max_moves == 0 {
+        return Err("--max-moves has to be at least 1".to_string());
+    }
+    let text = std::fs::read_to_string(games_path).map_err(|err| format!("can't read {games_path}: {err}"))?;
+    let records = GameRecord::parse_all(&text)?;
+
+    let start = Instant::now();
+    let mut puzzles: Vec<Puzzle> = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        if limit.is_some_and(|limit| puzzles.len() >= limit) {
+            break;
+        }
+        let name = game_name(record, i + 1);
+        let found = find_puzzles(record, &settings, min_ply).map_err(|err| format!("{name}: {err}"))?;
+        // find_puzzles has already turned down a start that can't be read
+        let first_ply = record.start_position().map_or(0, |start| first_ply(&start));
+        for (ply, mut puzzle) in found {
+            let growth = is_growth_capture(&puzzle.position, puzzle.solution[0]);
+            if growth_only && !growth {
+                continue;
+            }
+            if limit.is_some_and(|limit| puzzles.len() >= limit) {
+                break;
+            }
+            let first = move_text(first_ply + ply, &puzzle.solution[..1]);
+            puzzle.name = format!("{name}, {first}{}", if growth { ", growth capture" } else { "" });
+            println!(
+                "{:<40} rating {:>4}  {}",
+                puzzle.name,
+                puzzle.rating,
+                move_text(first_ply + ply, &puzzle.solution),
+            );
+            puzzles.push(puzzle);
+        }
+    }
+    println!("{} puzzles from {} games in {:.1?}", puzzles.len(), records.len(), start.elapsed());
+
+    puzzles.sort_by_key(|puzzle| puzzle.rating);
+    let text: Vec<String> = puzzles.iter().map(|puzzle| puzzle.to_record().to_string()).collect();
+    std::fs::write(out_path, text.join("\n")).map_err(|err| format!("can't write {out_path}: {err}"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args.iter().map(String::as_str).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,362 @@
+// Hosts games for players on other computers, with nothing drawn, so it can run anywhere.
+// Every move is checked against the rules here, and the clocks kept here, so a client that's
+// been tampered with can't sneak in an illegal move or more time.
+//
+//   server [--port <n>] [--clock <3+2|5d3|none>] [--grace <seconds>]
+//
+// players connect with "Join game" in the game, or anything that speaks the protocol described
+// in src/net.rs. Players are paired in the order they connect, the first of each pair playing
+// white, and any number of games can go on at once. "Watch game" follows the game that started
+// last, or the one numbered after the address. Every move is passed on to the other player and
+// anyone watching, with both clocks, and draw offers to the other player. A player whose
+// connection goes has --grace seconds (default 60) to come back with `resume` before they
+// lose, and their clock keeps running meanwhile
+
+use bevy::utils::Instant;
+use gmtk2024::args::take_option;
+use gmtk2024::clock::{ChessClock, TimeControl};
+use gmtk2024::net::{Connection, Host, Message, DEFAULT_PORT, PROTOCOL_VERSION};
+use gmtk2024::piece::PieceColour;
+use gmtk2024::position::{Move, Outcome, Position};
+use gmtk2024::record::GameResult;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// how long to sleep between looking at every connection
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// a connection that hasn't said what it wants by then is dropped
+const GREETING_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn side_index(colour: PieceColour) -> usize {
+    match colour {
+        PieceColour::White => 0,
+        PieceColour::Black => 1,
+    }
+}
+
+// hard to guess, so only the player it was given to can take their seat back. Every
+// `RandomState` hashes with its own random keys
+fn new_token() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    format!("{:016x}", hasher.finish())
+}
+
+struct Seat {
+    token: String,
+    connection: Option<Connection>,
+    // when the connection went, if it has
+    lost_at: Option<Instant>,
+}
+
+struct Game {
+    id: u32,
+    position: Position,
+    moves: Vec<Move>,
+    clock: ChessClock,
+    // white's, then black's
+    seats: [Seat; 2],
+    spectators: Vec<Connection>,
+    last_tick: Instant,
+    // the side whose draw offer stands, until the next move
+    draw_offer: Option<PieceColour>,
+    // the result and why, once it's over
+    over: Option<(GameResult, String)>,
+}
+
+impl Game {
+    fn new(id: u32, white: Connection, black: Connection, time_control: Option<TimeControl>) -> Self {
+        let seat = |connection| Seat { token: new_token(), connection: Some(connection), lost_at: None };
+        let mut game = Game {
+            id,
+            position: Position::default(),
+            moves: Vec::new(),
+            clock: ChessClock::new(time_control),
+            seats: [seat(white), seat(black)],
+            spectators: Vec::new(),
+            last_tick: Instant::now(),
+            draw_offer: None,
+            over: None,
+        };
+        for colour in [PieceColour::White, PieceColour::Black] {
+            let token = game.seats[side_index(colour)].token.clone();
+            game.send_to(colour, &Message::Seat { game: id, token });
+            game.send_to(colour, &Message::Start { colour, time_control });
+        }
+        game.clock.start(PieceColour::White);
+        game
+    }
+
+    // a player whose connection fails is counted as gone, and can come back
+    fn send_to(&mut self, colour: PieceColour, message: &Message) {
+        let seat = &mut self.seats[side_index(colour)];
+        if let Some(connection) = seat.connection.as_mut() {
+            if connection.send(message).is_err() {
+                seat.connection = None;
+                seat.lost_at = Some(Instant::now());
+            }
+        }
+    }
+
+    // to both players and everyone watching, except `except`
+    fn broadcast(&mut self, message: &Message, except: Option<PieceColour>) {
+        for colour in [PieceColour::White, PieceColour::Black] {
+            if except != Some(colour) {
+                self.send_to(colour, message);
+            }
+        }
+        self.spectators.retain_mut(|connection| connection.send(message).is_ok());
+    }
+
+    fn clock_message(&self) -> Message {
+        Message::Clock { white: self.clock.remaining(PieceColour::White), black: self.clock.remaining(PieceColour::Black) }
+    }
+
+    fn finish(&mut self, result: GameResult, reason: &str) {
+        println!("game {}: {} {reason} after {} moves", self.id, result.as_str(), self.moves.len().div_ceil(2));
+        self.clock.stop();
+        self.broadcast(&Message::Over { result, reason: reason.to_string() }, None);
+        self.over = Some((result, reason.to_string()));
+    }
+
+    fn play(&mut self, colour: PieceColour, mv: Move) -> Result<(), String> {
+        if self.position.turn != colour {
+            return Err(format!("it isn't {}'s move", colour.name()));
+        }
+        if !self.position.is_legal(mv) {
+            return Err(format!("{mv} is illegal"));
+        }
+        self.position.make_move(mv);
+        self.moves.push(mv);
+        self.draw_offer = None;
+        self.clock.switch(colour);
+        self.broadcast(&Message::Move(mv), Some(colour));
+        if self.clock.control().is_some() {
+            self.broadcast(&self.clock_message(), None);
+        }
+        match self.position.outcome() {
+            Some(Outcome::Checkmate(loser)) => self.finish(GameResult::win_for(loser.opposite()), "checkmate"),
+            Some(Outcome::Stalemate) => self.finish(GameResult::Draw, "stalemate"),
+            None => {},
+        }
+        Ok(())
+    }
+
+    // the clocks, and everything the players and spectators have sent
+    fn update(&mut self, grace: Duration) {
+        let now = Instant::now();
+        if let Some(loser) = self.clock.tick(now - self.last_tick) {
+            self.finish(GameResult::win_for(loser.opposite()), "timeout");
+        }
+        self.last_tick = now;
+
+        for colour in [PieceColour::White, PieceColour::Black] {
+            let lost_at = self.seats[side_index(colour)].lost_at;
+            if lost_at.is_some_and(|lost_at| now - lost_at > grace) && self.over.is_none() {
+                self.finish(GameResult::win_for(colour.opposite()), "abandoned");
+            }
+            while self.over.is_none() {
+                let Some(connection) = self.seats[side_index(colour)].connection.as_mut() else {
+                    break;
+                };
+                match connection.receive() {
+                    Ok(None) => break,
+                    Ok(Some(Message::Move(mv))) => {
+                        if let Err(reason) = self.play(colour, mv) {
+                            println!("game {}: rejected {mv} from {}: {reason}", self.id, colour.name());
+                            self.send_to(colour, &Message::Reject(reason));
+                        }
+                    }
+                    Ok(Some(Message::Resign)) => self.finish(GameResult::win_for(colour.opposite()), "resignation"),
+                    // offering back a draw that's been offered is as good as accepting it
+                    Ok(Some(Message::OfferDraw | Message::AcceptDraw)) if self.draw_offer == Some(colour.opposite()) => {
+                        self.finish(GameResult::Draw, "agreement");
+                    }
+                    Ok(Some(Message::OfferDraw)) => {
+                        self.draw_offer = Some(colour);
+                        self.send_to(colour.opposite(), &Message::OfferDraw);
+                    }
+                    Ok(Some(Message::DeclineDraw)) if self.draw_offer == Some(colour.opposite()) => {
+                        self.draw_offer = None;
+                        self.send_to(colour.opposite(), &Message::DeclineDraw);
+                    }
+                    Ok(Some(Message::AcceptDraw | Message::DeclineDraw)) => {
+                        self.send_to(colour, &Message::Reject("there's no draw offer to answer".to_string()));
+                    }
+                    Ok(Some(Message::Bye)) => self.finish(GameResult::win_for(colour.opposite()), "left"),
+                    Ok(Some(message)) => self.send_to(colour, &Message::Reject(format!("didn't expect {message}"))),
+                    Err(err) => {
+                        println!("game {}: lost {}: {err}", self.id, colour.name());
+                        let seat = &mut self.seats[side_index(colour)];
+                        seat.connection = None;
+                        seat.lost_at = Some(now);
+                    }
+                }
+            }
+        }
+        // spectators only ever say bye
+        self.spectators.retain_mut(|connection| matches!(connection.receive(), Ok(None)));
+    }
+
+    // what someone coming in partway through needs to catch up
+    fn catch_up(&self, connection: &mut Connection) -> Result<(), String> {
+        connection.send(&Message::State(self.moves.clone()))?;
+        if self.clock.control().is_some() {
+            connection.send(&self.clock_message())?;
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, token: &str, mut connection: Connection) -> Result<(), String> {
+        let colour = [PieceColour::White, PieceColour::Black].into_iter()
+            .find(|&colour| self.seats[side_index(colour)].token == token)
+            .ok_or("that isn't a seat in this game")?;
+        connection.send(&Message::Start { colour, time_control: self.clock.control() })?;
+        self.catch_up(&mut connection)?;
+        let seat = &mut self.seats[side_index(colour)];
+        // whichever connection the player was on before is no use to them now
+        seat.connection = Some(connection);
+        seat.lost_at = None;
+        println!("game {}: {} is back", self.id, colour.name());
+        Ok(())
+    }
+}
+
+struct Server {
+    host: Host,
+    time_control: Option<TimeControl>,
+    grace: Duration,
+    games: Vec<Game>,
+    next_id: u32,
+    // connected, but not yet said what they want
+    greeting: Vec<(Connection, Instant)>,
+    // said hello, and waiting for someone to play
+    waiting: Option<Connection>,
+}
+
+impl Server {
+    fn game_mut(&mut self, id: u32) -> Result<&mut Game, String> {
+        self.games.iter_mut().find(|game| game.id == id).ok_or(format!("there's no game {id}"))
+    }
+
+    // does what a new connection asked for, or gives it back with the reason it can't
+    fn greet(&mut self, mut connection: Connection, message: Message) -> Result<(), (Connection, String)> {
+        let version = match &message {
+            Message::Hello { version } | Message::Resume { version, .. } | Message::Watch { version, .. } => *version,
+            message => return Err((connection, format!("didn't expect {message}"))),
+        };
+        if version != PROTOCOL_VERSION {
+            return Err((connection, format!("this server speaks version {PROTOCOL_VERSION}, not {version}")));
+        }
+        match message {
+            Message::Hello { .. } => match self.waiting.take() {
+                Some(white) => {
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    println!("game {id}: {} against {}", white.peer(), connection.peer());
+                    self.games.push(Game::new(id, white, connection, self.time_control));
+                }
+                None => self.waiting = Some(connection),
+            },
+            Message::Resume { game, token, .. } => {
+                let game = match self.game_mut(game) {
+                    Ok(game) => game,
+                    Err(err) => return Err((connection, err)),
+                };
+                // the connection is gone if this fails, there's nobody to tell
+                if let Err(err) = game.resume(&token, connection) {
+                    println!("game {}: couldn't resume: {err}", game.id);
+                }
+            }
+            Message::Watch { game, .. } => {
+                let game = match game {
+                    Some(id) => self.game_mut(id),
+                    None => self.games.last_mut().ok_or("there are no games to watch".to_string()),
+                };
+                let game = match game {
+                    Ok(game) => game,
+                    Err(err) => return Err((connection, err)),
+                };
+                let watching = Message::Watching { time_control: game.clock.control() };
+                if connection.send(&watching).and_then(|()| game.catch_up(&mut connection)).is_ok() {
+                    println!("game {}: {} is watching", game.id, connection.peer());
+                    game.spectators.push(connection);
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<(), String> {
+        while let Some(connection) = self.host.accept()? {
+            self.greeting.push((connection, Instant::now()));
+        }
+        for (mut connection, connected_at) in std::mem::take(&mut self.greeting) {
+            match connection.receive() {
+                Ok(Some(message)) => {
+                    if let Err((mut connection, reason)) = self.greet(connection, message) {
+                        println!("turned away {}: {reason}", connection.peer());
+                        let _ = connection.send(&Message::Reject(reason));
+                    }
+                }
+                Ok(None) if connected_at.elapsed() < GREETING_TIMEOUT => self.greeting.push((connection, connected_at)),
+                _ => {},
+            }
+        }
+        // someone waiting who gives up isn't paired with the next player
+        if let Some(connection) = self.waiting.as_mut() {
+            if !matches!(connection.receive(), Ok(None)) {
+                self.waiting = None;
+            }
+        }
+        for game in &mut self.games {
+            game.update(self.grace);
+        }
+        self.games.retain(|game| game.over.is_none());
+        Ok(())
+    }
+}
+
+fn run(mut args: Vec<&str>) -> Result<(), String> {
+    let port = match take_option(&mut args, "--port")? {
+        Some(port) => port.parse().map_err(|_| format!("bad port {port}"))?,
+        None => DEFAULT_PORT,
+    };
+    let time_control = match take_option(&mut args, "--clock")? {
+        None | Some("none") => None,
+        Some(text) => Some(TimeControl::parse(text)?),
+    };
+    let grace = match take_option(&mut args, "--grace")? {
+        Some(seconds) => Duration::from_secs(seconds.parse().map_err(|_| format!("bad grace period {seconds}"))?),
+        None => Duration::from_secs(60),
+    };
+    if !args.is_empty() {
+        return Err("usage: server [--port <n>] [--clock <3+2|5d3|none>] [--grace <seconds>]".to_string());
+    }
+
+    let host = Host::listen(port)?;
+    println!(
+        "listening on {}, {}",
+        host.address(),
+        time_control.map_or("no clock".to_string(), |control| format!("clock {}", control.name())),
+    );
+    let mut server = Server { host, time_control, grace, games: Vec::new(), next_id: 1, greeting: Vec::new(), waiting: None };
+    loop {
+        server.update()?;
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args.iter().map(String::as_str).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// Generates and looks into endgame tables for a king and one piece against a king.
+//
+//   tablebase generate <KQvK|KRvK|KBvK|KNvK> [--max-footprint <1|2>] --out <dir>
+//       writes a table for every combination of footprints up to --max-footprint squares
+//       (default 1, just the ungrown pieces) to <dir>, one .tb file each
+//   tablebase probe [--tablebases <dir>] <pieces...> <w|b>
+//       looks up a position, e.g. `Kc3 Rh2 ka1 w`, with white pieces in capitals. A grown
+//       piece lists the rest of its squares after its own: `Kc3+c4`. The table is generated
+//       if it isn't in <dir>
+//
+// the engine loads tables given to it with --tablebases, and the game generates the ones it
+// needs while it is running
+
+use bevy::utils::Instant;
+use gmtk2024::args::take_option;
+use gmtk2024::piece::PieceType;
+use gmtk2024::position::Position;
+use gmtk2024::tablebase::{tablebases, Material, Probe, Table};
+use std::process::ExitCode;
+
+fn describe(probe: Probe) -> String {
+    match probe {
+        Probe::Win(plies) => format!("win, mate in {}", plies.div_ceil(2)),
+        Probe::Draw => "draw".to_string(),
+        Probe::Loss(0) => "checkmated".to_string(),
+        Probe::Loss(plies) => format!("loss, mated in {}", plies.div_ceil(2)),
+    }
+}
+
+fn generate(name: &str, max_footprint: usize, out_dir: &str) -> Result<(), String> {
+    let piece_type = match name {
+        "KQvK" => PieceType::Queen,
+        "KRvK" => PieceType::Rook,
+        "KBvK" => PieceType::Bishop,
+        "KNvK" => PieceType::Knight,
+        _ => return Err(format!("can't make tables for {name}")),
+    };
+    std::fs::create_dir_all(out_dir).map_err(|err| format!("can't create {out_dir}: {err}"))?;
+    for material in Material::all(piece_type, max_footprint)? {
+        let start = Instant::now();
+        let table = Table::generate(material);
+        let name = table.material.name();
+        table.save(format!("{out_dir}/{name}.tb"))?;
+        let (wins, draws, losses) = table.counts();
+        println!(
+            "{name:<20} {wins:>7} wins {draws:>7} draws {losses:>7} losses, longest mate {} plies, {:.2?}",
+            table.longest_mate(),
+            start.elapsed(),
+        );
+    }
+    Ok(())
+}
+
+fn probe(specs: &[&str], tablebase_dir: Option<&str>) -> Result<(), String> {
+    let position = Position::from_setup(&specs.join(" "))?;
+    let (material, _) = Material::of(&position).ok_or("not a king and one piece against a king")?;
+
+    if let Some(dir) = tablebase_dir {
+        tablebases().load_dir(dir)?;
+    }
+    if !tablebases().contains(&material) {
+        println!("generating {}", material.name());
+        tablebases().add(Table::generate(material));
+    }
+    let result = tablebases().probe(&position).ok_or("that position can't happen")?;
+    println!("{}", describe(result));
+
+    let mut line = Vec::new();
+    let mut position = position;
+    while let Some((mv, _)) = tablebases().best_move(&position) {
+        line.push(mv.to_string());
+        position.make_move(mv);
+        if line.len() >= 100 {
+            break;
+        }
+    }
+    if !line.is_empty() {
+        println!("line {}", line.join(" "));
+    }
+    Ok(())
+}
+
+fn run(mut args: Vec<&str>) -> Result<(), String> {
+    let usage = "usage: tablebase generate <KQvK|KRvK|KBvK|KNvK> [--max-footprint <1|2>] --out <dir> \
+        | tablebase probe [--tablebases <dir>] <pieces...> <w|b>".to_string();
+    match args.first() {
+        Some(&"generate") => {
+            let max_footprint = match take_option(&mut args, "--max-footprint")? {
+                Some(size) => size.parse().map_err(|_| format!("bad footprint size {size}"))?,
+                None => 1,
+            };
+            let out_dir = take_option(&mut args, "--out")?.ok_or(usage.clone())?;
+            match args[1..] {
+                [name] => generate(name, max_footprint, out_dir),
+                _ => Err(usage),
+            }
+        }
+        Some(&"probe") => {
+            let tablebase_dir = take_option(&mut args, "--tablebases")?;
+            probe(&args[1..], tablebase_dir)
+        }
+        _ => Err(usage),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args.iter().map(String::as_str).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
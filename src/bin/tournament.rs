@@ -0,0 +1,330 @@
+// Plays computer players against each other without the game window, to compare versions of
+// the engine or changes to the rules.
+//
+//   tournament [options] <engine> <engine> [engines...]
+//
+// an engine is a comma separated list of settings, applied in order: a difficulty (beginner,
+// casual, club, expert), a personality (balanced, glutton, turtle), or depth=<n>, nodes=<n>,
+// movetime=<ms>, noise=<centipawns>, weights=<file>, and book (the built in opening book) or
+// book=<file>. e.g. `club,glutton,book` or `depth=4,weights=new_weights.txt`. A name can go
+// in front with a colon: `new:depth=4`. Engines only use a book if they're given one.
+//
+// every pair of engines plays --games games (default 10), alternating colours and going
+// through the start positions two games at a time. Given only one engine it plays itself,
+// which together with --stats is for checking the balance of the rules. options:
+//
+//   --games <n>         games per pair of engines
+//   --openings <file>   start positions, one line of moves each (# starts a comment)
+//   --nodes <n>         node limit per move for every engine
+//   --movetime <ms>     time limit per move for every engine
+//   --max-plies <n>     call the game a draw after this many plies (default 200)
+//   --pgn <file>        write every game to this file
+//   --stats <file>      write numbers from every game (lengths, captures, how big pieces got)
+//                       as CSV, or JSON if the file name ends in .json
+
+use bevy::utils::Duration;
+use gmtk2024::ai::{AiSettings, Difficulty, Personality};
+use gmtk2024::args::{parse_option, take_option};
+use gmtk2024::book::OpeningBook;
+use gmtk2024::eval::EvalWeights;
+use gmtk2024::piece::PieceColour;
+use gmtk2024::position::Move;
+use gmtk2024::record::{parse_moves, GameRecord, GameResult};
+use gmtk2024::search::{SearchConfig, SearchLimits, Searcher};
+use gmtk2024::stats::{write_stats, GameStats};
+use std::process::ExitCode;
+
+const DEFAULT_OPENINGS: [&str; 6] = [
+    "e2e4 e7e5",
+    "d2d4 d7d5",
+    "e2e4 c7c5",
+    "d2d4 g8f6",
+    "c2c4 e7e5",
+    "e2e4 d7d5 e4d5 d8d5",
+];
+
+struct Engine {
+    name: String,
+    config: SearchConfig,
+    limits: SearchLimits,
+    book: Option<OpeningBook>,
+}
+
+fn parse_engine(spec: &str) -> Result<Engine, String> {
+    let (name, settings) = match spec.split_once(':') {
+        Some((name, settings)) => (name.to_string(), settings),
+        None => (spec.to_string(), spec),
+    };
+    let mut ai = AiSettings::default();
+    let mut limits = ai.difficulty.limits();
+    let mut eval_noise = ai.difficulty.eval_noise();
+    let mut weights_path = None;
+    let mut book = None;
+
+    for setting in settings.split(',') {
+        let parse_number = |value: &str| value.parse::<u64>().map_err(|_| format!("bad number in {setting}"));
+        match setting.split_once('=') {
+            Some(("depth", value)) => {
+                limits.depth = value.parse().map_err(|_| format!("bad depth in {setting}, it should be 0 to {}", u8::MAX))?;
+            }
+            Some(("nodes", value)) => limits.nodes = Some(parse_number(value)?),
+            Some(("movetime", value)) => limits.time = Some(Duration::from_millis(parse_number(value)?)),
+            Some(("noise", value)) => {
+                eval_noise = i32::try_from(parse_number(value)?).map_err(|_| format!("noise too big in {setting}"))?;
+            }
+            Some(("weights", path)) => weights_path = Some(path.to_string()),
+            Some(("book", path)) => book = Some(OpeningBook::load(path)?),
+            Some(_) => return Err(format!("unknown engine setting {setting}")),
+            None => {
+                if let Some(difficulty) = Difficulty::from_name(setting) {
+                    ai.difficulty = difficulty;
+                    limits = difficulty.limits();
+                    eval_noise = difficulty.eval_noise();
+                } else if let Some(personality) = Personality::from_name(setting) {
+                    ai.personality = personality;
+                } else if setting == "book" {
+                    book = Some(OpeningBook::standard().clone());
+                } else {
+                    return Err(format!("unknown engine setting {setting}"));
+                }
+            }
+        }
+    }
+
+    let mut weights = match weights_path {
+        Some(path) => EvalWeights::load(path)?,
+        None => EvalWeights::default(),
+    };
+    ai.personality.apply(&mut weights);
+    let config = SearchConfig { weights, eval_noise, ..ai.search_config(0) };
+    Ok(Engine { name, config, limits, book })
+}
+
+fn read_openings(path: &str) -> Result<Vec<Vec<Move>>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("can't read {path}: {err}"))?;
+    let mut openings = Vec::new();
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let moves = parse_moves(line).map_err(|err| format!("{path} line {}: {err}", line_number + 1))?;
+        openings.push(moves);
+    }
+    Ok(openings)
+}
+
+fn play_game(white: &Engine, black: &Engine, opening: &[Move], seed: u64, max_plies: usize) -> Result<GameRecord, String> {
+    let mut record = GameRecord { moves: opening.to_vec(), ..Default::default() };
+    record.set_tag("White", &white.name);
+    record.set_tag("Black", &black.name);
+    let mut position = record.position()?;
+
+    // one searcher per side for the whole game, so each keeps its transposition table
+    let mut white_searcher = Searcher::new(SearchConfig { seed, ..white.config });
+    let mut black_searcher = Searcher::new(SearchConfig { seed, ..black.config });
+
+    let termination = loop {
+        if let Some(outcome) = position.outcome() {
+            record.result = GameResult::from_outcome(outcome);
+            break if record.result == GameResult::Draw { "stalemate" } else { "checkmate" };
+        }
+        if position.repetitions() >= 2 {
+            record.result = GameResult::Draw;
+            break "repetition";
+        }
+        if record.moves.len() >= max_plies {
+            record.result = GameResult::Draw;
+            break "adjudication";
+        }
+        let (engine, searcher) = match position.turn {
+            PieceColour::White => (white, &mut white_searcher),
+            PieceColour::Black => (black, &mut black_searcher),
+        };
+        let book_move = engine.book.as_ref().and_then(|book| book.pick(&position, seed));
+        let mv = match book_move {
+            Some(mv) => mv,
+            None => searcher.search(&position, engine.limits).best_move
+                .ok_or("search found no move in a position with legal moves")?,
+        };
+        position.make_move(mv);
+        record.moves.push(mv);
+    };
+    record.set_tag("Result", record.result.as_str());
+    record.set_tag("Termination", termination);
+    Ok(record)
+}
+
+// wins, draws and losses of one engine against another
+#[derive(Clone, Copy, Default)]
+struct Score {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl Score {
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn points(&self) -> f64 {
+        self.wins as f64 + self.draws as f64 / 2.0
+    }
+
+    fn add(&mut self, other: Score) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+    }
+}
+
+fn elo_difference(score_fraction: f64) -> f64 {
+    // + 0.0 so an even score prints as +0 rather than -0
+    -400.0 * (1.0 / score_fraction - 1.0).log10() + 0.0
+}
+
+// the Elo difference a score suggests and how far off it could be (95% confidence)
+fn elo_estimate(score: Score) -> String {
+    let games = score.games() as f64;
+    if games == 0.0 {
+        return "-".to_string();
+    }
+    let p = score.points() / games;
+    if p <= 0.0 {
+        return "-inf".to_string();
+    } else if p >= 1.0 {
+        return "+inf".to_string();
+    }
+    let variance = (score.wins as f64 * (1.0 - p).powi(2)
+        + score.draws as f64 * (0.5 - p).powi(2)
+        + score.losses as f64 * p.powi(2)) / games;
+    let margin = 1.96 * (variance / games).sqrt();
+    let low = elo_difference((p - margin).max(1e-6));
+    let high = elo_difference((p + margin).min(1.0 - 1e-6));
+    format!("{:+.0} ± {:.0}", elo_difference(p), (high - low) / 2.0)
+}
+
+fn print_crosstable(engines: &[Engine], scores: &[Vec<Score>]) {
+    let width = engines.iter().map(|engine| engine.name.len()).max().unwrap_or(0).max(8);
+    print!("{:width$}", "");
+    for engine in engines {
+        print!("  {:>width$}", engine.name);
+    }
+    println!("  {:>10}  elo vs the rest", "score");
+
+    for (i, engine) in engines.iter().enumerate() {
+        print!("{:width$}", engine.name);
+        let mut total = Score::default();
+        for (j, score) in scores[i].iter().enumerate() {
+            if i == j {
+                print!("  {:>width$}", "-");
+            } else {
+                print!("  {:>width$}", format!("{}/{}", score.points(), score.games()));
+                total.add(*score);
+            }
+        }
+        println!("  {:>10}  {}", format!("{}/{}", total.points(), total.games()), elo_estimate(total));
+    }
+
+    if engines.len() > 2 {
+        println!();
+        for i in 0..engines.len() {
+            for j in i + 1..engines.len() {
+                println!("{} vs {}: {}", engines[i].name, engines[j].name, elo_estimate(scores[i][j]));
+            }
+        }
+    }
+}
+
+fn run(mut args: Vec<&str>) -> Result<(), String> {
+    let games: usize = parse_option(&mut args, "--games")?.unwrap_or(10);
+    let max_plies: usize = parse_option(&mut args, "--max-plies")?.unwrap_or(200);
+    let nodes: Option<u64> = parse_option(&mut args, "--nodes")?;
+    let movetime: Option<u64> = parse_option(&mut args, "--movetime")?;
+    let openings = match take_option(&mut args, "--openings")? {
+        Some(path) => read_openings(path)?,
+        None => DEFAULT_OPENINGS.iter().map(|moves| parse_moves(moves)).collect::<Result<_, _>>()?,
+    };
+    let pgn_path = take_option(&mut args, "--pgn")?;
+    let stats_path = take_option(&mut args, "--stats")?;
+
+    if args.is_empty() || args.iter().any(|arg| arg.starts_with("--")) {
+        return Err("usage: tournament [--games <n>] [--openings <file>] [--nodes <n>] [--movetime <ms>] \
+            [--max-plies <n>] [--pgn <file>] [--stats <file>] <engine> [engines...]".to_string());
+    }
+    if openings.is_empty() {
+        return Err("no start positions".to_string());
+    }
+    if args.len() == 1 {
+        // self-play
+        args.push(args[0]);
+    }
+    let mut engines = args.iter().map(|spec| parse_engine(spec)).collect::<Result<Vec<_>, _>>()?;
+    for engine in &mut engines {
+        if nodes.is_some() {
+            engine.limits.nodes = nodes;
+        }
+        if let Some(ms) = movetime {
+            engine.limits.time = Some(Duration::from_millis(ms));
+        }
+    }
+    for i in 1..engines.len() {
+        if engines[..i].iter().any(|engine| engine.name == engines[i].name) {
+            engines[i].name = format!("{}#{}", engines[i].name, i + 1);
+        }
+    }
+
+    let mut scores = vec![vec![Score::default(); engines.len()]; engines.len()];
+    let mut records = Vec::new();
+    let total_games = games * engines.len() * (engines.len() - 1) / 2;
+    for i in 0..engines.len() {
+        for j in i + 1..engines.len() {
+            for game in 0..games {
+                // each start position gets played once with each colour
+                let opening = &openings[(game / 2) % openings.len()];
+                let (white, black) = if game.is_multiple_of(2) { (i, j) } else { (j, i) };
+                let mut record = play_game(&engines[white], &engines[black], opening, records.len() as u64, max_plies)?;
+                record.set_tag("Round", (records.len() + 1).to_string());
+
+                let (white_score, black_score) = match record.result {
+                    GameResult::WhiteWins => (Score { wins: 1, ..Score::default() }, Score { losses: 1, ..Score::default() }),
+                    GameResult::BlackWins => (Score { losses: 1, ..Score::default() }, Score { wins: 1, ..Score::default() }),
+                    _ => (Score { draws: 1, ..Score::default() }, Score { draws: 1, ..Score::default() }),
+                };
+                scores[white][black].add(white_score);
+                scores[black][white].add(black_score);
+                println!(
+                    "game {}/{total_games}: {} vs {} {} ({}, {} plies)",
+                    records.len() + 1, engines[white].name, engines[black].name, record.result.as_str(),
+                    record.tag("Termination").unwrap_or("?"), record.moves.len(),
+                );
+                records.push(record);
+            }
+        }
+    }
+
+    println!();
+    print_crosstable(&engines, &scores);
+
+    if let Some(path) = stats_path {
+        let stats = records.iter().map(GameStats::from_record).collect::<Result<Vec<_>, _>>()?;
+        write_stats(path, &stats)?;
+    }
+    if let Some(path) = pgn_path {
+        let text: Vec<String> = records.iter().map(|record| record.to_string()).collect();
+        std::fs::write(path, text.join("\n")).map_err(|err| format!("can't write {path}: {err}"))?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args.iter().map(String::as_str).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
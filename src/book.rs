@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::piece::PieceColour;
+use crate::position::{Move, Position};
+use crate::record::{GameRecord, GameResult};
+
+const STANDARD_BOOK: &str = include_str!("../assets/opening_book.txt");
+
+/// Moves to play in the opening instead of searching, looked up by the position hash (which
+/// includes each piece's footprint). A move's weight is how likely it is to be picked.
+#[derive(Clone, Debug, Default)]
+pub struct OpeningBook {
+    entries: HashMap<u64, Vec<(Move, u32)>>,
+}
+
+impl OpeningBook {
+    /// The book in assets/opening_book.txt, built into the game.
+    pub fn standard() -> &'static OpeningBook {
+        static BOOK: OnceLock<OpeningBook> = OnceLock::new();
+        BOOK.get_or_init(|| OpeningBook::parse(STANDARD_BOOK).expect("bad standard opening book"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("can't read {}: {err}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    /// Reads `<hash in hex> <move> <weight>` lines, with `#` comments.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut book = Self::default();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {message}", line_number + 1);
+            let [hash, mv, weight] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(error("expected hash, move and weight"));
+            };
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| error(&format!("bad hash {hash}")))?;
+            let mv = Move::from_notation(mv).ok_or_else(|| error(&format!("can't read move {mv}")))?;
+            let weight = weight.parse().map_err(|_| error(&format!("bad weight {weight}")))?;
+            book.add(hash, mv, weight);
+        }
+        Ok(book)
+    }
+
+    pub fn add(&mut self, hash: u64, mv: Move, weight: u32) {
+        let moves = self.entries.entry(hash).or_default();
+        match moves.iter_mut().find(|(book_move, _)| *book_move == mv) {
+            Some((_, total)) => *total += weight,
+            None => moves.push((mv, weight)),
+        }
+    }
+
+    /// Adds the first `plies` moves of a game. Moves by the side that went on to win count
+    /// twice as much as drawn ones, and the loser's moves don't count.
+    pub fn add_game(&mut self, record: &GameRecord, plies: usize) -> Result<(), String> {
+        let mut position = record.start_position()?;
+        for (ply, &mv) in record.moves.iter().take(plies).enumerate() {
+            if !position.is_legal(mv) {
+                return Err(format!("illegal move {mv} at ply {}", ply + 1));
+            }
+            let weight = match record.result {
+                GameResult::WhiteWins if position.turn == PieceColour::White => 2,
+                GameResult::BlackWins if position.turn == PieceColour::Black => 2,
+                GameResult::WhiteWins | GameResult::BlackWins => 0,
+                GameResult::Draw | GameResult::Unfinished => 1,
+            };
+            if weight > 0 {
+                self.add(position.hash, mv, weight);
+            }
+            position.make_move(mv);
+        }
+        Ok(())
+    }
+
+    /// Drops moves lighter than `min_weight`, e.g. ones only seen in a single game.
+    pub fn prune(&mut self, min_weight: u32) {
+        for moves in self.entries.values_mut() {
+            moves.retain(|&(_, weight)| weight >= min_weight);
+        }
+        self.entries.retain(|_, moves| !moves.is_empty());
+    }
+
+    /// The book moves for a position that are legal in it, biggest weight first.
+    pub fn moves(&self, position: &Position) -> Vec<(Move, u32)> {
+        let mut moves: Vec<(Move, u32)> = self.entries.get(&position.hash)
+            .map(|moves| moves.iter()
+                // a hash collision could give moves from some other position
+                .filter(|&&(mv, weight)| weight > 0 && position.is_legal(mv))
+                .copied()
+                .collect())
+            .unwrap_or_default();
+        moves.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.to_string().cmp(&b.0.to_string())));
+        moves
+    }
+
+    /// Picks one of the book moves at random, in proportion to their weights. The same seed
+    /// picks the same move.
+    pub fn pick(&self, position: &Position, seed: u64) -> Option<Move> {
+        let moves = self.moves(position);
+        let total: u64 = moves.iter().map(|&(_, weight)| weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut z = seed ^ position.hash;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        let mut roll = (z ^ (z >> 31)) % total;
+        for (mv, weight) in moves {
+            if roll < weight as u64 {
+                return Some(mv);
+            }
+            roll -= weight as u64;
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The book in the format `parse` reads, sorted so rebuilding it gives a small diff.
+    pub fn to_text(&self) -> String {
+        let mut hashes: Vec<&u64> = self.entries.keys().collect();
+        hashes.sort();
+        let mut text = String::new();
+        for hash in hashes {
+            let mut moves = self.entries[hash].clone();
+            moves.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.to_string().cmp(&b.0.to_string())));
+            for (mv, weight) in moves {
+                text += &format!("{hash:016x} {mv} {weight}\n");
+            }
+        }
+        text
+    }
+}
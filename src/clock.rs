@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use std::time::Duration;
+
+use crate::piece::PieceColour;
+use crate::square::{CurrentPosition, MoveEvent};
+
+/// How much time each side gets: `base` for the whole game and `increment` more after each of
+/// their moves. With a `delay` the time only starts going down that long into each move.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+    pub delay: Duration,
+}
+
+impl TimeControl {
+    /// `minutes` each, and `increment` seconds a move.
+    pub const fn new(minutes: u64, increment: u64) -> Self {
+        Self { base: Duration::from_secs(minutes * 60), increment: Duration::from_secs(increment), delay: Duration::ZERO }
+    }
+
+    /// `minutes` each, with a delay of `delay` seconds a move.
+    pub const fn with_delay(minutes: u64, delay: u64) -> Self {
+        Self { base: Duration::from_secs(minutes * 60), increment: Duration::ZERO, delay: Duration::from_secs(delay) }
+    }
+
+    /// e.g. "3+2" for three minutes and two seconds a move, "5 d3" with a delay instead.
+    pub fn name(&self) -> String {
+        let minutes = self.base.as_secs() / 60;
+        if self.delay > Duration::ZERO {
+            format!("{minutes} d{}", self.delay.as_secs())
+        } else {
+            format!("{minutes}+{}", self.increment.as_secs())
+        }
+    }
+
+    /// Reads a time control written like `name` writes them, with or without the space.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let error = || format!("can't read time control {text}, it should look like 3+2 or 5d3");
+        let text: String = text.split_whitespace().collect();
+        let (minutes, seconds, delay) = match text.split_once('+') {
+            Some((minutes, seconds)) => (minutes, seconds, false),
+            None => {
+                let (minutes, seconds) = text.split_once('d').ok_or_else(error)?;
+                (minutes, seconds, true)
+            }
+        };
+        let minutes = minutes.parse().map_err(|_| error())?;
+        let seconds = seconds.parse().map_err(|_| error())?;
+        Ok(if delay { Self::with_delay(minutes, seconds) } else { Self::new(minutes, seconds) })
+    }
+}
+
+/// The time controls a game can be started with.
+pub const TIME_CONTROLS: [TimeControl; 6] = [
+    TimeControl::new(1, 0),
+    TimeControl::new(3, 2),
+    TimeControl::new(5, 0),
+    TimeControl::new(10, 5),
+    TimeControl::new(15, 10),
+    TimeControl::with_delay(5, 3),
+];
+
+/// Sent when a side's time runs out, which loses them the game.
+#[derive(Event)]
+pub struct TimeoutEvent(pub PieceColour);
+
+/// The game's clock. Once a side's time has run out no more moves can be played.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ChessClock {
+    // None for a game without one
+    control: Option<TimeControl>,
+    // white's, then black's
+    remaining: [Duration; 2],
+    // whose time is going down. None before the game starts and once it's over
+    running: Option<PieceColour>,
+    // what's left of the delay on the move being thought about
+    delay_left: Duration,
+    flagged: Option<PieceColour>,
+    /// Stops the time going down without handing the move over, e.g. while a menu is open.
+    pub paused: bool,
+}
+
+fn side_index(colour: PieceColour) -> usize {
+    match colour {
+        PieceColour::White => 0,
+        PieceColour::Black => 1,
+    }
+}
+
+impl ChessClock {
+    /// A clock set up for a new game, stopped until `start` is called.
+    pub fn new(control: Option<TimeControl>) -> Self {
+        let base = control.map_or(Duration::ZERO, |control| control.base);
+        Self { control, remaining: [base; 2], ..default() }
+    }
+
+    pub fn control(&self) -> Option<TimeControl> {
+        self.control
+    }
+
+    pub fn remaining(&self, colour: PieceColour) -> Duration {
+        self.remaining[side_index(colour)]
+    }
+
+    /// Puts the time left for a side right, e.g. to match the clock on a server.
+    pub fn set_remaining(&mut self, colour: PieceColour, remaining: Duration) {
+        self.remaining[side_index(colour)] = remaining;
+    }
+
+    pub fn running(&self) -> Option<PieceColour> {
+        self.running
+    }
+
+    /// The side whose time ran out, if one's did.
+    pub fn flagged(&self) -> Option<PieceColour> {
+        self.flagged
+    }
+
+    /// Starts `colour`'s time going down. Does nothing if the game isn't timed.
+    pub fn start(&mut self, colour: PieceColour) {
+        let Some(control) = self.control else {
+            return;
+        };
+        if self.flagged.is_none() {
+            self.running = Some(colour);
+            self.delay_left = control.delay;
+        }
+    }
+
+    /// Stops the clock for good, e.g. once the game is over.
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+
+    /// Gives `colour`, who just moved, their increment and starts the other side's time.
+    pub fn switch(&mut self, colour: PieceColour) {
+        let Some(control) = self.control else {
+            return;
+        };
+        if self.running != Some(colour) {
+            return;
+        }
+        self.remaining[side_index(colour)] += control.increment;
+        self.start(colour.opposite());
+    }
+
+    /// Runs the clock on by `delta`, returning the side whose time just ran out if it did.
+    pub fn tick(&mut self, delta: Duration) -> Option<PieceColour> {
+        let colour = self.running.filter(|_| !self.paused)?;
+        // the delay is used up before any of the side's own time
+        let delay = self.delay_left.min(delta);
+        self.delay_left -= delay;
+        let remaining = &mut self.remaining[side_index(colour)];
+        *remaining = remaining.saturating_sub(delta - delay);
+        if remaining.is_zero() {
+            self.running = None;
+            self.flagged = Some(colour);
+            return Some(colour);
+        }
+        None
+    }
+}
+
+// hands the clock over after every move, and stops it when the game is over
+fn switch_clock(
+    mut move_events: EventReader<MoveEvent>,
+    position: Res<CurrentPosition>,
+    mut clock: ResMut<ChessClock>,
+) {
+    if move_events.read().count() == 0 {
+        return;
+    }
+    clock.switch(position.0.turn.opposite());
+    if position.0.outcome().is_some() {
+        clock.stop();
+    }
+}
+
+fn run_clock(
+    time: Res<Time>,
+    mut clock: ResMut<ChessClock>,
+    mut timeout_writer: EventWriter<TimeoutEvent>,
+) {
+    // most frames there's nothing running, and nothing to redraw
+    if clock.running.is_none() || clock.paused {
+        return;
+    }
+    if let Some(colour) = clock.tick(time.delta()) {
+        timeout_writer.send(TimeoutEvent(colour));
+    }
+}
+
+pub struct ClockPlugin;
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ChessClock::default())
+            .add_event::<TimeoutEvent>()
+            .add_systems(Update, (switch_clock, run_clock).chain());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::piece::PieceColour;
+use crate::position::{Move, Position};
+use crate::sha1::{sha1, to_hex};
+
+/// A game played by passing a file back and forth, a move at a time. Every move is written
+/// with a hash of it and all the moves before it:
+///
+/// ```text
+/// game 5f0c9a1b2c3d4e5f
+/// move e2e4 41b3...
+/// move e7e5 9e0a...
+/// ```
+///
+/// so changing an earlier move means working out every hash after it again. That alone would
+/// only catch mistakes, but each side also keeps the hash of the last move it saw (`Seen`),
+/// which a history that's been rewritten can't match.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CorrespondenceGame {
+    pub id: String,
+    pub moves: Vec<Move>,
+}
+
+/// What one side remembers of a correspondence game between turns.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Seen {
+    /// The side played here.
+    pub colour: PieceColour,
+    /// How many moves had been played, and the hash after the last of them.
+    pub ply: usize,
+    pub hash: String,
+}
+
+impl CorrespondenceGame {
+    /// A game with no moves yet, and an id nobody else's game will have.
+    pub fn new() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+        Self { id: format!("{:016x}", hasher.finish()), moves: Vec::new() }
+    }
+
+    /// The hash before any moves, then after each one.
+    pub fn hashes(&self) -> Vec<String> {
+        let mut hashes = vec![to_hex(&sha1(format!("correspondence {}", self.id).as_bytes()))];
+        for mv in &self.moves {
+            let last = hashes.last().unwrap();
+            hashes.push(to_hex(&sha1(format!("{last} {mv}").as_bytes())));
+        }
+        hashes
+    }
+
+    pub fn position(&self) -> Position {
+        let mut position = Position::default();
+        for &mv in &self.moves {
+            position.make_move(mv);
+        }
+        position
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("game {}\n", self.id);
+        for (mv, hash) in self.moves.iter().zip(&self.hashes()[1..]) {
+            text += &format!("move {mv} {hash}\n");
+        }
+        text
+    }
+
+    /// Reads a game written by `to_text`, checking every move's hash and that every move is
+    /// legal.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        let id = match lines.next().and_then(|line| line.split_once(' ')) {
+            Some(("game", id)) if !id.trim().is_empty() => id.trim().to_string(),
+            _ => return Err("it doesn't start with the game's id".to_string()),
+        };
+        let mut game = Self { id, moves: Vec::new() };
+        let mut position = Position::default();
+        let mut hash = game.hashes().pop().unwrap();
+        for line in lines {
+            let number = game.moves.len() + 1;
+            let (mv, written_hash) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["move", mv, hash] => (Move::from_notation(mv).ok_or(format!("can't read move {number}, {mv}"))?, hash),
+                _ => return Err(format!("can't read line {line:?}")),
+            };
+            if !position.is_legal(mv) {
+                return Err(format!("move {number}, {mv}, is illegal"));
+            }
+            hash = to_hex(&sha1(format!("{hash} {mv}").as_bytes()));
+            if hash != written_hash {
+                return Err(format!("move {number}, {mv}, doesn't match its hash, so the moves have been changed"));
+            }
+            position.make_move(mv);
+            game.moves.push(mv);
+        }
+        Ok(game)
+    }
+
+    /// What the side playing `colour` should remember of the game as it is now.
+    pub fn seen(&self, colour: PieceColour) -> Seen {
+        Seen { colour, ply: self.moves.len(), hash: self.hashes().pop().unwrap() }
+    }
+
+    /// Checks the game carries on from the one `seen` remembers: the same moves, and at most
+    /// one more, by the other side.
+    pub fn check(&self, seen: &Seen) -> Result<(), String> {
+        if self.moves.len() < seen.ply {
+            return Err(format!("it's from before move {}, the last one you saw", seen.ply));
+        }
+        if self.hashes()[seen.ply] != seen.hash {
+            return Err("the moves up to the last one you saw have been changed".to_string());
+        }
+        let opponent = seen.colour.opposite().name();
+        if self.moves.len() > seen.ply + 1 {
+            return Err(format!("{opponent} has played more than one move"));
+        }
+        // whose move it was decides whose move was added
+        let white_added = seen.ply.is_multiple_of(2);
+        if self.moves.len() > seen.ply && white_added == (seen.colour == PieceColour::White) {
+            return Err(format!("a move has been played for you, not {opponent}"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for CorrespondenceGame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(moves: &[&str]) -> CorrespondenceGame {
+        let moves = moves.iter().map(|mv| Move::from_notation(mv).unwrap()).collect();
+        CorrespondenceGame { id: "5f0c9a1b2c3d4e5f".to_string(), moves }
+    }
+
+    #[test]
+    fn the_next_move_is_accepted() {
+        let seen = game(&["e2e4"]).seen(PieceColour::White);
+        assert_eq!(game(&["e2e4", "e7e5"]).check(&seen), Ok(()));
+        // and so is the file coming back with nothing added
+        assert_eq!(game(&["e2e4"]).check(&seen), Ok(()));
+        let seen = game(&["e2e4", "e7e5"]).seen(PieceColour::Black);
+        assert_eq!(game(&["e2e4", "e7e5", "g1f3"]).check(&seen), Ok(()));
+    }
+
+    #[test]
+    fn an_older_file_is_rejected() {
+        let seen = game(&["e2e4", "e7e5"]).seen(PieceColour::Black);
+        let err = game(&["e2e4"]).check(&seen).unwrap_err();
+        assert_eq!(err, "it's from before move 2, the last one you saw");
+    }
+
+    #[test]
+    fn a_changed_earlier_move_is_rejected() {
+        let seen = game(&["e2e4", "e7e5"]).seen(PieceColour::White);
+        // every hash worked out again, so it reads, but not from what white saw
+        let rewritten = CorrespondenceGame::parse(&game(&["d2d4", "e7e5", "g1f3"]).to_text()).unwrap();
+        let err = rewritten.check(&seen).unwrap_err();
+        assert_eq!(err, "the moves up to the last one you saw have been changed");
+    }
+
+    #[test]
+    fn two_moves_at_once_are_rejected() {
+        let seen = game(&["e2e4"]).seen(PieceColour::White);
+        let err = game(&["e2e4", "e7e5", "g1f3"]).check(&seen).unwrap_err();
+        assert_eq!(err, "Black has played more than one move");
+    }
+
+    #[test]
+    fn a_move_for_the_wrong_side_is_rejected() {
+        // white's turn, so the move added should have come from here
+        let seen = game(&["e2e4", "e7e5"]).seen(PieceColour::White);
+        let err = game(&["e2e4", "e7e5", "g1f3"]).check(&seen).unwrap_err();
+        assert_eq!(err, "a move has been played for you, not Black");
+    }
+
+    #[test]
+    fn a_move_changed_without_its_hash_is_caught() {
+        let text = game(&["e2e4", "e7e5"]).to_text();
+        assert_eq!(CorrespondenceGame::parse(&text), Ok(game(&["e2e4", "e7e5"])));
+        let err = CorrespondenceGame::parse(&text.replace("e7e5", "e7e6")).unwrap_err();
+        assert_eq!(err, "move 2, e7e6, doesn't match its hash, so the moves have been changed");
+    }
+}
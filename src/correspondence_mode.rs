@@ -0,0 +1,300 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use gmtk2024::ai::{AiTask, PlayerKind, Players};
+use gmtk2024::correspondence::{CorrespondenceGame, Seen};
+use gmtk2024::history::GameHistory;
+use gmtk2024::piece::PieceColour;
+use gmtk2024::square::{CurrentPosition, MoveEvent, SetPositionEvent};
+
+use crate::new_game::{setup_new_game_screen, spawn_button, NewGameScreen};
+
+const GAME_FILE: &str = "correspondence.txt";
+const SEEN_FILE: &str = "correspondence_seen.txt";
+
+/// On the new game screen, under "Puzzles".
+#[derive(Component)]
+pub struct CorrespondenceButton;
+
+#[derive(Component)]
+struct CorrespondencePanel;
+
+#[derive(Component)]
+struct CorrespondenceStatusText;
+
+#[derive(Component)]
+struct LoadCorrespondenceButton;
+
+#[derive(Component)]
+struct LeaveCorrespondenceButton;
+
+/// Reads correspondence.txt and sets the board up from it.
+#[derive(Event)]
+struct OpenCorrespondenceEvent;
+
+/// The game being played by passing correspondence.txt back and forth. Only the side played
+/// here moves on the board; the other side's moves come in with the file.
+#[derive(Resource, Default)]
+pub struct Correspondence {
+    active: bool,
+    // None until the file has been read without any problems
+    game: Option<CorrespondenceGame>,
+    colour: Option<PieceColour>,
+    // who was playing before, put back when leaving
+    players: Players,
+    status: String,
+}
+
+/// Every correspondence game played here, by id, as it was when this side last saw it.
+/// Kept in correspondence_seen.txt.
+#[derive(Resource, Default)]
+struct SeenGames(HashMap<String, Seen>);
+
+impl SeenGames {
+    // `<id> <white|black> <ply> <hash>` lines
+    fn load() -> Self {
+        let mut seen = Self::default();
+        let Ok(text) = std::fs::read_to_string(SEEN_FILE) else {
+            return seen;
+        };
+        for line in text.lines() {
+            let colour = match line.split_whitespace().nth(1) {
+                Some("white") => PieceColour::White,
+                Some("black") => PieceColour::Black,
+                _ => {
+                    warn!("ignoring line in {SEEN_FILE}: {line}");
+                    continue;
+                }
+            };
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [id, _, ply, hash] if ply.parse::<usize>().is_ok() => {
+                    seen.0.insert(id.to_string(), Seen { colour, ply: ply.parse().unwrap(), hash: hash.to_string() });
+                }
+                _ => warn!("ignoring line in {SEEN_FILE}: {line}"),
+            }
+        }
+        seen
+    }
+
+    fn save(&self) {
+        let mut lines: Vec<String> = self.0.iter().map(|(id, seen)| {
+            let colour = if seen.colour == PieceColour::White { "white" } else { "black" };
+            format!("{id} {colour} {} {}", seen.ply, seen.hash)
+        }).collect();
+        lines.sort();
+        if let Err(err) = std::fs::write(SEEN_FILE, lines.join("\n") + "\n") {
+            warn!("can't write {SEEN_FILE}: {err}");
+        }
+    }
+
+    fn record(&mut self, game: &CorrespondenceGame, colour: PieceColour) {
+        self.0.insert(game.id.clone(), game.seen(colour));
+        self.save();
+    }
+}
+
+pub fn in_correspondence(correspondence: Res<Correspondence>) -> bool {
+    correspondence.active
+}
+
+fn setup_correspondence_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 24.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        CorrespondencePanel,
+    )).with_children(|column| {
+        column.spawn(TextBundle::from_section("Correspondence game", text_style.clone()));
+        column.spawn((
+            TextBundle::from_section("", TextStyle { font_size: 20.0, ..text_style }),
+            CorrespondenceStatusText,
+        ));
+        column.spawn(NodeBundle {
+            style: Style { column_gap: Val::Px(10.0), ..default() },
+            ..default()
+        }).with_children(|row| {
+            spawn_button(row, &asset_server, 120.0, "Load", LoadCorrespondenceButton);
+            spawn_button(row, &asset_server, 120.0, "Leave", LeaveCorrespondenceButton);
+        });
+    });
+}
+
+type OpenButtonFilter = (Changed<Interaction>, Or<(With<CorrespondenceButton>, With<LoadCorrespondenceButton>)>);
+
+fn correspondence_buttons(
+    interaction_query: Query<(&Interaction, Has<CorrespondenceButton>), OpenButtonFilter>,
+    mut new_game_visibility: Query<&mut Visibility, With<NewGameScreen>>,
+    mut open_writer: EventWriter<OpenCorrespondenceEvent>,
+) {
+    for (interaction, correspondence_button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if correspondence_button {
+            *new_game_visibility.single_mut() = Visibility::Hidden;
+        }
+        open_writer.send(OpenCorrespondenceEvent);
+    }
+}
+
+fn leave_correspondence_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<LeaveCorrespondenceButton>)>,
+    mut correspondence: ResMut<Correspondence>,
+    mut players: ResMut<Players>,
+    mut panel_visibility: Query<&mut Visibility, With<CorrespondencePanel>>,
+    mut new_game_visibility: Query<&mut Visibility, (With<NewGameScreen>, Without<CorrespondencePanel>)>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    *panel_visibility.single_mut() = Visibility::Hidden;
+    *new_game_visibility.single_mut() = Visibility::Visible;
+    *players = correspondence.players;
+    *correspondence = Correspondence::default();
+}
+
+// a game carries on from the file if it's there and follows on from what was seen of it
+// here; otherwise a new one starts, with White played here
+fn read_game(seen: &SeenGames) -> Result<(CorrespondenceGame, PieceColour), String> {
+    let text = match std::fs::read_to_string(GAME_FILE) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((CorrespondenceGame::new(), PieceColour::White));
+        }
+        Err(err) => return Err(err.to_string()),
+    };
+    let game = CorrespondenceGame::parse(&text)?;
+    match seen.0.get(&game.id) {
+        Some(seen) => {
+            game.check(seen)?;
+            Ok((game, seen.colour))
+        }
+        // a game sent from somewhere else, so this side has the next move
+        None => {
+            let colour = game.position().turn;
+            Ok((game, colour))
+        }
+    }
+}
+
+fn open_correspondence(
+    mut open_events: EventReader<OpenCorrespondenceEvent>,
+    mut correspondence: ResMut<Correspondence>,
+    mut seen: ResMut<SeenGames>,
+    (mut players, mut ai_task): (ResMut<Players>, ResMut<AiTask>),
+    mut history: ResMut<GameHistory>,
+    mut set_position_writer: EventWriter<SetPositionEvent>,
+    mut panel_visibility: Query<&mut Visibility, With<CorrespondencePanel>>,
+) {
+    if open_events.read().count() == 0 {
+        return;
+    }
+    if !correspondence.active {
+        correspondence.players = *players;
+    }
+    correspondence.active = true;
+    *panel_visibility.single_mut() = Visibility::Visible;
+    ai_task.cancel();
+    let (game, colour) = match read_game(&seen) {
+        Ok(game) => game,
+        Err(err) => {
+            // nothing gets played until a file that's all right is loaded
+            *players = Players { white: PlayerKind::Scripted, black: PlayerKind::Scripted };
+            correspondence.game = None;
+            correspondence.status = format!("Can't carry on from {GAME_FILE}:\n{err}");
+            return;
+        }
+    };
+    // the other side's moves come in with the file
+    *players.get_mut(colour) = PlayerKind::Human;
+    *players.get_mut(colour.opposite()) = PlayerKind::Scripted;
+    *history = GameHistory::default();
+    for &mv in &game.moves {
+        history.play(mv);
+    }
+    let position = game.position();
+    set_position_writer.send(SetPositionEvent(position.clone()));
+    seen.record(&game, colour);
+    let opponent = colour.opposite().name();
+    correspondence.status = if position.outcome().is_some() {
+        "The game is over".to_string()
+    } else if position.turn == colour {
+        format!("Playing {}, your move", colour.name())
+    } else {
+        format!("Playing {}, waiting for {opponent}'s move.\nSend them {GAME_FILE}, and Load it once it's back", colour.name())
+    };
+    correspondence.game = Some(game);
+    correspondence.colour = Some(colour);
+}
+
+// each move played here goes straight into the file, ready to send
+fn save_correspondence_moves(
+    mut move_events: EventReader<MoveEvent>,
+    history: Res<GameHistory>,
+    position: Res<CurrentPosition>,
+    mut correspondence: ResMut<Correspondence>,
+    mut seen: ResMut<SeenGames>,
+) {
+    if move_events.read().count() == 0 {
+        return;
+    }
+    let Some(colour) = correspondence.colour.filter(|&colour| position.0.turn.opposite() == colour) else {
+        return;
+    };
+    let Some(game) = correspondence.game.as_mut() else {
+        return;
+    };
+    let line = history.line();
+    if line.len() != game.moves.len() + 1 {
+        return;
+    }
+    game.moves.push(*line.last().unwrap());
+    let game = game.clone();
+    let opponent = colour.opposite().name();
+    correspondence.status = match std::fs::write(GAME_FILE, game.to_text()) {
+        Ok(()) => {
+            seen.record(&game, colour);
+            format!("Saved to {GAME_FILE}, send it to {opponent}.\nLoad it once it's back with their move")
+        }
+        Err(err) => format!("Can't write {GAME_FILE}: {err}"),
+    };
+}
+
+fn update_correspondence_panel(
+    correspondence: Res<Correspondence>,
+    mut status_text: Query<&mut Text, With<CorrespondenceStatusText>>,
+) {
+    status_text.single_mut().sections[0].value.clone_from(&correspondence.status);
+}
+
+pub struct CorrespondenceModePlugin;
+impl Plugin for CorrespondenceModePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Correspondence::default())
+            .insert_resource(SeenGames::load())
+            .add_event::<OpenCorrespondenceEvent>()
+            // under the new game screen
+            .add_systems(Startup, setup_correspondence_panel.before(setup_new_game_screen))
+            .add_systems(Update, (correspondence_buttons, open_correspondence).chain())
+            .add_systems(Update, (
+                leave_correspondence_button,
+                save_correspondence_moves,
+                update_correspondence_panel.run_if(resource_changed::<Correspondence>),
+            ).chain().run_if(in_correspondence));
+    }
+}
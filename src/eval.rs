@@ -0,0 +1,162 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::piece::{is_square_defended, Piece, PieceColour, PieceType};
+use crate::position::Position;
+
+const DEFAULT_WEIGHTS: &str = include_str!("../assets/eval_weights.txt");
+
+/// Every piece type with the name used for it in weight files and stats.
+pub const PIECE_TYPES: [(PieceType, &str); 6] = [
+    (PieceType::King, "king"),
+    (PieceType::Queen, "queen"),
+    (PieceType::Bishop, "bishop"),
+    (PieceType::Knight, "knight"),
+    (PieceType::Rook, "rook"),
+    (PieceType::Pawn, "pawn"),
+];
+
+pub fn type_index(piece_type: PieceType) -> usize {
+    PIECE_TYPES.iter().position(|&(t, _)| t == piece_type).unwrap()
+}
+
+/// Weights for each term of the evaluation. The defaults live in assets/eval_weights.txt.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EvalWeights {
+    // these are indexed in the order of PIECE_TYPES
+    pub material: [i32; 6],
+    pub footprint: [i32; 6],
+    pub mobility: i32,
+    pub king_attacked: i32,
+    pub king_footprint: i32,
+    // indexed by rows left to go minus one
+    pub promotion: [i32; 6],
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        // parsed once, since every search and every computer move asks for them
+        static DEFAULTS: OnceLock<EvalWeights> = OnceLock::new();
+        *DEFAULTS.get_or_init(|| {
+            let zero = Self {
+                material: [0; 6],
+                footprint: [0; 6],
+                mobility: 0,
+                king_attacked: 0,
+                king_footprint: 0,
+                promotion: [0; 6],
+            };
+            zero.with_overrides(DEFAULT_WEIGHTS).expect("bad default eval weights")
+        })
+    }
+}
+
+impl EvalWeights {
+    /// Reads a weights file on top of the default weights.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("can't read {}: {err}", path.display()))?;
+        Self::default().with_overrides(&text)
+    }
+
+    /// Returns these weights with the `name = value` lines in `text` changed.
+    pub fn with_overrides(mut self, text: &str) -> Result<Self, String> {
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {message}", line_number + 1);
+            let (name, value) = line.split_once('=').ok_or_else(|| error("expected name = value"))?;
+            let (name, value) = (name.trim(), value.trim());
+            let value: i32 = value.parse().map_err(|_| error(&format!("{value} is not a whole number")))?;
+            *self.weight_mut(name).ok_or_else(|| error(&format!("unknown weight {name}")))? = value;
+        }
+        Ok(self)
+    }
+
+    fn weight_mut(&mut self, name: &str) -> Option<&mut i32> {
+        match name.split_once('.') {
+            Some(("material", piece)) => {
+                let i = PIECE_TYPES.iter().position(|&(_, n)| n == piece)?;
+                Some(&mut self.material[i])
+            },
+            Some(("footprint", piece)) => {
+                let i = PIECE_TYPES.iter().position(|&(_, n)| n == piece)?;
+                Some(&mut self.footprint[i])
+            },
+            Some(("promotion", rows)) => {
+                let rows: usize = rows.parse().ok()?;
+                self.promotion.get_mut(rows.checked_sub(1)?)
+            },
+            Some(_) => None,
+            None => match name {
+                "mobility" => Some(&mut self.mobility),
+                "king_attacked" => Some(&mut self.king_attacked),
+                "king_footprint" => Some(&mut self.king_footprint),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn material(&self, piece: &Piece) -> i32 {
+        let i = type_index(piece.piece_type);
+        self.material[i] + self.footprint[i] * (piece.squares_occupied.len() as i32 - 1)
+    }
+}
+
+fn piece_score(piece: &Piece, pieces: &Vec<Piece>, weights: &EvalWeights) -> i32 {
+    let mut score = weights.material(piece);
+
+    if weights.mobility != 0 {
+        let pieces_without_self = pieces.iter()
+            .filter_map(|p| if p != piece { Some(p.clone()) } else { None })
+            .collect();
+        score += weights.mobility * piece.valid_moves(&pieces_without_self).len() as i32;
+    }
+
+    match piece.piece_type {
+        PieceType::King => {
+            // a bigger king has more squares that can be attacked
+            score += weights.king_footprint * (piece.squares_occupied.len() as i32 - 1);
+            if weights.king_attacked != 0 {
+                for &(dx, dy) in &piece.squares_occupied {
+                    let x = piece.x.checked_add_signed(dx).unwrap();
+                    let y = piece.y.checked_add_signed(dy).unwrap();
+                    if is_square_defended((x, y), piece.colour.opposite(), pieces) {
+                        score += weights.king_attacked;
+                    }
+                }
+            }
+        },
+        PieceType::Pawn => {
+            for rows_to_go in 1..=6 {
+                let row = match piece.colour {
+                    PieceColour::White => 7 - rows_to_go,
+                    PieceColour::Black => rows_to_go,
+                };
+                if piece.occupies_row(row) {
+                    score += weights.promotion[rows_to_go as usize - 1];
+                    break;
+                }
+            }
+        },
+        _ => {},
+    }
+    score
+}
+
+/// Scores a position from the point of view of the side to move.
+pub fn evaluate(position: &Position, weights: &EvalWeights) -> i32 {
+    let mut score = 0;
+    for piece in &position.pieces {
+        let piece_score = piece_score(piece, &position.pieces, weights);
+        if piece.colour == position.turn {
+            score += piece_score;
+        } else {
+            score -= piece_score;
+        }
+    }
+    score
+}
@@ -0,0 +1,174 @@
+use bevy::prelude::*;
+use gmtk2024::clock::{ChessClock, TimeControl, TIME_CONTROLS};
+use gmtk2024::piece::PieceColour;
+use std::time::Duration;
+
+use crate::analysis::analysing;
+use crate::correspondence_mode::in_correspondence;
+use crate::new_game::{NewGameEvent, NewGameScreen};
+use crate::puzzle_mode::in_puzzle;
+use crate::replay::in_replay;
+use crate::resign::QuestionDialog;
+use crate::{LoadingState, Ui};
+
+/// Cycles through the time controls on the new game screen, starting with none.
+#[derive(Component)]
+pub struct TimeControlButton;
+
+#[derive(Component)]
+struct ClockPanel;
+
+#[derive(Component)]
+struct ClockText(PieceColour);
+
+/// The time control picked for the next game.
+#[derive(Resource, Default)]
+pub struct ClockSettings(pub Option<TimeControl>);
+
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    // tenths of a second once it gets close
+    if seconds < 10 {
+        format!("0:{seconds:02}.{}", time.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn setup_clock_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 32.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.0),
+                top: Val::Percent(40.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::FlexEnd,
+                row_gap: Val::Px(10.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        ClockPanel,
+    )).with_children(|column| {
+        // black's across the board, white's nearer
+        for colour in [PieceColour::Black, PieceColour::White] {
+            column.spawn((TextBundle::from_section("", text_style.clone()), ClockText(colour)));
+        }
+    });
+}
+
+fn time_control_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<TimeControlButton>)>,
+    mut settings: ResMut<ClockSettings>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    let next = match settings.0 {
+        None => 0,
+        Some(control) => TIME_CONTROLS.iter().position(|&c| c == control).unwrap() + 1,
+    };
+    settings.0 = TIME_CONTROLS.get(next).copied();
+}
+
+fn update_time_control_label(
+    settings: Res<ClockSettings>,
+    button_query: Query<&Children, With<TimeControlButton>>,
+    mut texts: Query<&mut Text>,
+) {
+    for children in &button_query {
+        if let Ok(mut text) = texts.get_mut(children[0]) {
+            text.sections[0].value = match settings.0 {
+                Some(control) => format!("Clock: {}", control.name()),
+                None => "No clock".to_string(),
+            };
+        }
+    }
+}
+
+fn start_clock(
+    mut new_game_events: EventReader<NewGameEvent>,
+    settings: Res<ClockSettings>,
+    mut clock: ResMut<ChessClock>,
+) {
+    if new_game_events.read().count() == 0 {
+        return;
+    }
+    *clock = ChessClock::new(settings.0);
+    clock.start(PieceColour::White);
+}
+
+// nobody loses time while a menu or question is covering the board, or while it's loading
+fn pause_clock(
+    mut clock: ResMut<ChessClock>,
+    new_game_visibility: Query<&Visibility, With<NewGameScreen>>,
+    ui_visibility: Query<&Visibility, With<Ui>>,
+    question_visibility: Query<&Visibility, With<QuestionDialog>>,
+    loading_state: Res<LoadingState>,
+) {
+    let paused = new_game_visibility.iter().chain(&ui_visibility).chain(&question_visibility)
+        .any(|visibility| *visibility == Visibility::Visible)
+        || matches!(*loading_state, LoadingState::LevelLoading);
+    if clock.paused != paused {
+        clock.paused = paused;
+    }
+}
+
+// analysis and puzzles aren't against the clock, and a flag from the game before mustn't stop
+// moves being tried out
+fn clear_clock(mut clock: ResMut<ChessClock>) {
+    if clock.control().is_some() {
+        *clock = ChessClock::default();
+    }
+}
+
+fn update_clock_panel(
+    clock: Res<ChessClock>,
+    mut panel_visibility: Query<&mut Visibility, With<ClockPanel>>,
+    mut clock_texts: Query<(&ClockText, &mut Text)>,
+) {
+    *panel_visibility.single_mut() = match clock.control() {
+        Some(_) => Visibility::Inherited,
+        None => Visibility::Hidden,
+    };
+    for (ClockText(colour), mut text) in &mut clock_texts {
+        let name = match colour {
+            PieceColour::White => "White",
+            PieceColour::Black => "Black",
+        };
+        let section = &mut text.sections[0];
+        section.value = format!("{name} {}", format_time(clock.remaining(*colour)));
+        section.style.color = if clock.flagged() == Some(*colour) {
+            Color::srgb(0.9, 0.2, 0.2)
+        } else if clock.running() == Some(*colour) {
+            Color::srgb(0.9, 0.9, 0.9)
+        } else {
+            Color::srgb(0.5, 0.5, 0.5)
+        };
+    }
+}
+
+pub struct GameClockPlugin;
+impl Plugin for GameClockPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ClockSettings::default())
+            .add_systems(Startup, setup_clock_panel)
+            .add_systems(Update, (
+                time_control_button,
+                update_time_control_label.run_if(resource_changed::<ClockSettings>),
+                start_clock,
+                pause_clock,
+                clear_clock.run_if(analysing.or_else(in_puzzle).or_else(in_correspondence).or_else(in_replay)),
+                update_clock_panel.run_if(resource_changed::<ChessClock>),
+            ).chain());
+    }
+}
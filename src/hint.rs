@@ -0,0 +1,203 @@
+use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use gmtk2024::ai::Players;
+use gmtk2024::book::OpeningBook;
+use gmtk2024::piece::Piece;
+use gmtk2024::position::{Move, Position};
+use gmtk2024::search::{SearchConfig, SearchLimits, Searcher};
+use gmtk2024::square::{CurrentPosition, Hint};
+use gmtk2024::tablebase::{tablebases, Material, Probe, Table};
+
+use crate::new_game::{setup_new_game_screen, spawn_button};
+
+#[derive(Component)]
+struct HintButton;
+
+/// The search for a hint, if one is running.
+#[derive(Resource, Default)]
+struct HintTask {
+    task: Option<Task<Option<Move>>>,
+    // the position it's for, in case a move gets played before it's done
+    position_hash: u64,
+}
+
+// says who wins a king and piece against king ending, once its table is ready
+#[derive(Component)]
+struct EndgameText;
+
+/// The endgame table being generated for the current position, if any. Tables are only made
+/// when a game gets to them, and kept for the rest of the session.
+#[derive(Resource, Default)]
+struct TablebaseTask {
+    task: Option<Task<()>>,
+    material: Option<Material>,
+}
+
+// the book's favourite move, or else a quick search, which is perfect if an endgame table
+// has the position
+fn suggest_move(position: &Position) -> Option<Move> {
+    if let Some(&(mv, _)) = OpeningBook::standard().moves(position).first() {
+        return Some(mv);
+    }
+    let limits = SearchLimits { depth: 3, nodes: Some(50_000), time: None };
+    Searcher::new(SearchConfig::default()).search(position, limits).best_move
+}
+
+fn board_squares(piece: &Piece) -> Vec<(u8, u8)> {
+    piece.squares_occupied.iter()
+        .map(|&(dx, dy)| (piece.x.checked_add_signed(dx).unwrap(), piece.y.checked_add_signed(dy).unwrap()))
+        .collect()
+}
+
+fn setup_hint_button(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..default()
+        },
+        ..default()
+    }).with_children(|parent| {
+        spawn_button(parent, &asset_server, 100.0, "Hint", HintButton);
+    });
+}
+
+fn hint_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<HintButton>)>,
+    players: Res<Players>,
+    position: Res<CurrentPosition>,
+    mut hint_task: ResMut<HintTask>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    // no peeking at the computer's move, and one search at a time
+    if !players.is_human(position.0.turn) || hint_task.task.is_some() {
+        return;
+    }
+    let position = position.0.clone();
+    hint_task.position_hash = position.hash;
+    hint_task.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        suggest_move(&position)
+    }));
+}
+
+fn finish_hint(
+    mut hint_task: ResMut<HintTask>,
+    position: Res<CurrentPosition>,
+    mut hint: ResMut<Hint>,
+) {
+    let Some(task) = hint_task.task.as_mut() else {
+        return;
+    };
+    let Some(suggestion) = block_on(future::poll_once(task)) else {
+        return;
+    };
+    hint_task.task = None;
+    let Some(mv) = suggestion.filter(|_| hint_task.position_hash == position.0.hash) else {
+        return;
+    };
+    let Some(piece) = position.0.piece_at(mv.from) else {
+        return;
+    };
+    let mut after = position.0.clone();
+    after.make_move(mv);
+    let moved = after.pieces.iter().find(|piece| (piece.x, piece.y) == mv.to).expect("moved piece is gone");
+    *hint = Hint { from: board_squares(piece), to: board_squares(moved) };
+}
+
+// a hint is only for the position it was asked for in
+fn clear_hint(mut hint: ResMut<Hint>, mut hint_task: ResMut<HintTask>) {
+    if !hint.from.is_empty() {
+        *hint = Hint::default();
+    }
+    hint_task.task = None;
+}
+
+fn setup_endgame_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/IBMPlexSerif-Italic.ttf"),
+                font_size: 24.0,
+                color: Color::srgb(0.9, 0.9, 0.9),
+            },
+        ).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        EndgameText,
+    ));
+}
+
+fn generate_tablebase(position: Res<CurrentPosition>, mut tablebase_task: ResMut<TablebaseTask>) {
+    // there's only the one thread on the web, and the game would freeze while it ran
+    if cfg!(target_arch = "wasm32") || tablebase_task.task.is_some() {
+        return;
+    }
+    let Some((material, _)) = Material::of(&position.0) else {
+        return;
+    };
+    if tablebases().contains(&material) {
+        return;
+    }
+    tablebase_task.material = Some(material.clone());
+    tablebase_task.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        tablebases().add(Table::generate(material));
+    }));
+}
+
+fn finish_tablebase(mut tablebase_task: ResMut<TablebaseTask>) {
+    let Some(task) = tablebase_task.task.as_mut() else {
+        return;
+    };
+    if block_on(future::poll_once(task)).is_some() {
+        tablebase_task.task = None;
+        tablebase_task.material = None;
+    }
+}
+
+fn update_endgame_text(
+    position: Res<CurrentPosition>,
+    tablebase_task: Res<TablebaseTask>,
+    mut endgame_text: Query<&mut Text, With<EndgameText>>,
+) {
+    let position = &position.0;
+    let to_move = position.turn.name();
+    let other = position.turn.opposite().name();
+    let value = match tablebases().probe(position) {
+        Some(Probe::Win(plies)) => format!("{to_move} mates in {}", plies.div_ceil(2)),
+        Some(Probe::Draw) => "Drawn ending".to_string(),
+        Some(Probe::Loss(0)) | None => {
+            let generating = tablebase_task.material.is_some()
+                && Material::of(position).map(|(material, _)| material) == tablebase_task.material;
+            if generating { "Working out the ending...".to_string() } else { String::new() }
+        }
+        Some(Probe::Loss(plies)) => format!("{other} mates in {}", plies.div_ceil(2)),
+    };
+    let mut text = endgame_text.single_mut();
+    if text.sections[0].value != value {
+        text.sections[0].value = value;
+    }
+}
+
+pub struct HintPlugin;
+impl Plugin for HintPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(TablebaseTask::default())
+            .insert_resource(HintTask::default())
+            // under the new game screen
+            .add_systems(Startup, (setup_hint_button, setup_endgame_text).before(setup_new_game_screen))
+            .add_systems(Update, (
+                clear_hint.run_if(resource_changed::<CurrentPosition>),
+                hint_button,
+                finish_hint,
+            ).chain())
+            .add_systems(Update, (generate_tablebase, finish_tablebase, update_endgame_text).chain());
+    }
+}
@@ -0,0 +1,476 @@
+use bevy::prelude::*;
+
+use crate::position::{Move, Position};
+use crate::record::{GameRecord, GameResult, Line, MoveSymbol};
+
+#[derive(Clone, Debug)]
+struct Node {
+    // None for the starting position
+    mv: Option<Move>,
+    parent: usize,
+    // the first child carries on the line this node is in, the rest are variations
+    children: Vec<usize>,
+    symbol: Option<MoveSymbol>,
+    // about the position after the move
+    comment: String,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, parent: usize) -> Self {
+        Self { mv, parent, children: Vec::new(), symbol: None, comment: String::new() }
+    }
+}
+
+/// Every move that has been played on the board, as a tree: the game itself is the main line,
+/// and a move played from any earlier position starts a variation instead of overwriting
+/// what came after. The board can be at any node.
+#[derive(Resource, Clone)]
+pub struct GameHistory {
+    nodes: Vec<Node>,
+    // the node the board is showing
+    cursor: usize,
+    // None for the usual starting position
+    start: Option<Position>,
+    // how the game ended, when it wasn't on the board
+    result: Option<GameResult>,
+}
+
+impl Default for GameHistory {
+    fn default() -> Self {
+        Self { nodes: vec![Node::new(None, 0)], cursor: 0, start: None, result: None }
+    }
+}
+
+impl GameHistory {
+    /// An empty history for a game set up some other way, like a puzzle.
+    pub fn from_position(start: Position) -> Self {
+        Self { start: Some(start), ..default() }
+    }
+
+    pub fn start_position(&self) -> Position {
+        self.start.clone().unwrap_or_default()
+    }
+
+    // nodes from the start to `node`, not including the root
+    fn path(&self, mut node: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        while node != 0 {
+            path.push(node);
+            node = self.nodes[node].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The moves from the start to the position being shown.
+    pub fn line(&self) -> Vec<Move> {
+        self.path(self.cursor).into_iter().filter_map(|node| self.nodes[node].mv).collect()
+    }
+
+    /// The game as played, or whatever line has been promoted in its place.
+    pub fn main_line(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut node = 0;
+        while let Some(&child) = self.nodes[node].children.first() {
+            moves.extend(self.nodes[child].mv);
+            node = child;
+        }
+        moves
+    }
+
+    pub fn ply(&self) -> usize {
+        self.path(self.cursor).len()
+    }
+
+    // the last node of the main line
+    fn main_line_end(&self) -> usize {
+        let mut node = 0;
+        while let Some(&child) = self.nodes[node].children.first() {
+            node = child;
+        }
+        node
+    }
+
+    /// How the game ended: by a resignation or an agreed draw, or else however the main line
+    /// ends on the board.
+    pub fn result(&self) -> GameResult {
+        self.result.unwrap_or_else(|| {
+            let mut position = self.start_position();
+            for mv in self.main_line() {
+                position.make_move(mv);
+            }
+            position.outcome().map_or(GameResult::Unfinished, GameResult::from_outcome)
+        })
+    }
+
+    /// Ends the game where the main line stops with a result that isn't on the board.
+    pub fn set_result(&mut self, result: GameResult) {
+        self.result = Some(result);
+    }
+
+    /// Whether the position being shown is where a resigned or drawn game ended, so no more
+    /// moves can be played from it.
+    pub fn ended_here(&self) -> bool {
+        self.result.is_some() && self.cursor == self.main_line_end()
+    }
+
+    /// Whether the position being shown is off the main line.
+    pub fn in_variation(&self) -> bool {
+        self.path(self.cursor).into_iter().any(|node| self.nodes[self.nodes[node].parent].children[0] != node)
+    }
+
+    /// The position the board should show.
+    pub fn position(&self) -> Position {
+        let mut position = self.start_position();
+        for mv in self.line() {
+            position.make_move(mv);
+        }
+        position
+    }
+
+    /// Records a move played on the board: following the tree if the move is already in it,
+    /// otherwise adding it after the position being shown. At the end of a line that carries
+    /// the line on, anywhere else it starts a new variation.
+    pub fn play(&mut self, mv: Move) {
+        let existing = self.nodes[self.cursor].children.iter()
+            .find(|&&child| self.nodes[child].mv == Some(mv))
+            .copied();
+        self.cursor = match existing {
+            Some(child) => child,
+            None => {
+                self.nodes.push(Node::new(Some(mv), self.cursor));
+                let child = self.nodes.len() - 1;
+                self.nodes[self.cursor].children.push(child);
+                child
+            }
+        };
+    }
+
+    /// The symbol on the move that led to the position being shown.
+    pub fn symbol(&self) -> Option<MoveSymbol> {
+        self.nodes[self.cursor].symbol
+    }
+
+    /// Puts a symbol on the move that led to the position being shown, if there was one.
+    pub fn set_symbol(&mut self, symbol: Option<MoveSymbol>) {
+        if self.cursor != 0 {
+            self.nodes[self.cursor].symbol = symbol;
+        }
+    }
+
+    /// The comment on the position being shown.
+    pub fn comment(&self) -> &str {
+        &self.nodes[self.cursor].comment
+    }
+
+    pub fn set_comment(&mut self, comment: String) {
+        self.nodes[self.cursor].comment = comment;
+    }
+
+    // the moves to each of `nodes` in turn, with their symbols and the comments after them
+    fn annotated_line(&self, nodes: &[usize]) -> Line {
+        let mut line = Line::default();
+        for (i, &node) in nodes.iter().enumerate() {
+            let node = &self.nodes[node];
+            line.moves.extend(node.mv);
+            line.symbols.extend(node.symbol.map(|symbol| (i, symbol)));
+            if !node.comment.is_empty() {
+                line.comments.push((i + 1, node.comment.clone()));
+            }
+        }
+        line
+    }
+
+    /// The moves that got to the position being shown, with the comment on the starting
+    /// position before them.
+    pub fn line_played(&self) -> Line {
+        let mut line = self.annotated_line(&self.path(self.cursor));
+        if !self.nodes[0].comment.is_empty() {
+            line.comments.insert(0, (0, self.nodes[0].comment.clone()));
+        }
+        line
+    }
+
+    /// The moves after the position being shown, along the line it's in.
+    pub fn line_ahead(&self) -> Line {
+        let mut nodes = Vec::new();
+        let mut node = self.cursor;
+        while let Some(&child) = self.nodes[node].children.first() {
+            nodes.push(child);
+            node = child;
+        }
+        self.annotated_line(&nodes)
+    }
+
+    /// Steps back a move, returning whether there was one.
+    pub fn back(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor = self.nodes[self.cursor].parent;
+        true
+    }
+
+    /// Steps forward along the line being shown, returning whether there was a move.
+    pub fn forward(&mut self) -> bool {
+        match self.nodes[self.cursor].children.first() {
+            Some(&child) => {
+                self.cursor = child;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn to_start(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Goes to the end of the line being shown.
+    pub fn to_end(&mut self) {
+        while self.forward() {}
+    }
+
+    /// Switches to the next (or with `step` -1, previous) variation for the last move played,
+    /// wrapping around. Returns whether there was another one.
+    pub fn switch_variation(&mut self, step: isize) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        let siblings = &self.nodes[self.nodes[self.cursor].parent].children;
+        if siblings.len() < 2 {
+            return false;
+        }
+        let i = siblings.iter().position(|&node| node == self.cursor).unwrap();
+        self.cursor = siblings[(i as isize + step).rem_euclid(siblings.len() as isize) as usize];
+        true
+    }
+
+    /// Makes the line through the position being shown the main line, so the moves it
+    /// branched off from become variations.
+    pub fn promote_variation(&mut self) {
+        // the game's result went with the old main line
+        if self.in_variation() {
+            self.result = None;
+        }
+        for node in self.path(self.cursor) {
+            let parent = self.nodes[node].parent;
+            let siblings = &mut self.nodes[parent].children;
+            let i = siblings.iter().position(|&sibling| sibling == node).unwrap();
+            let promoted = siblings.remove(i);
+            siblings.insert(0, promoted);
+        }
+    }
+
+    // the line starting with `node`, with the variations on each of its moves
+    fn line_from(&self, mut node: usize) -> Line {
+        let mut line = Line::default();
+        loop {
+            line.moves.extend(self.nodes[node].mv);
+            line.symbols.extend(self.nodes[node].symbol.map(|symbol| (line.moves.len() - 1, symbol)));
+            if !self.nodes[node].comment.is_empty() {
+                line.comments.push((line.moves.len(), self.nodes[node].comment.clone()));
+            }
+            let siblings = &self.nodes[self.nodes[node].parent].children;
+            // a variation belongs to the move it could have been played instead of
+            if siblings[0] == node {
+                for &sibling in &siblings[1..] {
+                    line.variations.push((line.moves.len() - 1, self.line_from(sibling)));
+                }
+            }
+            match self.nodes[node].children.first() {
+                Some(&child) => node = child,
+                None => return line,
+            }
+        }
+    }
+
+    /// The whole tree as a game record: the main line as its moves, with every variation.
+    pub fn to_record(&self) -> GameRecord {
+        let line = match self.nodes[0].children.first() {
+            Some(&first) => self.line_from(first),
+            None => Line::default(),
+        };
+        let mut comments = line.comments;
+        if !self.nodes[0].comment.is_empty() {
+            comments.insert(0, (0, self.nodes[0].comment.clone()));
+        }
+        let mut record = GameRecord {
+            moves: line.moves,
+            variations: line.variations,
+            symbols: line.symbols,
+            comments,
+            result: self.result(),
+            ..default()
+        };
+        if let Some(start) = &self.start {
+            record.set_tag("Setup", start.setup());
+        }
+        record
+    }
+
+    // adds a line after `node`, which is `position`, and its variations after the positions
+    // they start from. A comment before the line's first move goes on the move, since the
+    // position before it has its own
+    fn add_line(&mut self, mut node: usize, position: &Position, line: &Line) -> Result<(), String> {
+        let mut position = position.clone();
+        for (i, &mv) in line.moves.iter().enumerate() {
+            if !position.is_legal(mv) {
+                return Err(format!("illegal move {mv} at ply {}", position.history.len() + 1));
+            }
+            let parent = node;
+            self.cursor = parent;
+            self.play(mv);
+            node = self.cursor;
+            self.nodes[node].symbol = line.symbols.iter().find(|(at, _)| *at == i).map(|&(_, symbol)| symbol);
+            let comments: Vec<&str> = line.comments.iter()
+                .filter(|(at, _)| *at == i + 1 || (i == 0 && *at == 0))
+                .map(|(_, comment)| comment.as_str())
+                .collect();
+            self.nodes[node].comment = comments.join(" ");
+            // after the move they replace, so it stays first
+            for (_, variation) in line.variations.iter().filter(|(at, _)| *at == i) {
+                self.add_line(parent, &position, variation)?;
+            }
+            position.make_move(mv);
+        }
+        Ok(())
+    }
+
+    /// The tree for a game record, showing its starting position. Every move in it, in the
+    /// variations too, has to be legal.
+    pub fn from_record(record: &GameRecord) -> Result<Self, String> {
+        let mut history = Self::default();
+        if record.tag("Setup").is_some() {
+            history.start = Some(record.start_position()?);
+        }
+        // the comments before the first move are about the starting position
+        let start_comments: Vec<&str> = record.comments.iter()
+            .filter(|(at, _)| *at == 0)
+            .map(|(_, comment)| comment.as_str())
+            .collect();
+        history.nodes[0].comment = start_comments.join(" ");
+        let line = Line {
+            moves: record.moves.clone(),
+            variations: record.variations.clone(),
+            symbols: record.symbols.clone(),
+            comments: record.comments.iter().filter(|(at, _)| *at > 0).cloned().collect(),
+        };
+        history.add_line(0, &history.start_position(), &line)?;
+        if history.result() != record.result && record.result != GameResult::Unfinished {
+            history.result = Some(record.result);
+        }
+        history.cursor = 0;
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::parse_moves;
+
+    // a history with `moves` played from the start
+    fn played(moves: &str) -> GameHistory {
+        let mut history = GameHistory::default();
+        for mv in parse_moves(moves).unwrap() {
+            history.play(mv);
+        }
+        history
+    }
+
+    #[test]
+    fn playing_at_the_end_carries_the_line_on() {
+        let history = played("e2e4 e7e5 g1f3");
+        assert_eq!(history.main_line(), parse_moves("e2e4 e7e5 g1f3").unwrap());
+        assert_eq!(history.line(), history.main_line());
+        assert!(!history.in_variation());
+    }
+
+    #[test]
+    fn playing_in_the_middle_starts_a_variation() {
+        let mut history = played("e2e4 e7e5 g1f3");
+        history.back();
+        history.back();
+        history.play(Move::from_notation("c7c5").unwrap());
+        // the game is still the main line, and the board is off it
+        assert_eq!(history.main_line(), parse_moves("e2e4 e7e5 g1f3").unwrap());
+        assert_eq!(history.line(), parse_moves("e2e4 c7c5").unwrap());
+        assert!(history.in_variation());
+        assert_eq!(history.nodes[history.nodes[history.cursor].parent].children.len(), 2);
+    }
+
+    #[test]
+    fn playing_a_move_already_there_follows_it() {
+        let mut history = played("e2e4 e7e5 g1f3");
+        let nodes = history.nodes.len();
+        history.to_start();
+        history.play(Move::from_notation("e2e4").unwrap());
+        history.play(Move::from_notation("e7e5").unwrap());
+        assert_eq!(history.nodes.len(), nodes);
+        assert_eq!(history.ply(), 2);
+        assert!(!history.in_variation());
+        assert!(history.forward());
+        assert_eq!(history.line(), history.main_line());
+    }
+
+    #[test]
+    fn to_start_and_forward_walk_the_line() {
+        let mut history = played("e2e4 e7e5");
+        history.to_start();
+        assert_eq!(history.ply(), 0);
+        assert!(history.line().is_empty());
+        assert!(history.forward());
+        assert_eq!(history.line(), parse_moves("e2e4").unwrap());
+        assert!(history.forward());
+        assert!(!history.forward());
+        assert_eq!(history.ply(), 2);
+    }
+
+    #[test]
+    fn forward_stays_in_the_variation() {
+        let mut history = played("e2e4 e7e5 g1f3");
+        history.back();
+        history.back();
+        history.play(Move::from_notation("c7c5").unwrap());
+        history.play(Move::from_notation("g1f3").unwrap());
+        history.back();
+        assert!(history.forward());
+        assert_eq!(history.line(), parse_moves("e2e4 c7c5 g1f3").unwrap());
+    }
+
+    #[test]
+    fn promoting_a_side_line_makes_it_the_game() {
+        let mut history = played("e2e4 e7e5 g1f3");
+        history.back();
+        history.back();
+        history.play(Move::from_notation("c7c5").unwrap());
+        history.play(Move::from_notation("g1f3").unwrap());
+        history.promote_variation();
+        assert_eq!(history.main_line(), parse_moves("e2e4 c7c5 g1f3").unwrap());
+        assert!(!history.in_variation());
+        // and the old main line is now the variation
+        history.back();
+        assert!(history.switch_variation(1));
+        assert_eq!(history.line(), parse_moves("e2e4 e7e5").unwrap());
+        assert!(history.in_variation());
+        history.to_end();
+        assert_eq!(history.line(), parse_moves("e2e4 e7e5 g1f3").unwrap());
+    }
+
+    #[test]
+    fn a_result_ends_the_main_line_until_a_variation_is_promoted() {
+        let mut history = played("e2e4 e7e5");
+        history.set_result(GameResult::WhiteWins);
+        assert!(history.ended_here());
+        history.back();
+        assert!(!history.ended_here());
+        history.play(Move::from_notation("c7c5").unwrap());
+        assert!(!history.ended_here());
+        assert_eq!(history.result(), GameResult::WhiteWins);
+        // the result was for the line that's no longer the game
+        history.promote_variation();
+        assert_eq!(history.result(), GameResult::Unfinished);
+        assert!(!history.ended_here());
+    }
+}
@@ -1,16 +1,35 @@
-mod piece;
-mod square;
-mod pipelines_ready;
-
 use bevy::{asset::AssetMetaCheck, color::palettes::css::PURPLE};
+use bevy::input::mouse::MouseWheel;
 use bevy::prelude::*;
 use bevy::time::Stopwatch;
 use bevy_mod_picking::prelude::*;
+use gmtk2024::{ai, clock, piece, square, pipelines_ready, LoadingData};
+use ai::{AiTask, Players};
+use analysis::{AnalyseButton, Analysis};
+use new_game::{NewGameEvent, NewGameScreen};
 use piece::{create_pieces, Piece, PieceColour};
 use core::f32::consts::PI;
 use std::time::Duration;
+use clock::TimeoutEvent;
 use square::{CheckmateEvent, PlayerTurn};
 use pipelines_ready::PipelinesReady;
+use gmtk2024::zobrist::PositionHash;
+use gmtk2024::square::CurrentPosition;
+use gmtk2024::history::GameHistory;
+use gmtk2024::record::GameResult;
+use resign::AgreedResultEvent;
+
+mod analysis;
+mod autosave;
+mod correspondence_mode;
+mod game_clock;
+mod hint;
+mod network;
+mod new_game;
+mod puzzle_mode;
+mod replay;
+mod resign;
+mod summary;
 
 
 const BUTTON_COLOR: Color = Color::srgb(0.4, 0.2, 0.24);
@@ -36,6 +55,9 @@ struct Ui;
 #[derive(Component)]
 struct GameStatusText;
 
+#[derive(Component)]
+struct PlayAgainButton;
+
 #[derive(Component)]
 struct LoadingScreen;
 
@@ -48,29 +70,6 @@ enum LoadingState {
     LevelLoading,
 }
 
-// A resource that holds the current loading data.
-#[derive(Resource, Debug, Default)]
-pub struct LoadingData {
-    // This will hold the currently unloaded/loading assets.
-    pub loading_assets: Vec<UntypedHandle>,
-    // Number of frames that everything needs to be ready for.
-    // This is to prevent going into the fully loaded state in instances
-    // where there might be a some frames between certain loading/pipelines action.
-    pub confirmation_frames_target: usize,
-    // Current number of confirmation frames.
-    pub confirmation_frames_count: usize,
-}
-
-impl LoadingData {
-    fn new(confirmation_frames_target: usize) -> Self {
-        Self {
-            loading_assets: Vec::new(),
-            confirmation_frames_target,
-            confirmation_frames_count: 0,
-        }
-    }
-}
-
 
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     let board_centre = Vec3::new(3.5, 0.0, 3.5);
@@ -153,7 +152,19 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             ),
             GameStatusText,
         ));
-        parent.spawn(
+        parent.spawn((
+            NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            summary::ReportPanel,
+        ));
+        parent.spawn((
             ButtonBundle {
                 style: Style {
                     width: Val::Px(200.0),
@@ -168,7 +179,8 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 border_radius: BorderRadius::all(Val::Px(5.0)),
                 ..default()
             },
-        ).with_children(|parent2| {
+            PlayAgainButton,
+        )).with_children(|parent2| {
             parent2.spawn(TextBundle::from_section(
                 "Play again",
                 TextStyle {
@@ -178,6 +190,32 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 },
             ));
         });
+        parent.spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(200.0),
+                    height: Val::Px(65.0),
+                    // horizontally center child text
+                    justify_content: JustifyContent::Center,
+                    // vertically center child text
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                border_radius: BorderRadius::all(Val::Px(5.0)),
+                ..default()
+            },
+            AnalyseButton,
+        )).with_children(|parent2| {
+            parent2.spawn(TextBundle::from_section(
+                "Analyse",
+                TextStyle {
+                    font: asset_server.load("fonts/IBMPlexSerif-Italic.ttf"),
+                    font_size: 40.0,
+                    color: Color::srgb(0.9, 0.9, 0.9),
+                },
+            ));
+        });
     });
 
     commands.spawn(WinDelay { time: {
@@ -215,9 +253,40 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     });
 }
 
+// someone watching a game moves the camera themselves: left and right go round the board,
+// up and down (or the mouse wheel) closer and further away
+fn orbit_camera(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut camera_transform_query: Query<&mut Transform, With<SwivelDelay>>,
+) {
+    let board_centre = Vec3::new(3.5, 0.0, 3.5);
+    let axis = |less, more| match (keys.pressed(less), keys.pressed(more)) {
+        (true, false) => -1.0,
+        (false, true) => 1.0,
+        _ => 0.0,
+    };
+    let turn = axis(KeyCode::ArrowLeft, KeyCode::ArrowRight) * time.delta_seconds();
+    let zoom = axis(KeyCode::ArrowUp, KeyCode::ArrowDown) * time.delta_seconds()
+        - wheel_events.read().map(|event| event.y.signum() * 0.1).sum::<f32>();
+    if turn == 0.0 && zoom == 0.0 {
+        return;
+    }
+    let mut camera_transform = camera_transform_query.single_mut();
+    let offset = Quat::from_rotation_y(turn) * (camera_transform.translation - board_centre);
+    let distance = (offset.length() * (1.0 + zoom)).clamp(6.0, 30.0);
+    *camera_transform = Transform::from_translation(board_centre + offset.normalize() * distance)
+        .looking_at(board_centre, Vec3::Y);
+}
+
 fn swivel_camera(
     time: Res<Time>,
     turn: ResMut<PlayerTurn>,
+    players: Res<Players>,
+    analysis: Res<Analysis>,
+    net_game: Res<network::NetGame>,
+    replay: Res<replay::Replay>,
     mut camera_transform_query: Query<(&mut Transform, &mut SwivelDelay), With<Camera>>,
 ) {
     let radius = 11.0;
@@ -227,7 +296,24 @@ fn swivel_camera(
 
     let (mut camera_transform, mut swivel_delay) = camera_transform_query.get_single_mut().unwrap();
 
-    let dist = match turn.0 {
+    // back round the board at the usual distance, after someone watching has moved it
+    let offset = (camera_transform.translation - board_centre).with_y(0.0);
+    if (offset.length() - radius).abs() > 0.01 || (camera_transform.translation.y - 10.0).abs() > 0.01 {
+        let translation = board_centre + offset.normalize_or_zero() * radius + Vec3::Y * 10.0;
+        *camera_transform = Transform::from_translation(translation).looking_at(board_centre, Vec3::Y);
+    }
+
+    // against the computer or someone on another computer the camera stays on the side
+    // played here, and it stays put while stepping through a game
+    let side = match (players.is_human(PieceColour::White), players.is_human(PieceColour::Black)) {
+        _ if analysis.active => PieceColour::White,
+        _ if net_game.camera_side().is_some() => net_game.camera_side().unwrap(),
+        _ if replay.camera_side().is_some() => replay.camera_side().unwrap(),
+        (false, true) => PieceColour::Black,
+        (true, false) => PieceColour::White,
+        _ => turn.0,
+    };
+    let dist = match side {
         PieceColour::White => camera_transform.translation - white_camera_pos,
         PieceColour::Black => camera_transform.translation - black_camera_pos,
     };
@@ -252,17 +338,28 @@ fn swivel_camera(
 fn update_game_status(
     mut game_status_text: Query<&mut Text, With<GameStatusText>>,
     mut checkmate_event: EventReader<CheckmateEvent>,
+    mut timeout_event: EventReader<TimeoutEvent>,
+    mut agreed_result_event: EventReader<AgreedResultEvent>,
     mut win_delay: Query<&mut WinDelay>,
 ) {
-    for ev in checkmate_event.read() {
+    // the side that lost, and how, or None for a draw
+    let losses = checkmate_event.read().map(|ev| (Some(ev.0), ""))
+        .chain(timeout_event.read().map(|ev| (Some(ev.0), " on time")))
+        .chain(agreed_result_event.read().map(|ev| match ev.0 {
+            GameResult::WhiteWins => (Some(PieceColour::Black), " by resignation"),
+            GameResult::BlackWins => (Some(PieceColour::White), " by resignation"),
+            _ => (None, ""),
+        }));
+    for (loser, how) in losses {
         let mut win_delay = win_delay.single_mut();
         win_delay.time.reset();
         win_delay.time.unpause();
 
         let mut text = game_status_text.get_single_mut().unwrap();
-        match ev.0 {
-            PieceColour::White => { text.sections[0].value = "Black wins!".to_string(); },
-            PieceColour::Black => { text.sections[0].value = "White wins!".to_string(); },
+        match loser {
+            Some(PieceColour::White) => { text.sections[0].value = format!("Black wins{how}!"); },
+            Some(PieceColour::Black) => { text.sections[0].value = format!("White wins{how}!"); },
+            None => { text.sections[0].value = "Draw agreed!".to_string(); },
         }
     }
 }
@@ -290,37 +387,16 @@ fn show_ui_on_win(
     }
 }
 
-fn button_system(
-    mut commands: Commands,
+fn button_colours(
     mut interaction_query: Query<
         (&Interaction, &mut BackgroundColor),
         (Changed<Interaction>, With<Button>),
     >,
-    pieces_query: Query<Entity, With<Piece>>,
-    asset_server: Res<AssetServer>,
-    mut turn: ResMut<PlayerTurn>,
-    mut ui_visibility: Query<&mut Visibility, With<Ui>>,
-    mut swivel_delay_query: Query<&mut SwivelDelay, With<Camera>>,
-    loading_data: ResMut<LoadingData>,
 ) {
-    if let Ok((interaction, mut color)) = interaction_query.get_single_mut() {
+    for (interaction, mut color) in interaction_query.iter_mut() {
         match *interaction {
             Interaction::Pressed => {
                 *color = BUTTON_COLOR_PRESS.into();
-                // Despawn all pieces
-                for piece_entity in pieces_query.iter() {
-                    commands.entity(piece_entity).despawn_recursive();
-                }
-                // Create new set of pieces
-                create_pieces(commands, loading_data, asset_server);
-                // Set turn to white
-                turn.0 = PieceColour::White;
-                // Hide UI
-                let mut visibility = ui_visibility.get_single_mut().unwrap();
-                *visibility = Visibility::Hidden;
-                // Set swivel delay to 1.0 so it immediately swivels
-                let mut swivel_delay = swivel_delay_query.get_single_mut().unwrap();
-                swivel_delay.time.set_elapsed(Duration::from_secs_f32(1.0));
             }
             Interaction::Hovered => {
                 *color = BUTTON_COLOR_HOVER.into();
@@ -332,6 +408,52 @@ fn button_system(
     }
 }
 
+fn play_again_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<PlayAgainButton>)>,
+    mut ui_visibility: Query<&mut Visibility, With<Ui>>,
+    mut new_game_visibility: Query<&mut Visibility, (With<NewGameScreen>, Without<Ui>)>,
+) {
+    if let Ok(Interaction::Pressed) = interaction_query.get_single() {
+        // Hide UI and go back to picking players
+        let mut visibility = ui_visibility.get_single_mut().unwrap();
+        *visibility = Visibility::Hidden;
+        let mut visibility = new_game_visibility.get_single_mut().unwrap();
+        *visibility = Visibility::Visible;
+    }
+}
+
+fn start_new_game(
+    mut commands: Commands,
+    mut new_game_event: EventReader<NewGameEvent>,
+    pieces_query: Query<Entity, With<Piece>>,
+    asset_server: Res<AssetServer>,
+    mut turn: ResMut<PlayerTurn>,
+    mut ai_task: ResMut<AiTask>,
+    mut swivel_delay_query: Query<&mut SwivelDelay, With<Camera>>,
+    loading_data: ResMut<LoadingData>,
+    position_hash: ResMut<PositionHash>,
+    current_position: ResMut<CurrentPosition>,
+    mut history: ResMut<GameHistory>,
+) {
+    if new_game_event.read().count() == 0 {
+        return;
+    }
+    *history = GameHistory::default();
+    // Whatever the computer was thinking about is for the old board
+    ai_task.cancel();
+    // Despawn all pieces
+    for piece_entity in pieces_query.iter() {
+        commands.entity(piece_entity).despawn_recursive();
+    }
+    // Create new set of pieces
+    create_pieces(commands, loading_data, position_hash, current_position, asset_server);
+    // Set turn to white
+    turn.0 = PieceColour::White;
+    // Set swivel delay to 1.0 so it immediately swivels
+    let mut swivel_delay = swivel_delay_query.get_single_mut().unwrap();
+    swivel_delay.time.set_elapsed(Duration::from_secs_f32(1.0));
+}
+
 
 fn display_loading_screen(
     mut loading_screen: Query<&mut Visibility, With<LoadingScreen>>,
@@ -400,11 +522,27 @@ fn main() {
             DefaultPickingPlugins,
             piece::PiecesPlugin,
             square::SquaresPlugin,
+            ai::AiPlugin,
+            clock::ClockPlugin,
+            new_game::NewGamePlugin,
+            hint::HintPlugin,
+            resign::ResignPlugin,
+            analysis::AnalysisPlugin,
+            summary::SummaryPlugin,
+            game_clock::GameClockPlugin,
+            autosave::AutosavePlugin,
+            // ways to play other than a game on this computer
+            (network::NetworkPlugin, puzzle_mode::PuzzleModePlugin, correspondence_mode::CorrespondenceModePlugin, replay::ReplayPlugin),
             pipelines_ready::PipelinesReadyPlugin,
         ))
-        .add_systems(Startup, setup)
+        // spawned after the new game screen so the loading screen is drawn over it
+        .add_systems(Startup, setup.after(new_game::setup_new_game_screen))
         .add_systems(Update, (
-                swivel_camera, update_game_status, button_system, show_ui_on_win,
+                swivel_camera.run_if(not(network::free_camera)),
+                orbit_camera.run_if(network::free_camera),
+                update_game_status.run_if(not(analysis::analysing).and_then(not(puzzle_mode::in_puzzle)).and_then(not(replay::in_replay))),
+                button_colours, play_again_button, start_new_game,
+                show_ui_on_win,
                 display_loading_screen, update_loading_data))
         .run();
 }
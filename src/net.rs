@@ -0,0 +1,333 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{TcpListener, UdpSocket};
+use std::time::Duration;
+
+use crate::clock::TimeControl;
+use crate::piece::PieceColour;
+use crate::position::Move;
+use crate::record::GameResult;
+#[cfg(target_arch = "wasm32")]
+use crate::transport::{websocket_url, BrowserSocket};
+use crate::transport::{TcpTransport, Transport};
+
+/// Goes up whenever the messages change, games only play others on the same version.
+pub const PROTOCOL_VERSION: u32 = 4;
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// What games and the server say to each other, a line each (over WebSocket, a text message
+/// each). Between two games:
+///
+/// ```text
+/// hello 4                  first thing from the one joining, with its protocol version
+/// start black 300 2 0      the host's answer: the colour the joiner plays and the time
+///                          control in seconds (base, increment, delay), or `none`
+/// reject <reason>          the host's answer if it won't play, e.g. a different version
+/// move e2e4                a move, either way
+/// resign                   giving up the game, either way
+/// draw                     offering a draw, until the next move is played
+/// accept                   accepting the draw offered
+/// decline                  turning it down
+/// bye                      leaving the game, either way
+/// ```
+///
+/// Anyone else can watch, and is sent every move and how the game ends:
+///
+/// ```text
+/// watch 4                  instead of hello, to follow the game without playing
+/// watching 300 2 0         the answer, with the time control
+/// state e2e4 e7e5          the moves so far, to someone watching or resuming
+/// clock 178000 180000      white's and black's time left in milliseconds, after every move
+/// over 1-0 checkmate       the game is over, with the result and why
+/// ```
+///
+/// A server starts games with `hello` and `start` too, and adds:
+///
+/// ```text
+/// seat 12 5f0c9a...        before start: the game's number, and a token to get back in with
+/// resume 4 12 5f0c9a...    instead of hello, to get back into game 12 after losing the
+///                          connection
+/// watch 4 12               to watch game 12, rather than the one that started last
+/// reject <reason>          also for a move the server won't play
+/// over 1/2-1/2 agreement   instead of passing on `accept`, to both players
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Message {
+    Hello { version: u32 },
+    Resume { version: u32, game: u32, token: String },
+    Watch { version: u32, game: Option<u32> },
+    Watching { time_control: Option<TimeControl> },
+    Seat { game: u32, token: String },
+    Start { colour: PieceColour, time_control: Option<TimeControl> },
+    State(Vec<Move>),
+    Clock { white: Duration, black: Duration },
+    Move(Move),
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    Over { result: GameResult, reason: String },
+    Reject(String),
+    Bye,
+}
+
+// `none`, or the base, increment and delay in seconds
+fn parse_time_control(words: &[&str]) -> Option<Option<TimeControl>> {
+    let seconds = |word: &str| word.parse().map(Duration::from_secs).ok();
+    match words {
+        ["none"] => Some(None),
+        [base, increment, delay] => Some(Some(TimeControl {
+            base: seconds(base)?,
+            increment: seconds(increment)?,
+            delay: seconds(delay)?,
+        })),
+        _ => None,
+    }
+}
+
+struct ShowTimeControl(Option<TimeControl>);
+
+impl fmt::Display for ShowTimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => write!(f, "none"),
+            Some(control) => {
+                write!(f, "{} {} {}", control.base.as_secs(), control.increment.as_secs(), control.delay.as_secs())
+            }
+        }
+    }
+}
+
+impl Message {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let error = || format!("can't read message {line:?}");
+        let mut words = line.split_whitespace();
+        let kind = words.next().ok_or_else(error)?;
+        let rest: Vec<&str> = words.collect();
+        let number = |word: &str| word.parse::<u32>().map_err(|_| error());
+        let millis = |word: &str| word.parse().map(Duration::from_millis).map_err(|_| error());
+        let message = match (kind, &rest[..]) {
+            ("hello", [version]) => Message::Hello { version: number(version)? },
+            ("resume", [version, game, token]) => Message::Resume {
+                version: number(version)?,
+                game: number(game)?,
+                token: token.to_string(),
+            },
+            ("watch", [version]) => Message::Watch { version: number(version)?, game: None },
+            ("watch", [version, game]) => Message::Watch { version: number(version)?, game: Some(number(game)?) },
+            ("seat", [game, token]) => Message::Seat { game: number(game)?, token: token.to_string() },
+            ("start", [colour, time_control @ ..]) => {
+                let colour = match *colour {
+                    "white" => PieceColour::White,
+                    "black" => PieceColour::Black,
+                    _ => return Err(error()),
+                };
+                Message::Start { colour, time_control: parse_time_control(time_control).ok_or_else(error)? }
+            }
+            ("watching", time_control) => Message::Watching {
+                time_control: parse_time_control(time_control).ok_or_else(error)?,
+            },
+            ("state", moves) => Message::State(
+                moves.iter().map(|&mv| Move::from_notation(mv).ok_or_else(error)).collect::<Result<_, _>>()?,
+            ),
+            ("clock", [white, black]) => Message::Clock { white: millis(white)?, black: millis(black)? },
+            ("move", [mv]) => Message::Move(Move::from_notation(mv).ok_or_else(error)?),
+            ("resign", []) => Message::Resign,
+            ("draw", []) => Message::OfferDraw,
+            ("accept", []) => Message::AcceptDraw,
+            ("decline", []) => Message::DeclineDraw,
+            ("over", [result, reason @ ..]) => Message::Over {
+                result: GameResult::parse(result).ok_or_else(error)?,
+                reason: reason.join(" "),
+            },
+            ("reject", reason) => Message::Reject(reason.join(" ")),
+            ("bye", []) => Message::Bye,
+            _ => return Err(error()),
+        };
+        Ok(message)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::Hello { version } => write!(f, "hello {version}"),
+            Message::Resume { version, game, token } => write!(f, "resume {version} {game} {token}"),
+            Message::Watch { version, game: None } => write!(f, "watch {version}"),
+            Message::Watch { version, game: Some(game) } => write!(f, "watch {version} {game}"),
+            Message::Watching { time_control } => write!(f, "watching {}", ShowTimeControl(*time_control)),
+            Message::Seat { game, token } => write!(f, "seat {game} {token}"),
+            Message::Start { colour, time_control } => {
+                write!(f, "start {} {}", colour.name().to_lowercase(), ShowTimeControl(*time_control))
+            }
+            Message::State(moves) => {
+                write!(f, "state")?;
+                for mv in moves {
+                    write!(f, " {mv}")?;
+                }
+                Ok(())
+            }
+            Message::Clock { white, black } => write!(f, "clock {} {}", white.as_millis(), black.as_millis()),
+            Message::Move(mv) => write!(f, "move {mv}"),
+            Message::Resign => write!(f, "resign"),
+            Message::OfferDraw => write!(f, "draw"),
+            Message::AcceptDraw => write!(f, "accept"),
+            Message::DeclineDraw => write!(f, "decline"),
+            Message::Over { result, reason } => write!(f, "over {} {reason}", result.as_str()),
+            Message::Reject(reason) => write!(f, "reject {reason}"),
+            Message::Bye => write!(f, "bye"),
+        }
+    }
+}
+
+/// A connection to another game, sending and receiving messages over some `Transport`.
+pub struct Connection {
+    transport: Box<dyn Transport>,
+}
+
+impl Connection {
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        Self { transport: Box::new(transport) }
+    }
+
+    /// Connects to a game hosting at `address`, which can leave out the port. `ws://`
+    /// addresses connect with WebSocket, the only way the web version can. On the desktop this
+    /// blocks until it connects or gives up, so it's best run off the main thread.
+    pub fn connect(address: &str) -> Result<Self, String> {
+        #[cfg(target_arch = "wasm32")]
+        return BrowserSocket::connect(&websocket_url(address)).map(Self::new);
+        #[cfg(not(target_arch = "wasm32"))]
+        if address.starts_with("ws://") {
+            TcpTransport::connect_websocket(address).map(Self::new)
+        } else {
+            TcpTransport::connect(address).map(Self::new)
+        }
+    }
+
+    /// Who's on the other end.
+    pub fn peer(&self) -> String {
+        self.transport.peer()
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), String> {
+        self.transport.send_line(&message.to_string())
+    }
+
+    /// The next message that has come in, if there is one. An error once the connection is
+    /// closed or broken, or if the other side sends something that isn't a message.
+    pub fn receive(&mut self) -> Result<Option<Message>, String> {
+        self.transport.receive_line()?.map(|line| Message::parse(&line)).transpose()
+    }
+}
+
+/// Waits for a game to join this one, from the desktop version or a browser.
+pub struct Host {
+    listener: TcpListener,
+    // worked out once, as finding it means opening a socket
+    address: String,
+}
+
+impl Host {
+    /// Listens on every network this computer is on.
+    pub fn listen(port: u16) -> Result<Self, String> {
+        if cfg!(target_arch = "wasm32") {
+            return Err("hosting needs the desktop version".to_string());
+        }
+        let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|err| format!("can't listen on port {port}: {err}"))?;
+        listener.set_nonblocking(true).map_err(|err| format!("can't listen on port {port}: {err}"))?;
+        let address = lan_address(&listener);
+        Ok(Self { listener, address })
+    }
+
+    /// The connection from a game that has joined, if one has.
+    pub fn accept(&mut self) -> Result<Option<Connection>, String> {
+        match self.listener.accept() {
+            Ok((stream, _)) => TcpTransport::accepted(stream).map(|transport| Some(Connection::new(transport))),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(format!("can't accept connections: {err}")),
+        }
+    }
+
+    /// The address to give the other player, e.g. "192.168.1.20:7878".
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+// the address other computers on the network can reach `listener` on
+fn lan_address(listener: &TcpListener) -> String {
+    let port = listener.local_addr().map_or(DEFAULT_PORT, |address| address.port());
+    // connecting a UDP socket sends nothing, but picks the address other computers can
+    // reach this one on
+    let ip = UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| socket.connect("8.8.8.8:80").map(|_| socket))
+        .and_then(|socket| socket.local_addr())
+        .map_or("localhost".to_string(), |address| address.ip().to_string());
+    format!("{ip}:{port}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reads `line`, checking it's `message` and writes back the same
+    fn round_trip(line: &str, message: Message) {
+        assert_eq!(Message::parse(line), Ok(message.clone()), "reading {line:?}");
+        assert_eq!(message.to_string(), line);
+    }
+
+    fn mv(notation: &str) -> Move {
+        Move::from_notation(notation).unwrap()
+    }
+
+    #[test]
+    fn game_messages_round_trip() {
+        round_trip("hello 4", Message::Hello { version: 4 });
+        let time_control = Some(TimeControl::new(5, 2));
+        round_trip("start black 300 2 0", Message::Start { colour: PieceColour::Black, time_control });
+        round_trip("start white none", Message::Start { colour: PieceColour::White, time_control: None });
+        let reason = "this game has two players already".to_string();
+        round_trip("reject this game has two players already", Message::Reject(reason));
+        round_trip("move e2e4", Message::Move(mv("e2e4")));
+        round_trip("bye", Message::Bye);
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+        round_trip("seat 12 5f0c9a", Message::Seat { game: 12, token: "5f0c9a".to_string() });
+        round_trip("resume 4 12 5f0c9a", Message::Resume { version: 4, game: 12, token: "5f0c9a".to_string() });
+        round_trip("state e2e4 e7e5", Message::State(vec![mv("e2e4"), mv("e7e5")]));
+        round_trip("state", Message::State(Vec::new()));
+        let (white, black) = (Duration::from_millis(178_000), Duration::from_millis(180_000));
+        round_trip("clock 178000 180000", Message::Clock { white, black });
+        round_trip("over 1-0 checkmate", Message::Over { result: GameResult::WhiteWins, reason: "checkmate".to_string() });
+        round_trip("over 1/2-1/2 agreement", Message::Over { result: GameResult::Draw, reason: "agreement".to_string() });
+    }
+
+    #[test]
+    fn watching_messages_round_trip() {
+        round_trip("watch 4", Message::Watch { version: 4, game: None });
+        round_trip("watch 4 12", Message::Watch { version: 4, game: Some(12) });
+        round_trip("watching none", Message::Watching { time_control: None });
+        round_trip("watching 300 0 3", Message::Watching { time_control: Some(TimeControl::with_delay(5, 3)) });
+    }
+
+    #[test]
+    fn draw_messages_round_trip() {
+        round_trip("resign", Message::Resign);
+        round_trip("draw", Message::OfferDraw);
+        round_trip("accept", Message::AcceptDraw);
+        round_trip("decline", Message::DeclineDraw);
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        let lines = [
+            "", "hello", "hello x", "start purple", "start white 300", "move zz", "move", "bye now", "hi 4",
+            "clock x y", "clock 1000", "seat x token", "over 3-0 checkmate", "state e2e4 zz", "watch 4 x",
+        ];
+        for line in lines {
+            assert!(Message::parse(line).is_err(), "{line:?} was read");
+        }
+    }
+}
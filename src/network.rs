@@ -0,0 +1,901 @@
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use bevy::utils::{Duration, Instant};
+use gmtk2024::ai::{AiTask, PlayerKind, Players};
+use gmtk2024::clock::{ChessClock, TimeoutEvent};
+use gmtk2024::history::GameHistory;
+use gmtk2024::net::{Connection, Host, Message, DEFAULT_PORT, PROTOCOL_VERSION};
+use gmtk2024::piece::PieceColour;
+use gmtk2024::position::{Move, Position};
+use gmtk2024::record::GameResult;
+use gmtk2024::square::{CheckmateEvent, CurrentPosition, MoveEvent, PlayMoveEvent, SetPositionEvent};
+
+use crate::game_clock::ClockSettings;
+use crate::new_game::{setup_new_game_screen, spawn_button, NewGameEvent, NewGameScreen};
+use crate::resign::{resign_or_draw, AgreedResultEvent, ResignOrDrawEvent};
+
+// as long as a server keeps a seat for by default
+const RECONNECT_FOR: Duration = Duration::from_secs(60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// On the new game screen: waits for another game on the network to join this one.
+#[derive(Component)]
+pub struct HostButton;
+
+/// On the new game screen: asks for the address of a game that's hosting.
+#[derive(Component)]
+pub struct JoinButton;
+
+/// On the new game screen: asks for the address of a game to watch without playing.
+#[derive(Component)]
+pub struct WatchButton;
+
+#[derive(Component)]
+struct NetworkPanel;
+
+#[derive(Component)]
+struct NetworkStatusText;
+
+#[derive(Component)]
+struct LeaveNetworkButton;
+
+// while watching: which side the camera follows
+#[derive(Component)]
+struct CameraButton;
+
+// what to ask for once connected
+#[derive(Clone)]
+enum Request {
+    Play,
+    // a game on a server, or None for the host's game or the server's newest
+    Watch(Option<u32>),
+    // the seat kept by a server for the game this lost its connection to
+    Resume { game: u32, token: String },
+}
+
+// what the other side agreed to
+enum Agreed {
+    Play(PieceColour),
+    Watch,
+    // back in the game, with the moves played so far
+    Resume(Vec<Move>),
+}
+
+// a seat in a game on a server, and what's needed to take it back if the connection goes
+struct ServerSeat {
+    game: u32,
+    token: String,
+    // when the connection went, if it has
+    lost_at: Option<Instant>,
+}
+
+#[derive(Default)]
+enum NetState {
+    #[default]
+    Off,
+    // typing in the address to join or watch
+    Address { address: String, watching: bool },
+    // waiting for someone to join the game hosted here
+    Hosting,
+    Connecting(Task<Result<Connection, String>>, Request),
+    // connected, and waiting for the other side to agree on the game
+    Greeting { connection: Connection, request: Request },
+    Playing(Connection),
+    // lost the connection to a game on a server, and trying again at `retry_at`
+    Reconnecting { reason: String, retry_at: Instant },
+    // back in the game, until the board shows the position it's caught up to
+    Resuming { connection: Connection, awaiting: u64 },
+    // `awaiting` is the hash of the position the board is about to show, nothing more is
+    // read until it does so each move is played on the position it was played in
+    Watching { connection: Connection, awaiting: Option<u64> },
+    // what went wrong, or why the game stopped
+    Closed(String),
+}
+
+/// A game against another computer on the network, or one being watched.
+#[derive(Resource, Default)]
+pub struct NetGame {
+    state: NetState,
+    // the side played on this computer. Kept until the next game starts, so the camera
+    // doesn't move at the end
+    colour: Option<PieceColour>,
+    // who was playing before, put back once the game is over
+    players: Players,
+    // set while the board is being reset for a game over the network, so the reset isn't
+    // taken for an ordinary new game
+    starting: bool,
+    // while hosting: the listener, kept after someone joins for people to watch
+    host: Option<Host>,
+    // connected to the game hosted here, but not yet said whether to play or watch
+    arriving: Vec<Connection>,
+    watchers: Vec<Connection>,
+    // what was typed in to join or watch
+    address: String,
+    // while playing on a server: the way back in
+    seat: Option<ServerSeat>,
+    // watching a game rather than playing in it, until leaving
+    spectating: bool,
+    // while spectating: the side the camera stays on, None to move it freely
+    following: Option<PieceColour>,
+}
+
+impl NetGame {
+    /// The side the camera stays on: the one played here, or the one followed while watching.
+    pub fn camera_side(&self) -> Option<PieceColour> {
+        self.colour.or(self.following.filter(|_| self.spectating))
+    }
+
+    fn in_game(&self) -> bool {
+        matches!(self.state, NetState::Playing(_))
+    }
+
+    // a server keeps the seat for a while after the connection goes, so that's worth getting
+    // back into. Anything else is over
+    fn lose_connection(&mut self, reason: String) {
+        let now = Instant::now();
+        let lost_at = self.seat.as_mut().map(|seat| *seat.lost_at.get_or_insert(now));
+        self.state = match lost_at {
+            Some(lost_at) if now - lost_at < RECONNECT_FOR => NetState::Reconnecting { reason, retry_at: now + RETRY_INTERVAL },
+            _ => {
+                stop_hosting(self, &Message::Bye);
+                NetState::Closed(reason)
+            }
+        };
+    }
+}
+
+/// Whether someone watching has the camera to move themselves.
+pub fn free_camera(net_game: Res<NetGame>) -> bool {
+    net_game.spectating && net_game.following.is_none()
+}
+
+fn setup_network_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        NetworkPanel,
+    )).with_children(|column| {
+        column.spawn((
+            TextBundle::from_section("", TextStyle {
+                font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+                font_size: 24.0,
+                color: Color::srgb(0.9, 0.9, 0.9),
+            }),
+            NetworkStatusText,
+        ));
+        column.spawn(NodeBundle {
+            style: Style { column_gap: Val::Px(10.0), ..default() },
+            ..default()
+        }).with_children(|row| {
+            spawn_button(row, &asset_server, 120.0, "Leave", LeaveNetworkButton);
+            spawn_button(row, &asset_server, 240.0, "", CameraButton);
+        });
+    });
+}
+
+type NetworkButtons<'a> = (&'a Interaction, Has<HostButton>, Has<JoinButton>, Has<WatchButton>);
+
+fn network_buttons(
+    interaction_query: Query<NetworkButtons, Changed<Interaction>>,
+    mut net_game: ResMut<NetGame>,
+    mut new_game_visibility: Query<&mut Visibility, With<NewGameScreen>>,
+    mut panel_visibility: Query<&mut Visibility, (With<NetworkPanel>, Without<NewGameScreen>)>,
+) {
+    for (interaction, host_button, join_button, watch_button) in &interaction_query {
+        if *interaction != Interaction::Pressed || !(host_button || join_button || watch_button) {
+            continue;
+        }
+        net_game.state = if host_button {
+            match Host::listen(DEFAULT_PORT) {
+                Ok(host) => {
+                    net_game.host = Some(host);
+                    NetState::Hosting
+                }
+                Err(err) => NetState::Closed(err),
+            }
+        } else {
+            NetState::Address { address: String::new(), watching: watch_button }
+        };
+        *new_game_visibility.single_mut() = Visibility::Hidden;
+        *panel_visibility.single_mut() = Visibility::Visible;
+    }
+}
+
+fn type_address(mut keyboard_events: EventReader<KeyboardInput>, mut net_game: ResMut<NetGame>) {
+    if keyboard_events.is_empty() || !matches!(net_game.state, NetState::Address { .. }) {
+        keyboard_events.clear();
+        return;
+    }
+    let NetState::Address { address, watching } = &mut net_game.state else {
+        return;
+    };
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Character(text) => address.push_str(text),
+            Key::Space => address.push(' '),
+            Key::Backspace => { address.pop(); },
+            Key::Enter if !address.trim().is_empty() => {
+                // a game on a server is picked by its number after the address
+                let mut words = address.split_whitespace();
+                let host = words.next().unwrap_or_default().to_string();
+                let next_state = match (*watching, words.next()) {
+                    (false, _) => connect(host.clone(), Request::Play),
+                    (true, None) => connect(host.clone(), Request::Watch(None)),
+                    (true, Some(game)) => match game.parse() {
+                        Ok(game) => connect(host.clone(), Request::Watch(Some(game))),
+                        Err(_) => NetState::Closed(format!("{game} isn't a game number")),
+                    },
+                };
+                net_game.address = host;
+                net_game.seat = None;
+                net_game.state = next_state;
+                return;
+            }
+            _ => {},
+        }
+    }
+}
+
+fn say_hello(connection: Result<Connection, String>, request: Request) -> NetState {
+    let hello = match request {
+        Request::Play => Message::Hello { version: PROTOCOL_VERSION },
+        Request::Watch(game) => Message::Watch { version: PROTOCOL_VERSION, game },
+        Request::Resume { game, ref token } => Message::Resume { version: PROTOCOL_VERSION, game, token: token.clone() },
+    };
+    match connection.and_then(|mut connection| connection.send(&hello).map(|()| connection)) {
+        Ok(connection) => NetState::Greeting { connection, request },
+        Err(err) => NetState::Closed(err),
+    }
+}
+
+// the browser connects in the background by itself, the desktop version needs a task to
+#[cfg(target_arch = "wasm32")]
+fn connect(address: String, request: Request) -> NetState {
+    say_hello(Connection::connect(&address), request)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn connect(address: String, request: Request) -> NetState {
+    NetState::Connecting(AsyncComputeTaskPool::get().spawn(async move { Connection::connect(&address) }), request)
+}
+
+// both sides set up the same game: the player here against moves from the network
+fn start_net_game(
+    net_game: &mut NetGame,
+    colour: PieceColour,
+    players: &mut Players,
+    ai_task: &mut AiTask,
+    new_game_writer: &mut EventWriter<NewGameEvent>,
+) {
+    net_game.colour = Some(colour);
+    net_game.players = *players;
+    net_game.starting = true;
+    *players.get_mut(colour) = PlayerKind::Human;
+    *players.get_mut(colour.opposite()) = PlayerKind::Scripted;
+    ai_task.cancel();
+    new_game_writer.send(NewGameEvent);
+}
+
+// the board follows a game being played somewhere else, with nobody here moving the pieces
+fn start_watching(
+    net_game: &mut NetGame,
+    players: &mut Players,
+    ai_task: &mut AiTask,
+    new_game_writer: &mut EventWriter<NewGameEvent>,
+) {
+    net_game.colour = None;
+    net_game.players = *players;
+    net_game.starting = true;
+    net_game.spectating = true;
+    net_game.following = Some(PieceColour::White);
+    *players.get_mut(PieceColour::White) = PlayerKind::Scripted;
+    *players.get_mut(PieceColour::Black) = PlayerKind::Scripted;
+    ai_task.cancel();
+    new_game_writer.send(NewGameEvent);
+}
+
+// agrees on the game with the host or server, once they answer
+fn greet(
+    connection: &mut Connection,
+    request: &Request,
+    colour: Option<PieceColour>,
+    seat: &mut Option<ServerSeat>,
+    clock_settings: &mut ClockSettings,
+) -> Result<Option<Agreed>, String> {
+    let Some(message) = connection.receive()? else {
+        return Ok(None);
+    };
+    match (request, message) {
+        // a server's way back into the game if the connection goes
+        (Request::Play, Message::Seat { game, token }) => {
+            *seat = Some(ServerSeat { game, token, lost_at: None });
+            Ok(None)
+        }
+        (Request::Play, Message::Start { colour, time_control }) => {
+            clock_settings.0 = time_control;
+            Ok(Some(Agreed::Play(colour)))
+        }
+        (Request::Watch(_), Message::Watching { time_control }) => {
+            clock_settings.0 = time_control;
+            Ok(Some(Agreed::Watch))
+        }
+        // the server says which side again, then catches up with the moves played meanwhile
+        (Request::Resume { .. }, Message::Start { colour: side, .. }) if Some(side) == colour => Ok(None),
+        (Request::Resume { .. }, Message::State(moves)) => Ok(Some(Agreed::Resume(moves))),
+        (Request::Play, Message::Reject(reason)) => Err(format!("The host won't play: {reason}")),
+        (Request::Watch(_), Message::Reject(reason)) => Err(format!("Can't watch: {reason}")),
+        (Request::Resume { .. }, Message::Reject(reason)) => Err(format!("Can't get back into the game: {reason}")),
+        (_, message) => Err(format!("Unexpected message: {message}")),
+    }
+}
+
+// the game so far, from moves sent over the network
+fn replay(moves: Vec<Move>) -> Result<(GameHistory, Position), String> {
+    let mut history = GameHistory::default();
+    let mut position = Position::default();
+    for mv in moves {
+        if !position.is_legal(mv) {
+            return Err(format!("The game sent an illegal move, {mv}"));
+        }
+        position.make_move(mv);
+        history.play(mv);
+    }
+    Ok((history, position))
+}
+
+fn clock_message(clock: &ChessClock) -> Message {
+    Message::Clock { white: clock.remaining(PieceColour::White), black: clock.remaining(PieceColour::Black) }
+}
+
+// people connecting to the game hosted here: the first to say hello plays it, and anyone can
+// watch. Returns the connection of the one who's going to play
+fn greet_arrivals(
+    net_game: &mut NetGame,
+    clock_settings: &ClockSettings,
+    clock: &ChessClock,
+    history: &GameHistory,
+) -> Option<Connection> {
+    if let Some(host) = net_game.host.as_mut() {
+        // the game carries on if the listener breaks, it just can't be joined
+        while let Ok(Some(connection)) = host.accept() {
+            net_game.arriving.push(connection);
+        }
+    }
+    let in_game = net_game.in_game();
+    let mut player = None;
+    for mut connection in std::mem::take(&mut net_game.arriving) {
+        let answer = match connection.receive() {
+            Ok(None) => {
+                net_game.arriving.push(connection);
+                continue;
+            }
+            Ok(Some(Message::Hello { version } | Message::Watch { version, .. })) if version != PROTOCOL_VERSION => {
+                Message::Reject(format!("this game speaks version {PROTOCOL_VERSION}, not {version}"))
+            }
+            // the host plays white
+            Ok(Some(Message::Hello { .. })) if !in_game && player.is_none() => {
+                let start = Message::Start { colour: PieceColour::Black, time_control: clock_settings.0 };
+                if connection.send(&start).is_ok() {
+                    player = Some(connection);
+                }
+                continue;
+            }
+            Ok(Some(Message::Hello { .. })) => Message::Reject("this game has two players already, but it can be watched".to_string()),
+            // someone watching from before the game starts sees it from the first move
+            Ok(Some(Message::Watch { .. })) => {
+                let (time_control, moves) = if in_game { (clock.control(), history.line()) } else { (clock_settings.0, Vec::new()) };
+                let mut caught_up = connection.send(&Message::Watching { time_control })
+                    .and_then(|()| connection.send(&Message::State(moves)));
+                if in_game && time_control.is_some() {
+                    caught_up = caught_up.and_then(|()| connection.send(&clock_message(clock)));
+                }
+                if caught_up.is_ok() {
+                    net_game.watchers.push(connection);
+                }
+                continue;
+            }
+            Ok(Some(message)) => Message::Reject(format!("didn't expect {message}")),
+            Err(_) => continue,
+        };
+        let _ = connection.send(&answer);
+    }
+    player
+}
+
+// tells everyone watching the game hosted here that it's over, and stops anyone else joining
+fn stop_hosting(net_game: &mut NetGame, message: &Message) {
+    for connection in &mut net_game.watchers {
+        let _ = connection.send(message);
+    }
+    net_game.watchers.clear();
+    net_game.arriving.clear();
+    net_game.host = None;
+}
+
+// plays the other side's moves on the board, one a frame so each is checked against the
+// position it's played in. They're checked here as well as by the board, so a bad one ends the
+// game instead of being quietly dropped
+fn receive_moves(
+    message: Option<Message>,
+    connection: &mut Connection,
+    colour: PieceColour,
+    position: &CurrentPosition,
+    clock: &mut ChessClock,
+    play_move_writer: &mut EventWriter<PlayMoveEvent>,
+    resign_or_draw_writer: &mut EventWriter<ResignOrDrawEvent>,
+) -> Result<(), String> {
+    let other = colour.opposite();
+    match message {
+        None => Ok(()),
+        Some(Message::Move(mv)) if position.0.turn != colour && position.0.is_legal(mv) => {
+            play_move_writer.send(PlayMoveEvent(mv));
+            Ok(())
+        }
+        Some(Message::Move(mv)) => {
+            let _ = connection.send(&Message::Bye);
+            Err(format!("The other player sent an illegal move, {mv}"))
+        }
+        // a server's clocks are the ones that count
+        Some(Message::Clock { white, black }) => {
+            clock.set_remaining(PieceColour::White, white);
+            clock.set_remaining(PieceColour::Black, black);
+            Ok(())
+        }
+        // the rest of the game ends once the board has the result, so nothing more's read
+        // before then
+        Some(Message::Resign) => {
+            resign_or_draw_writer.send(ResignOrDrawEvent::Resign(other));
+            Ok(())
+        }
+        Some(Message::OfferDraw) => {
+            resign_or_draw_writer.send(ResignOrDrawEvent::OfferDraw(other));
+            Ok(())
+        }
+        Some(Message::AcceptDraw) => {
+            resign_or_draw_writer.send(ResignOrDrawEvent::AcceptDraw(other));
+            Ok(())
+        }
+        Some(Message::DeclineDraw) => {
+            resign_or_draw_writer.send(ResignOrDrawEvent::DeclineDraw(other));
+            Ok(())
+        }
+        // the board sees checkmate and running out of time for itself
+        Some(Message::Over { reason, .. }) if reason == "checkmate" || reason == "timeout" => Ok(()),
+        // a server says how the other player resigned or took the draw instead of passing it on
+        Some(Message::Over { reason, .. }) if reason == "resignation" => {
+            resign_or_draw_writer.send(ResignOrDrawEvent::Resign(other));
+            Ok(())
+        }
+        Some(Message::Over { reason, .. }) if reason == "agreement" => {
+            resign_or_draw_writer.send(ResignOrDrawEvent::AcceptDraw(other));
+            Ok(())
+        }
+        Some(Message::Over { result, reason }) => Err(format!("Game over, {}: {reason}", result.as_str())),
+        Some(Message::Reject(reason)) => Err(format!("The server wouldn't play that: {reason}")),
+        Some(Message::Bye) => Err("The other player left".to_string()),
+        Some(message) => Err(format!("Unexpected message: {message}")),
+    }
+}
+
+// follows the game being watched: the moves so far all at once, then each as it's played
+fn watch_moves(
+    connection: &mut Connection,
+    awaiting: &mut Option<u64>,
+    position: &CurrentPosition,
+    history: &mut GameHistory,
+    clock: &mut ChessClock,
+    set_position_writer: &mut EventWriter<SetPositionEvent>,
+    play_move_writer: &mut EventWriter<PlayMoveEvent>,
+) -> Result<(), String> {
+    // a move the board won't play once a side has flagged isn't worth waiting for
+    if awaiting.is_some_and(|hash| hash != position.0.hash) && clock.flagged().is_none() {
+        return Ok(());
+    }
+    *awaiting = None;
+    let message = connection.receive().map_err(|err| format!("Lost the other player: {err}"))?;
+    match message {
+        None => Ok(()),
+        Some(Message::State(moves)) => {
+            let (caught_up, position) = replay(moves)?;
+            *history = caught_up;
+            if position.outcome().is_none() {
+                clock.start(position.turn);
+            }
+            *awaiting = Some(position.hash);
+            set_position_writer.send(SetPositionEvent(position));
+            Ok(())
+        }
+        Some(Message::Move(mv)) if position.0.is_legal(mv) => {
+            let mut after = position.0.clone();
+            after.make_move(mv);
+            *awaiting = Some(after.hash);
+            play_move_writer.send(PlayMoveEvent(mv));
+            Ok(())
+        }
+        Some(Message::Move(mv)) => Err(format!("The game sent an illegal move, {mv}")),
+        Some(Message::Clock { white, black }) => {
+            clock.set_remaining(PieceColour::White, white);
+            clock.set_remaining(PieceColour::Black, black);
+            Ok(())
+        }
+        Some(Message::Over { result, reason }) => Err(format!("Game over, {}: {reason}", result.as_str())),
+        Some(Message::Bye) => Err("The players left".to_string()),
+        Some(message) => Err(format!("Unexpected message: {message}")),
+    }
+}
+
+// everything that comes in from the other game, and keeping the connection going
+fn poll_network(
+    mut net_game: ResMut<NetGame>,
+    (mut players, mut ai_task): (ResMut<Players>, ResMut<AiTask>),
+    (mut clock_settings, mut clock): (ResMut<ClockSettings>, ResMut<ChessClock>),
+    (position, mut history): (Res<CurrentPosition>, ResMut<GameHistory>),
+    mut new_game_writer: EventWriter<NewGameEvent>,
+    (mut play_move_writer, mut set_position_writer, mut resign_or_draw_writer): (
+        EventWriter<PlayMoveEvent>,
+        EventWriter<SetPositionEvent>,
+        EventWriter<ResignOrDrawEvent>,
+    ),
+) {
+    // polling isn't a change, the panel only needs redoing when the state does
+    let net = net_game.bypass_change_detection();
+    let colour = net.colour;
+    if net.host.is_some() {
+        let watchers = net.watchers.len();
+        let player = greet_arrivals(net, &clock_settings, &clock, &history);
+        // anyone watching only ever says bye
+        net.watchers.retain_mut(|connection| matches!(connection.receive(), Ok(None)));
+        if let Some(connection) = player {
+            start_net_game(net, PieceColour::White, &mut players, &mut ai_task, &mut new_game_writer);
+            net_game.state = NetState::Playing(connection);
+            return;
+        }
+        if net.watchers.len() != watchers {
+            net_game.set_changed();
+        }
+    }
+    let net = net_game.bypass_change_detection();
+    let resuming = matches!(
+        net.state,
+        NetState::Reconnecting { .. }
+            | NetState::Connecting(_, Request::Resume { .. })
+            | NetState::Greeting { request: Request::Resume { .. }, .. }
+    );
+    let next_state = match &mut net.state {
+        NetState::Off | NetState::Address { .. } | NetState::Hosting | NetState::Closed(_) => return,
+        NetState::Connecting(task, request) => match block_on(future::poll_once(task)) {
+            Some(connection) => say_hello(connection, request.clone()),
+            None => return,
+        },
+        NetState::Greeting { connection, request } => match greet(connection, request, colour, &mut net.seat, &mut clock_settings) {
+            Ok(Some(agreed)) => {
+                let NetState::Greeting { connection, .. } = std::mem::take(&mut net.state) else {
+                    unreachable!();
+                };
+                match agreed {
+                    Agreed::Play(colour) => {
+                        start_net_game(net, colour, &mut players, &mut ai_task, &mut new_game_writer);
+                        NetState::Playing(connection)
+                    }
+                    Agreed::Watch => {
+                        start_watching(net, &mut players, &mut ai_task, &mut new_game_writer);
+                        NetState::Watching { connection, awaiting: None }
+                    }
+                    // the board goes to where the game's got to, which is where it was unless
+                    // the other player moved or a move from here didn't get there
+                    Agreed::Resume(moves) => match replay(moves) {
+                        Ok((caught_up, position)) => {
+                            if let Some(seat) = net.seat.as_mut() {
+                                seat.lost_at = None;
+                            }
+                            *history = caught_up;
+                            if position.outcome().is_none() {
+                                clock.start(position.turn);
+                            }
+                            let awaiting = position.hash;
+                            set_position_writer.send(SetPositionEvent(position));
+                            NetState::Resuming { connection, awaiting }
+                        }
+                        Err(reason) => NetState::Closed(reason),
+                    },
+                }
+            }
+            Ok(None) => return,
+            Err(reason) => NetState::Closed(reason),
+        },
+        NetState::Playing(connection) => {
+            let colour = colour.expect("playing without a side");
+            let message = match connection.receive() {
+                Ok(message) => message,
+                Err(err) => return net_game.lose_connection(format!("Lost the other player: {err}")),
+            };
+            match receive_moves(message, connection, colour, &position, &mut clock, &mut play_move_writer, &mut resign_or_draw_writer) {
+                Ok(()) => return,
+                Err(reason) => NetState::Closed(reason),
+            }
+        }
+        NetState::Reconnecting { retry_at, .. } => {
+            if Instant::now() < *retry_at {
+                return;
+            }
+            let seat = net.seat.as_ref().expect("reconnecting without a seat");
+            connect(net.address.clone(), Request::Resume { game: seat.game, token: seat.token.clone() })
+        }
+        // moves from the server are only read once they can be checked against the board
+        NetState::Resuming { awaiting, .. } => {
+            if position.0.hash != *awaiting {
+                return;
+            }
+            let NetState::Resuming { connection, .. } = std::mem::take(&mut net.state) else {
+                unreachable!();
+            };
+            NetState::Playing(connection)
+        }
+        NetState::Watching { connection, awaiting } => match watch_moves(
+            connection,
+            awaiting,
+            &position,
+            &mut history,
+            &mut clock,
+            &mut set_position_writer,
+            &mut play_move_writer,
+        ) {
+            Ok(()) => return,
+            Err(reason) => NetState::Closed(reason),
+        },
+    };
+    match next_state {
+        // the server might just be out of reach for a moment
+        NetState::Closed(reason) if resuming => net_game.lose_connection(reason),
+        NetState::Closed(_) => {
+            stop_hosting(net, &Message::Bye);
+            net_game.state = next_state;
+        }
+        _ => net_game.state = next_state,
+    }
+}
+
+// moves played here go to the other game
+fn send_moves(
+    mut move_events: EventReader<MoveEvent>,
+    history: Res<GameHistory>,
+    position: Res<CurrentPosition>,
+    mut net_game: ResMut<NetGame>,
+) {
+    if move_events.read().count() == 0 || !net_game.in_game() {
+        return;
+    }
+    // the other side's moves came from the network in the first place
+    if Some(position.0.turn.opposite()) != net_game.colour {
+        return;
+    }
+    let Some(&mv) = history.line().last() else {
+        return;
+    };
+    let NetState::Playing(connection) = &mut net_game.state else {
+        return;
+    };
+    if let Err(err) = connection.send(&Message::Move(mv)) {
+        net_game.lose_connection(format!("Lost the other player: {err}"));
+    }
+}
+
+// every move in the game hosted here goes to everyone watching it, with the clocks after it
+fn tell_watchers(
+    mut move_events: EventReader<MoveEvent>,
+    history: Res<GameHistory>,
+    clock: Res<ChessClock>,
+    mut net_game: ResMut<NetGame>,
+) {
+    if move_events.read().count() == 0 || net_game.watchers.is_empty() || !net_game.in_game() {
+        return;
+    }
+    let Some(&mv) = history.line().last() else {
+        return;
+    };
+    let clock = clock.control().map(|_| clock_message(&clock));
+    // the panel only changes if someone's gone
+    net_game.bypass_change_detection().watchers.retain_mut(|connection| {
+        connection.send(&Message::Move(mv)).is_ok() && clock.iter().all(|clock| connection.send(clock).is_ok())
+    });
+}
+
+// resigning and draw offers made here go to the other player
+fn send_offers(mut resign_or_draw_events: EventReader<ResignOrDrawEvent>, mut net_game: ResMut<NetGame>) {
+    let colour = net_game.colour;
+    let messages: Vec<Message> = resign_or_draw_events.read().filter_map(|&event| match event {
+        ResignOrDrawEvent::Resign(side) if Some(side) == colour => Some(Message::Resign),
+        ResignOrDrawEvent::OfferDraw(side) if Some(side) == colour => Some(Message::OfferDraw),
+        ResignOrDrawEvent::AcceptDraw(side) if Some(side) == colour => Some(Message::AcceptDraw),
+        ResignOrDrawEvent::DeclineDraw(side) if Some(side) == colour => Some(Message::DeclineDraw),
+        _ => None,
+    }).collect();
+    if messages.is_empty() || !net_game.in_game() {
+        return;
+    }
+    let NetState::Playing(connection) = &mut net_game.state else {
+        return;
+    };
+    if let Err(err) = messages.iter().try_for_each(|message| connection.send(message)) {
+        net_game.lose_connection(format!("Lost the other player: {err}"));
+    }
+}
+
+// the game is over the same way on both sides, so the connection can go
+fn end_net_game(
+    (mut checkmate_events, mut timeout_events): (EventReader<CheckmateEvent>, EventReader<TimeoutEvent>),
+    mut agreed_result_events: EventReader<AgreedResultEvent>,
+    mut net_game: ResMut<NetGame>,
+    mut players: ResMut<Players>,
+    mut panel_visibility: Query<&mut Visibility, With<NetworkPanel>>,
+) {
+    let checkmates = checkmate_events.read().map(|&CheckmateEvent(loser)| (GameResult::win_for(loser.opposite()), "checkmate"));
+    let over = checkmates
+        .chain(timeout_events.read().map(|&TimeoutEvent(loser)| (GameResult::win_for(loser.opposite()), "timeout")))
+        .chain(agreed_result_events.read().map(|&AgreedResultEvent(result)| match result {
+            GameResult::Draw => (result, "agreement"),
+            _ => (result, "resignation"),
+        }))
+        .last();
+    let Some((result, reason)) = over.filter(|_| net_game.in_game()) else {
+        return;
+    };
+    if let NetState::Playing(connection) = &mut net_game.state {
+        let _ = connection.send(&Message::Bye);
+    }
+    stop_hosting(&mut net_game, &Message::Over { result, reason: reason.to_string() });
+    net_game.state = NetState::Off;
+    net_game.seat = None;
+    *players = net_game.players;
+    *panel_visibility.single_mut() = Visibility::Hidden;
+}
+
+// a new game that isn't over the network puts the camera back to normal
+fn forget_colour(mut new_game_events: EventReader<NewGameEvent>, mut net_game: ResMut<NetGame>) {
+    if new_game_events.read().count() == 0 {
+        return;
+    }
+    if net_game.starting {
+        net_game.starting = false;
+    } else if net_game.colour.is_some() {
+        net_game.colour = None;
+    }
+}
+
+fn leave_network_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<LeaveNetworkButton>)>,
+    mut net_game: ResMut<NetGame>,
+    mut players: ResMut<Players>,
+    mut panel_visibility: Query<&mut Visibility, With<NetworkPanel>>,
+    mut new_game_visibility: Query<&mut Visibility, (With<NewGameScreen>, Without<NetworkPanel>)>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    let was_playing = net_game.colour.is_some() || net_game.spectating;
+    match &mut net_game.state {
+        NetState::Greeting { connection, .. }
+        | NetState::Playing(connection)
+        | NetState::Resuming { connection, .. }
+        | NetState::Watching { connection, .. } => {
+            let _ = connection.send(&Message::Bye);
+        }
+        _ => {},
+    }
+    stop_hosting(&mut net_game, &Message::Bye);
+    net_game.state = NetState::Off;
+    net_game.seat = None;
+    if was_playing {
+        net_game.colour = None;
+        net_game.spectating = false;
+        net_game.following = None;
+        *players = net_game.players;
+    }
+    *panel_visibility.single_mut() = Visibility::Hidden;
+    *new_game_visibility.single_mut() = Visibility::Visible;
+}
+
+// White's side, then Black's, then wherever whoever's watching puts it
+fn camera_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<CameraButton>)>,
+    mut net_game: ResMut<NetGame>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    net_game.following = match net_game.following {
+        Some(PieceColour::White) => Some(PieceColour::Black),
+        Some(PieceColour::Black) => None,
+        None => Some(PieceColour::White),
+    };
+}
+
+fn update_network_panel(
+    net_game: Res<NetGame>,
+    mut status_text: Query<&mut Text, With<NetworkStatusText>>,
+    mut camera_button: Query<(&mut Visibility, &Children), With<CameraButton>>,
+    mut texts: Query<&mut Text, Without<NetworkStatusText>>,
+) {
+    let host_address = net_game.host.as_ref().map(|host| host.address()).unwrap_or_default();
+    let mut status = match &net_game.state {
+        NetState::Off => String::new(),
+        NetState::Address { address, watching: false } => {
+            format!("Join the game at: {address}_\nType the address shown on the host, then Enter")
+        }
+        NetState::Address { address, watching: true } => format!(
+            "Watch the game at: {address}_\nType the address shown on the host, or a server's and\n\
+             the number of the game on it, then Enter"
+        ),
+        NetState::Hosting => format!("Waiting for someone to join at {host_address}"),
+        NetState::Connecting(..) => "Connecting...".to_string(),
+        NetState::Greeting { .. } => "Connected, setting up the game...".to_string(),
+        NetState::Playing(connection) => format!(
+            "Playing {} against {}",
+            net_game.colour.unwrap_or(PieceColour::White).name(),
+            connection.peer(),
+        ),
+        NetState::Reconnecting { reason, .. } => format!("{reason}\nTrying to get back in..."),
+        NetState::Resuming { .. } => "Back in, catching up...".to_string(),
+        NetState::Watching { connection, .. } => format!("Watching the game at {}", connection.peer()),
+        NetState::Closed(reason) => reason.clone(),
+    };
+    if net_game.in_game() && net_game.host.is_some() {
+        status += &format!("\nOthers can watch at {host_address}");
+        if !net_game.watchers.is_empty() {
+            status += &format!(", {} watching", net_game.watchers.len());
+        }
+    }
+    if net_game.spectating && net_game.following.is_none() {
+        status += "\nLeft and right go round the board, up and down closer";
+    }
+    status_text.single_mut().sections[0].value = status;
+
+    let (mut visibility, children) = camera_button.single_mut();
+    *visibility = if net_game.spectating { Visibility::Inherited } else { Visibility::Hidden };
+    if let Ok(mut text) = texts.get_mut(children[0]) {
+        text.sections[0].value = match net_game.following {
+            Some(colour) => format!("Camera: {}'s side", colour.name()),
+            None => "Camera: free".to_string(),
+        };
+    }
+}
+
+pub struct NetworkPlugin;
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(NetGame::default())
+            // under the new game screen
+            .add_systems(Startup, setup_network_panel.before(setup_new_game_screen))
+            .add_systems(Update, (
+                network_buttons,
+                type_address,
+                // the other player resigning or taking a draw ends the game here before
+                // anything else is read
+                poll_network.before(resign_or_draw),
+                forget_colour,
+                send_moves,
+                tell_watchers,
+                send_offers.after(resign_or_draw),
+                end_net_game,
+                leave_network_button,
+                camera_button,
+                update_network_panel.run_if(resource_changed::<NetGame>),
+            ).chain());
+    }
+}
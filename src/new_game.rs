@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use gmtk2024::ai::{AiSettings, PlayerKind, Players, DIFFICULTIES, PERSONALITIES};
+use gmtk2024::piece::PieceColour;
+
+use crate::autosave::ResumeButton;
+use crate::correspondence_mode::CorrespondenceButton;
+use crate::game_clock::TimeControlButton;
+use crate::network::{HostButton, JoinButton, WatchButton};
+use crate::puzzle_mode::PuzzlesButton;
+use crate::replay::ReplayButton;
+use crate::BUTTON_COLOR;
+
+/// Sent when the start button is pressed, the board gets reset for the players picked.
+#[derive(Event)]
+pub struct NewGameEvent;
+
+#[derive(Component)]
+pub struct NewGameScreen;
+
+// cycles between human and each difficulty
+#[derive(Component)]
+struct PlayerButton(PieceColour);
+
+// cycles through the personalities, hidden for humans
+#[derive(Component)]
+struct PersonalityButton(PieceColour);
+
+#[derive(Component)]
+struct StartButton;
+
+
+fn next_player(kind: PlayerKind) -> PlayerKind {
+    match kind {
+        PlayerKind::Human => PlayerKind::Computer(AiSettings { difficulty: DIFFICULTIES[0], ..default() }),
+        PlayerKind::Computer(settings) => {
+            let i = DIFFICULTIES.iter().position(|&d| d == settings.difficulty).unwrap();
+            match DIFFICULTIES.get(i + 1) {
+                Some(&difficulty) => PlayerKind::Computer(AiSettings { difficulty, ..settings }),
+                None => PlayerKind::Human,
+            }
+        }
+        PlayerKind::Scripted => PlayerKind::Human,
+    }
+}
+
+fn player_label(kind: PlayerKind) -> String {
+    match kind {
+        PlayerKind::Human => "Human".to_string(),
+        PlayerKind::Computer(settings) => format!("Computer ({})", settings.difficulty.name()),
+        PlayerKind::Scripted => "Scripted".to_string(),
+    }
+}
+
+pub fn spawn_button(parent: &mut ChildBuilder, asset_server: &AssetServer, width: f32, label: &str, marker: impl Bundle) {
+    parent.spawn((
+        ButtonBundle {
+            style: Style {
+                width: Val::Px(width),
+                height: Val::Px(50.0),
+                // horizontally center child text
+                justify_content: JustifyContent::Center,
+                // vertically center child text
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            background_color: BUTTON_COLOR.into(),
+            border_radius: BorderRadius::all(Val::Px(5.0)),
+            ..default()
+        },
+        marker,
+    )).with_children(|parent2| {
+        parent2.spawn(TextBundle::from_section(
+            label,
+            TextStyle {
+                font: asset_server.load("fonts/IBMPlexSerif-Italic.ttf"),
+                font_size: 28.0,
+                color: Color::srgb(0.9, 0.9, 0.9),
+            },
+        ));
+    });
+}
+
+pub fn setup_new_game_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 32.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            background_color: Color::srgba(0.12, 0.1, 0.15, 0.8).into(),
+            ..default()
+        },
+        NewGameScreen,
+    )).with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+            "New game",
+            TextStyle { font_size: 40.0, ..text_style.clone() },
+        ));
+        for (colour, name) in [(PieceColour::White, "White"), (PieceColour::Black, "Black")] {
+            parent.spawn(NodeBundle {
+                style: Style {
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                row.spawn(TextBundle::from_section(name, text_style.clone()).with_style(Style {
+                    width: Val::Px(100.0),
+                    ..default()
+                }));
+                // the labels are filled in by update_labels
+                spawn_button(row, &asset_server, 260.0, "", PlayerButton(colour));
+                spawn_button(row, &asset_server, 160.0, "", PersonalityButton(colour));
+            });
+        }
+        // the label is filled in by the clock's plugin
+        spawn_button(parent, &asset_server, 200.0, "", TimeControlButton);
+        spawn_button(parent, &asset_server, 200.0, "Start", StartButton);
+        spawn_button(parent, &asset_server, 240.0, "Resume game", ResumeButton);
+        spawn_button(parent, &asset_server, 200.0, "Puzzles", PuzzlesButton);
+        spawn_button(parent, &asset_server, 240.0, "Correspondence", CorrespondenceButton);
+        spawn_button(parent, &asset_server, 200.0, "Replay", ReplayButton);
+        parent.spawn(NodeBundle {
+            style: Style { column_gap: Val::Px(10.0), ..default() },
+            ..default()
+        }).with_children(|row| {
+            spawn_button(row, &asset_server, 200.0, "Host game", HostButton);
+            spawn_button(row, &asset_server, 200.0, "Join game", JoinButton);
+            spawn_button(row, &asset_server, 200.0, "Watch game", WatchButton);
+        });
+    });
+}
+
+type NewGameButtons<'a> = (&'a Interaction, Option<&'a PlayerButton>, Option<&'a PersonalityButton>, Has<StartButton>);
+
+fn new_game_buttons(
+    interaction_query: Query<NewGameButtons, Changed<Interaction>>,
+    mut players: ResMut<Players>,
+    mut screen_visibility: Query<&mut Visibility, With<NewGameScreen>>,
+    mut new_game_writer: EventWriter<NewGameEvent>,
+) {
+    for (interaction, player_button, personality_button, start_button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let Some(PlayerButton(colour)) = player_button {
+            let player = players.get_mut(*colour);
+            *player = next_player(*player);
+        }
+        if let Some(PersonalityButton(colour)) = personality_button {
+            if let PlayerKind::Computer(settings) = players.get_mut(*colour) {
+                let i = PERSONALITIES.iter().position(|&p| p == settings.personality).unwrap();
+                settings.personality = PERSONALITIES[(i + 1) % PERSONALITIES.len()];
+            }
+        }
+        if start_button {
+            *screen_visibility.single_mut() = Visibility::Hidden;
+            new_game_writer.send(NewGameEvent);
+        }
+    }
+}
+
+fn update_labels(
+    players: Res<Players>,
+    mut player_buttons: Query<(&PlayerButton, &Children)>,
+    mut personality_buttons: Query<(&PersonalityButton, &Children, &mut Visibility)>,
+    mut texts: Query<&mut Text>,
+) {
+    for (PlayerButton(colour), children) in player_buttons.iter_mut() {
+        if let Ok(mut text) = texts.get_mut(children[0]) {
+            text.sections[0].value = player_label(players.get(*colour));
+        }
+    }
+    for (PersonalityButton(colour), children, mut visibility) in personality_buttons.iter_mut() {
+        match players.get(*colour) {
+            PlayerKind::Human | PlayerKind::Scripted => *visibility = Visibility::Hidden,
+            PlayerKind::Computer(settings) => {
+                *visibility = Visibility::Inherited;
+                if let Ok(mut text) = texts.get_mut(children[0]) {
+                    text.sections[0].value = settings.personality.name().to_string();
+                }
+            }
+        }
+    }
+}
+
+pub struct NewGamePlugin;
+impl Plugin for NewGamePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<NewGameEvent>()
+            .add_systems(Startup, setup_new_game_screen)
+            .add_systems(Update, (new_game_buttons, update_labels.run_if(resource_changed::<Players>)).chain());
+    }
+}
@@ -3,9 +3,11 @@ use bevy_mod_picking::picking_core::Pickable;
 use core::f32::consts::PI;
 use std::collections::HashSet;
 
-use crate::{square::{CastleEvent, ConsumeEvent, MoveEvent}, LoadingData};
+use crate::{square::{CastleEvent, ConsumeEvent, CurrentPosition, MoveEvent}, LoadingData};
+use crate::position::Position;
+use crate::zobrist::{piece_hash, PositionHash};
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum PieceColour {
     White,
     Black,
@@ -18,9 +20,17 @@ impl PieceColour {
             Self::Black => Self::White,
         }
     }
+
+    /// "White" or "Black", for showing to players.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::White => "White",
+            Self::Black => "Black",
+        }
+    }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum PieceType {
     King,
     Queen,
@@ -60,6 +70,10 @@ pub fn is_square_defended(pos: (u8, u8), colour: PieceColour, pieces: &Vec<Piece
                 // a piece cannot defend itself
                 continue;
             }
+            if !piece.could_capture_on(pos) {
+                // saves working out every move of every piece, which is slow
+                continue;
+            }
             let pieces_without_self = pieces.iter()
                 .filter_map(|p| if p != piece { Some(p.clone()) } else { None })
                 .collect();
@@ -161,7 +175,36 @@ pub fn is_colour_in_checkmate(colour: PieceColour, pieces: &Vec<Piece>) -> bool
     true
 }
 
+pub fn starting_pieces() -> Vec<Piece> {
+    let back_rank = [
+        PieceType::Rook, PieceType::Knight, PieceType::Bishop, PieceType::King,
+        PieceType::Queen, PieceType::Bishop, PieceType::Knight, PieceType::Rook,
+    ];
+    let mut pieces = Vec::new();
+    for (colour, back_y, pawn_y) in [(PieceColour::White, 0, 1), (PieceColour::Black, 7, 6)] {
+        for (x, piece_type) in back_rank.into_iter().enumerate() {
+            pieces.push(Piece::new(colour, piece_type, x as u8, back_y));
+        }
+        for x in 0..8 {
+            pieces.push(Piece::new(colour, PieceType::Pawn, x, pawn_y));
+        }
+    }
+    pieces
+}
+
 impl Piece {
+    pub fn new(colour: PieceColour, piece_type: PieceType, x: u8, y: u8) -> Self {
+        Self {
+            colour, piece_type, x, y,
+            transform: Transform::from_translation(Vec3::new(x as f32, 0.0, y as f32)),
+            offset: Vec3::ZERO,
+            squares_occupied: HashSet::from([(0, 0)]),
+            has_moved: false,
+            just_moved: false,
+            can_en_passant: false,
+        }
+    }
+
     pub fn occupies_square(&self, square: (u8, u8)) -> bool {
         for &(dx, dy) in &self.squares_occupied {
             let x = self.x.checked_add_signed(dx).expect("x of piece < 0");
@@ -173,6 +216,30 @@ impl Piece {
         false
     }
 
+    /// Cheap check for whether any of this piece's capture patterns could land on `pos`,
+    /// ignoring anything in the way. If this is false the piece can't capture there.
+    pub fn could_capture_on(&self, pos: (u8, u8)) -> bool {
+        for &(dx, dy) in &self.squares_occupied {
+            let move_dx = pos.0 as i8 - (self.x as i8 + dx);
+            let move_dy = pos.1 as i8 - (self.y as i8 + dy);
+            let reachable = match self.piece_type {
+                PieceType::King => move_dx.abs().max(move_dy.abs()) == 1 || (move_dx.abs() == 2 && move_dy == 0),
+                PieceType::Queen => move_dx == 0 || move_dy == 0 || move_dx.abs() == move_dy.abs(),
+                PieceType::Bishop => move_dx.abs() == move_dy.abs(),
+                PieceType::Knight => (move_dx.abs() == 1 && move_dy.abs() == 2) || (move_dx.abs() == 2 && move_dy.abs() == 1),
+                PieceType::Rook => move_dx == 0 || move_dy == 0,
+                PieceType::Pawn => move_dx.abs() == 1 && move_dy == match self.colour {
+                    PieceColour::White => 1,
+                    PieceColour::Black => -1,
+                },
+            };
+            if reachable {
+                return true;
+            }
+        }
+        false
+    }
+
     pub fn occupies_row(&self, row: u8) -> bool {
         for &(_dx, dy) in &self.squares_occupied {
             let y = self.y.checked_add_signed(dy).expect("y of piece < 0");
@@ -311,6 +378,7 @@ impl Piece {
                 ];
                 'move_dir_loop: for (move_dx, move_dy) in move_directions {
                     for move_magnitude in 1..=7 {
+                        let mut captures = false;
                         for &(piece_dx, piece_dy) in &self.squares_occupied {
                             // (new_x, new_y) is the space occupied by this portion of the piece
                             // after the move
@@ -329,14 +397,18 @@ impl Piece {
                                 if piece.occupies_square((new_x, new_y)) {
                                     // if the piece is of the opposite colour, that's fine, we can capture it,
                                     // but we can't move past it
-                                    if piece.colour != self.colour {
-                                        moves.push((move_dx * move_magnitude, move_dy * move_magnitude));
+                                    if piece.colour == self.colour {
+                                        continue 'move_dir_loop;
                                     }
-                                    continue 'move_dir_loop;
+                                    captures = true;
                                 }
                             }
                         }
                         moves.push((move_dx * move_magnitude, move_dy * move_magnitude));
+                        if captures {
+                            // only stop here once every square of the piece is known to fit
+                            continue 'move_dir_loop;
+                        }
                     }
                 }
             },
@@ -347,6 +419,7 @@ impl Piece {
                 ];
                 'move_dir_loop: for (move_dx, move_dy) in move_directions {
                     for move_magnitude in 1..=7 {
+                        let mut captures = false;
                         for &(piece_dx, piece_dy) in &self.squares_occupied {
                             // (new_x, new_y) is the space occupied by this portion of the piece
                             // after the move
@@ -365,14 +438,18 @@ impl Piece {
                                 if piece.occupies_square((new_x, new_y)) {
                                     // if the piece is of the opposite colour, that's fine, we can capture it,
                                     // but we can't move past it
-                                    if piece.colour != self.colour {
-                                        moves.push((move_dx * move_magnitude, move_dy * move_magnitude));
+                                    if piece.colour == self.colour {
+                                        continue 'move_dir_loop;
                                     }
-                                    continue 'move_dir_loop;
+                                    captures = true;
                                 }
                             }
                         }
                         moves.push((move_dx * move_magnitude, move_dy * move_magnitude));
+                        if captures {
+                            // only stop here once every square of the piece is known to fit
+                            continue 'move_dir_loop;
+                        }
                     }
                 }
             },
@@ -414,6 +491,7 @@ impl Piece {
                 ];
                 'move_dir_loop: for (move_dx, move_dy) in move_directions {
                     for move_magnitude in 1..=7 {
+                        let mut captures = false;
                         for &(piece_dx, piece_dy) in &self.squares_occupied {
                             // (new_x, new_y) is the space occupied by this portion of the piece
                             // after the move
@@ -432,14 +510,18 @@ impl Piece {
                                 if piece.occupies_square((new_x, new_y)) {
                                     // if the piece is of the opposite colour, that's fine, we can capture it,
                                     // but we can't move past it
-                                    if piece.colour != self.colour {
-                                        moves.push((move_dx * move_magnitude, move_dy * move_magnitude));
+                                    if piece.colour == self.colour {
+                                        continue 'move_dir_loop;
                                     }
-                                    continue 'move_dir_loop;
+                                    captures = true;
                                 }
                             }
                         }
                         moves.push((move_dx * move_magnitude,  move_dy * move_magnitude));
+                        if captures {
+                            // only stop here once every square of the piece is known to fit
+                            continue 'move_dir_loop;
+                        }
                     }
                 }
             },
@@ -562,10 +644,12 @@ impl Piece {
 
     /// Like is_move_valid, but also checks if the king would be in check afterwards
     pub fn is_move_playable(&self, new_position: (u8, u8), pieces: &Vec<Piece>) -> bool {
-        if !self.is_move_valid(new_position, pieces) {
-            return false;
-        }
+        self.is_move_valid(new_position, pieces) && self.is_move_safe(new_position, pieces)
+    }
 
+    /// Checks that moving to new_position doesn't leave the king in check, assuming the move
+    /// is otherwise valid
+    pub fn is_move_safe(&self, new_position: (u8, u8), pieces: &[Piece]) -> bool {
         let pieces_after_move = pieces.iter().filter_map(|piece| {
             if piece == self {
                 let mut new_piece = piece.clone();
@@ -581,11 +665,7 @@ impl Piece {
             }
         }).collect();
 
-        if is_colour_in_check(self.colour, &pieces_after_move) {
-            return false;
-        }
-
-        true
+        !is_colour_in_check(self.colour, &pieces_after_move)
     }
 
     pub fn update_transform(&mut self) {
@@ -725,6 +805,7 @@ impl Piece {
 fn promote_pawns(
     mut q_parent: Query<(&mut Piece, &Children)>,
     mut q_child: Query<&mut Handle<Mesh>>,
+    mut position_hash: ResMut<PositionHash>,
     asset_server: Res<AssetServer>,
 ) {
     for (mut piece, children) in q_parent.iter_mut() {
@@ -733,7 +814,9 @@ fn promote_pawns(
                 (piece.colour == PieceColour::White && piece.occupies_row(7))
                 || (piece.colour == PieceColour::Black && piece.occupies_row(0))) {
                 // too lazy to do a picker, so just promote to queen
+                position_hash.0 ^= piece_hash(&piece);
                 piece.piece_type = PieceType::Queen;
+                position_hash.0 ^= piece_hash(&piece);
 
                 let mut mesh = q_child.get_mut(child).unwrap();
                 let queen_handle: Handle<Mesh> = asset_server.load("Chess.glb#Mesh3/Primitive1");
@@ -746,14 +829,17 @@ fn promote_pawns(
 fn castle(
     mut event_reader: EventReader<CastleEvent>,
     mut query: Query<&mut Piece>,
+    mut position_hash: ResMut<PositionHash>,
 ) {
     for castle_event in event_reader.read() {
         match castle_event {
             CastleEvent::Queenside(colour) => {
                 for mut piece in query.iter_mut() {
                     if piece.colour == *colour && piece.piece_type == PieceType::Rook && piece.x == 0 {
+                        position_hash.0 ^= piece_hash(&piece);
                         piece.x = 2;
                         piece.has_moved = true;
+                        position_hash.0 ^= piece_hash(&piece);
                         piece.transform.translation = Vec3::new(piece.x as f32, 0., piece.y as f32) + piece.offset;
                     }
                 }
@@ -761,8 +847,10 @@ fn castle(
             CastleEvent::Kingside(colour) => {
                 for mut piece in query.iter_mut() {
                     if piece.colour == *colour && piece.piece_type == PieceType::Rook && piece.x == 7 {
+                        position_hash.0 ^= piece_hash(&piece);
                         piece.x = 4;
                         piece.has_moved = true;
+                        position_hash.0 ^= piece_hash(&piece);
                         piece.transform.translation = Vec3::new(piece.x as f32, 0., piece.y as f32) + piece.offset;
                     }
                 }
@@ -826,27 +914,17 @@ fn spawn_piece(
     commands: &mut Commands,
     material: Handle<StandardMaterial>,
     mesh: Handle<Mesh>,
-    piece_type: PieceType,
-    colour: PieceColour,
-    x: u8,
-    y: u8,
+    piece: Piece,
 ) {
     let piece_transform = Transform::from_scale(Vec3::new(0.3, 0.3, 0.3));
 
     commands.spawn((
         PbrBundle {
-            transform: Transform::from_translation(Vec3::new(x as f32, 0.0, y as f32)),
+            // grown pieces start out grown
+            transform: piece.transform,
             ..Default::default()
         },
-        Piece {
-            colour, piece_type, x, y,
-            transform: Transform::from_translation(Vec3::new(x as f32, 0.0, y as f32)),
-            offset: Vec3::ZERO,
-            squares_occupied: HashSet::from([(0, 0)]),
-            has_moved: false,
-            just_moved: false,
-            can_en_passant: false,
-        },
+        piece,
         Pickable::IGNORE,
     )).with_children(|parent| {
         parent.spawn((
@@ -861,93 +939,140 @@ fn spawn_piece(
     });
 }
 
+fn piece_mesh(asset_server: &AssetServer, piece_type: PieceType) -> Handle<Mesh> {
+    match piece_type {
+        PieceType::King => asset_server.load("Chess.glb#Mesh2/Primitive1"),
+        PieceType::Queen => asset_server.load("Chess.glb#Mesh3/Primitive1"),
+        PieceType::Pawn => asset_server.load("Chess.glb#Mesh4/Primitive0"),
+        PieceType::Knight => asset_server.load("Chess.glb#Mesh5/Primitive0"),
+        PieceType::Bishop => asset_server.load("Chess.glb#Mesh6/Primitive0"),
+        PieceType::Rook => asset_server.load("Chess.glb#Mesh7/Primitive0"),
+    }
+}
+
+fn piece_material(asset_server: &AssetServer, colour: PieceColour) -> Handle<StandardMaterial> {
+    match colour {
+        PieceColour::White => asset_server.load("Chess.glb#Material6"),
+        PieceColour::Black => asset_server.load("Chess.glb#Material9"),
+    }
+}
+
+/// Spawns the pieces of a position, e.g. one picked from a game's history. Whatever pieces
+/// were on the board before need despawning first.
+pub fn spawn_pieces(commands: &mut Commands, asset_server: &AssetServer, pieces: &[Piece]) {
+    for piece in pieces {
+        let material = piece_material(asset_server, piece.colour);
+        let mesh = piece_mesh(asset_server, piece.piece_type);
+        spawn_piece(commands, material, mesh, piece.clone());
+    }
+}
+
 pub fn create_pieces(
     mut commands: Commands,
     mut loading_data: ResMut<LoadingData>,
+    mut position_hash: ResMut<PositionHash>,
+    mut current_position: ResMut<CurrentPosition>,
     asset_server: Res<AssetServer>,
 ) {
-    let king_handle: Handle<Mesh> = asset_server.load("Chess.glb#Mesh2/Primitive1");
-    let queen_handle: Handle<Mesh> = asset_server.load("Chess.glb#Mesh3/Primitive1");
-    let pawn_handle: Handle<Mesh> = asset_server.load("Chess.glb#Mesh4/Primitive0");
-    let knight_handle: Handle<Mesh> = asset_server.load("Chess.glb#Mesh5/Primitive0");
-    let bishop_handle: Handle<Mesh> = asset_server.load("Chess.glb#Mesh6/Primitive0");
-    let rook_handle: Handle<Mesh> = asset_server.load("Chess.glb#Mesh7/Primitive0");
-    let chrome_handle: Handle<StandardMaterial> = asset_server.load("Chess.glb#Material6");
-    let brass_handle: Handle<StandardMaterial> = asset_server.load("Chess.glb#Material9");
-
-    loading_data.loading_assets.push(king_handle.clone().into());
-    loading_data.loading_assets.push(queen_handle.clone().into());
-    loading_data.loading_assets.push(pawn_handle.clone().into());
-    loading_data.loading_assets.push(knight_handle.clone().into());
-    loading_data.loading_assets.push(bishop_handle.clone().into());
-    loading_data.loading_assets.push(rook_handle.clone().into());
-    loading_data.loading_assets.push(chrome_handle.clone().into());
-    loading_data.loading_assets.push(brass_handle.clone().into());
-
-    // white king
-    spawn_piece(&mut commands, chrome_handle.clone(), king_handle.clone(), PieceType::King, PieceColour::White, 3, 0);
-
-    // black king
-    spawn_piece(&mut commands, brass_handle.clone(), king_handle.clone(), PieceType::King, PieceColour::Black, 3, 7);
-
-    // white queen
-    spawn_piece(&mut commands, chrome_handle.clone(), queen_handle.clone(), PieceType::Queen, PieceColour::White, 4, 0);
-
-    // black queen
-    spawn_piece(&mut commands, brass_handle.clone(), queen_handle.clone(), PieceType::Queen, PieceColour::Black, 4, 7);
-
-    // white rooks
-    spawn_piece(&mut commands, chrome_handle.clone(), rook_handle.clone(), PieceType::Rook, PieceColour::White, 0, 0);
-    spawn_piece(&mut commands, chrome_handle.clone(), rook_handle.clone(), PieceType::Rook, PieceColour::White, 7, 0);
-
-    // black rooks
-    spawn_piece(&mut commands, brass_handle.clone(), rook_handle.clone(), PieceType::Rook, PieceColour::Black, 0, 7);
-    spawn_piece(&mut commands, brass_handle.clone(), rook_handle.clone(), PieceType::Rook, PieceColour::Black, 7, 7);
-
-    // white knights
-    spawn_piece(&mut commands, chrome_handle.clone(), knight_handle.clone(), PieceType::Knight, PieceColour::White, 1, 0);
-    spawn_piece(&mut commands, chrome_handle.clone(), knight_handle.clone(), PieceType::Knight, PieceColour::White, 6, 0);
-
-    // black knights
-    spawn_piece(&mut commands, brass_handle.clone(), knight_handle.clone(), PieceType::Knight, PieceColour::Black, 1, 7);
-    spawn_piece(&mut commands, brass_handle.clone(), knight_handle.clone(), PieceType::Knight, PieceColour::Black, 6, 7);
-
-    // white bishops
-    spawn_piece(&mut commands, chrome_handle.clone(), bishop_handle.clone(), PieceType::Bishop, PieceColour::White, 2, 0);
-    spawn_piece(&mut commands, chrome_handle.clone(), bishop_handle.clone(), PieceType::Bishop, PieceColour::White, 5, 0);
-
-    // black bishops
-    spawn_piece(&mut commands, brass_handle.clone(), bishop_handle.clone(), PieceType::Bishop, PieceColour::Black, 2, 7);
-    spawn_piece(&mut commands, brass_handle.clone(), bishop_handle.clone(), PieceType::Bishop, PieceColour::Black, 5, 7);
-
-    // white pawns
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 0, 1);
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 1, 1);
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 2, 1);
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 3, 1);
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 4, 1);
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 5, 1);
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 6, 1);
-    spawn_piece(&mut commands, chrome_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::White, 7, 1);
-
-    // black pawns
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 0, 6);
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 1, 6);
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 2, 6);
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 3, 6);
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 4, 6);
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 5, 6);
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 6, 6);
-    spawn_piece(&mut commands, brass_handle.clone(), pawn_handle.clone(), PieceType::Pawn, PieceColour::Black, 7, 6);
+    for piece_type in [PieceType::King, PieceType::Queen, PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook] {
+        loading_data.loading_assets.push(piece_mesh(&asset_server, piece_type).into());
+    }
+    for colour in [PieceColour::White, PieceColour::Black] {
+        loading_data.loading_assets.push(piece_material(&asset_server, colour).into());
+    }
 
+    let pieces = starting_pieces();
+    current_position.0 = Position::from_pieces(pieces.clone(), PieceColour::White);
+    position_hash.0 = current_position.0.hash;
+    spawn_pieces(&mut commands, &asset_server, &pieces);
 }
 
 pub struct PiecesPlugin;
 impl Plugin for PiecesPlugin {
     fn build(&self, app: &mut App) {
         app
+            .insert_resource(PositionHash::default())
             .add_systems(Startup, create_pieces)
             .add_systems(Update, (move_pieces, transform_pieces, disappear_pieces, promote_pawns,
                     castle, disable_en_passant));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{parse_piece, Position};
+
+    fn sorted_moves(spec: &str, others: &[&str]) -> Vec<(i8, i8)> {
+        let piece = parse_piece(spec).unwrap();
+        let others = others.iter().map(|spec| parse_piece(spec).unwrap()).collect();
+        let mut moves = piece.valid_moves(&others);
+        moves.sort_unstable();
+        moves
+    }
+
+    #[test]
+    fn grown_rook_blocked_by_own_piece_under_footprint() {
+        // going up, h4 has a black pawn to take but g4 has a white one, so the rook stops
+        // short; going along, it takes the knight on c1 and stops there
+        let moves = sorted_moves("Rh1+g1", &["Pg4", "ph4", "nc1"]);
+        assert_eq!(moves, vec![(0, 1), (0, 2), (1, 0), (2, 0), (3, 0), (4, 0)]);
+    }
+
+    #[test]
+    fn grown_bishop_blocked_by_own_piece_under_footprint() {
+        // towards a3 the white pawn on b3 is under the bishop alongside the black one on a3;
+        // towards h6 it takes the knight on f4
+        let moves = sorted_moves("Bc1+d1", &["Pb3", "pa3", "nf4"]);
+        assert_eq!(moves, vec![(-3, 3), (-2, 2), (-1, 1), (1, 1)]);
+    }
+
+    // is_square_defended as it was before could_capture_on, trying every piece's captures
+    fn defended_without_filter(pos: (u8, u8), colour: PieceColour, pieces: &[Piece]) -> bool {
+        pieces.iter().filter(|piece| piece.colour == colour && !piece.occupies_square(pos)).any(|piece| {
+            let pieces_without_self: Vec<Piece> = pieces.iter().filter(|p| *p != piece).cloned().collect();
+            piece.valid_captures(&pieces_without_self).into_iter().any(|(move_dx, move_dy)| {
+                piece.squares_occupied.iter().any(|&(piece_dx, piece_dy)| {
+                    let move_x = piece.x.checked_add_signed(piece_dx + move_dx);
+                    let move_y = piece.y.checked_add_signed(piece_dy + move_dy);
+                    (move_x, move_y) == (Some(pos.0), Some(pos.1))
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn capture_filter_agrees_with_every_capture() {
+        let mut grown_positions = 0;
+        let mut seed = 12345_u64;
+        for _ in 0..8 {
+            let mut position = Position::default();
+            for _ in 0..40 {
+                // captures first, so plenty of pieces grow
+                let moves = position.legal_moves();
+                let captures: Vec<_> = moves.iter().copied().filter(|&mv| position.is_capture(mv)).collect();
+                let choices = if captures.is_empty() { &moves } else { &captures };
+                if choices.is_empty() {
+                    break;
+                }
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                position.make_move(choices[(seed >> 33) as usize % choices.len()]);
+                if position.pieces.iter().any(|piece| piece.squares_occupied.len() > 1) {
+                    grown_positions += 1;
+                }
+                for colour in [PieceColour::White, PieceColour::Black] {
+                    for x in 0..8 {
+                        for y in 0..8 {
+                            assert_eq!(
+                                is_square_defended((x, y), colour, &position.pieces),
+                                defended_without_filter((x, y), colour, &position.pieces),
+                                "square {:?} for {colour:?} in {}", (x, y), position.setup(),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        assert!(grown_positions > 100, "only {grown_positions} positions had grown pieces");
+    }
+}
@@ -0,0 +1,322 @@
+use bevy::prelude::*;
+use std::fmt;
+
+use crate::piece::{is_colour_in_check, starting_pieces, Piece, PieceColour, PieceType};
+use crate::zobrist::{castling_hash, en_passant_file_hash, en_passant_hash, piece_hash, position_hash, turn_hash};
+
+/// A move of the piece anchored on `from` so that its anchor ends up on `to`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Move {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+}
+
+// x = 0 is the h-file (the white king starts on x = 3, which is e1)
+pub fn square_name(square: (u8, u8)) -> String {
+    format!("{}{}", (b'h' - square.0) as char, square.1 + 1)
+}
+
+pub fn parse_square(name: &str) -> Option<(u8, u8)> {
+    let bytes = name.as_bytes();
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+        return None;
+    }
+    Some((b'h' - bytes[0], bytes[1] - b'1'))
+}
+
+// the letter for a piece in a setup, white's in capitals
+fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::King => 'K',
+        PieceType::Queen => 'Q',
+        PieceType::Rook => 'R',
+        PieceType::Bishop => 'B',
+        PieceType::Knight => 'N',
+        PieceType::Pawn => 'P',
+    }
+}
+
+/// Reads a piece the way setups write them: `Kc3` for a white king on c3 (black's letters are
+/// lower case), and a grown piece lists the rest of its squares after its own, `Kc3+c4`.
+/// Pieces read this way have moved, so they can't castle.
+pub fn parse_piece(spec: &str) -> Result<Piece, String> {
+    let error = || format!("can't read piece {spec}");
+    let mut chars = spec.chars();
+    let letter = chars.next().ok_or_else(error)?;
+    let colour = if letter.is_ascii_uppercase() { PieceColour::White } else { PieceColour::Black };
+    let piece_type = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn]
+        .into_iter()
+        .find(|&piece_type| piece_letter(piece_type) == letter.to_ascii_uppercase())
+        .ok_or_else(error)?;
+    let mut squares = chars.as_str().split('+');
+    let (x, y) = squares.next().and_then(parse_square).ok_or_else(error)?;
+    let mut piece = Piece::new(colour, piece_type, x, y);
+    piece.has_moved = true;
+    for square in squares {
+        let (square_x, square_y) = parse_square(square).ok_or_else(error)?;
+        piece.squares_occupied.insert((square_x as i8 - x as i8, square_y as i8 - y as i8));
+    }
+    piece.update_transform();
+    piece.transform.translation = Vec3::new(x as f32, 0., y as f32) + piece.offset;
+    Ok(piece)
+}
+
+/// A piece the way `parse_piece` reads it.
+pub fn piece_spec(piece: &Piece) -> String {
+    let letter = piece_letter(piece.piece_type);
+    let mut spec = match piece.colour {
+        PieceColour::White => letter.to_string(),
+        PieceColour::Black => letter.to_ascii_lowercase().to_string(),
+    };
+    spec += &square_name((piece.x, piece.y));
+    let mut others: Vec<(i8, i8)> = piece.squares_occupied.iter().copied().filter(|&square| square != (0, 0)).collect();
+    others.sort_unstable();
+    for (dx, dy) in others {
+        let square = (piece.x.checked_add_signed(dx).unwrap(), piece.y.checked_add_signed(dy).unwrap());
+        spec += &format!("+{}", square_name(square));
+    }
+    spec
+}
+
+impl Move {
+    /// Parses coordinate notation such as `e2e4`.
+    pub fn from_notation(notation: &str) -> Option<Self> {
+        if notation.len() != 4 || !notation.is_ascii() {
+            return None;
+        }
+        Some(Self {
+            from: parse_square(&notation[..2])?,
+            to: parse_square(&notation[2..])?,
+        })
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", square_name(self.from), square_name(self.to))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Checkmate(PieceColour),
+    Stalemate,
+}
+
+/// The board without any of the ECS around it, for searching and for checking moves that
+/// didn't come from a click. Moves are applied exactly the way `select_square` and the piece
+/// systems apply them in game.
+#[derive(Clone)]
+pub struct Position {
+    pub pieces: Vec<Piece>,
+    pub turn: PieceColour,
+    pub hash: u64,
+    // hashes of every earlier position in the game, oldest first
+    pub history: Vec<u64>,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::from_pieces(starting_pieces(), PieceColour::White)
+    }
+}
+
+impl Position {
+    pub fn from_pieces(pieces: Vec<Piece>, turn: PieceColour) -> Self {
+        let hash = position_hash(&pieces, turn);
+        Self { pieces, turn, hash, history: Vec::new() }
+    }
+
+    /// Reads a position written as its pieces then the side to move, e.g. `Kc3 Rh2 ka1 w`
+    /// (see `parse_piece`).
+    pub fn from_setup(setup: &str) -> Result<Self, String> {
+        let words: Vec<&str> = setup.split_whitespace().collect();
+        let [pieces @ .., turn] = &words[..] else {
+            return Err("no position given".to_string());
+        };
+        let turn = match *turn {
+            "w" => PieceColour::White,
+            "b" => PieceColour::Black,
+            _ => return Err(format!("expected w or b for the side to move, not {turn}")),
+        };
+        let pieces = pieces.iter().map(|spec| parse_piece(spec)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_pieces(pieces, turn))
+    }
+
+    /// The position the way `from_setup` reads it.
+    pub fn setup(&self) -> String {
+        let mut words: Vec<String> = self.pieces.iter().map(piece_spec).collect();
+        words.push(match self.turn {
+            PieceColour::White => "w".to_string(),
+            PieceColour::Black => "b".to_string(),
+        });
+        words.join(" ")
+    }
+
+    pub fn piece_at(&self, square: (u8, u8)) -> Option<&Piece> {
+        self.pieces.iter().find(|piece| piece.occupies_square(square))
+    }
+
+    fn anchor_index(&self, square: (u8, u8)) -> Option<usize> {
+        self.pieces.iter().position(|piece| (piece.x, piece.y) == square)
+    }
+
+    /// Indices of the pieces a move would capture: anything of the other colour under the
+    /// piece's footprint at its destination, plus pawns taken en passant.
+    pub fn captured_by(&self, mv: Move) -> Vec<usize> {
+        let mut captured = Vec::new();
+        let Some(piece) = self.pieces.iter().find(|piece| (piece.x, piece.y) == mv.from) else {
+            return captured;
+        };
+        for &(dx, dy) in &piece.squares_occupied {
+            let square_x = mv.to.0.checked_add_signed(dx).expect("x < 0");
+            let square_y = mv.to.1.checked_add_signed(dy).expect("y < 0");
+            for (i, other_piece) in self.pieces.iter().enumerate() {
+                if other_piece.colour != piece.colour.opposite() || captured.contains(&i) {
+                    continue;
+                }
+                let behind = match piece.colour {
+                    PieceColour::White => square_y.checked_sub(1),
+                    PieceColour::Black => square_y.checked_add(1),
+                };
+                if other_piece.occupies_square((square_x, square_y))
+                    || (other_piece.can_en_passant && behind.is_some_and(|y| other_piece.occupies_square((square_x, y))))
+                {
+                    captured.push(i);
+                }
+            }
+        }
+        captured
+    }
+
+    pub fn is_capture(&self, mv: Move) -> bool {
+        !self.captured_by(mv).is_empty()
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for piece in &self.pieces {
+            if piece.colour != self.turn {
+                continue;
+            }
+            let pieces_without_self = self.pieces.iter()
+                .filter_map(|p| if p != piece { Some(p.clone()) } else { None })
+                .collect();
+            // these are the same lists is_move_valid checks against, so only pawn captures (which
+            // need something to capture) have to go through it again
+            for (dx, dy) in piece.valid_moves(&pieces_without_self) {
+                let to = (piece.x.checked_add_signed(dx).unwrap(), piece.y.checked_add_signed(dy).unwrap());
+                if piece.is_move_safe(to, &self.pieces) {
+                    moves.push(Move { from: (piece.x, piece.y), to });
+                }
+            }
+            if piece.piece_type == PieceType::Pawn {
+                for (dx, dy) in piece.valid_captures(&pieces_without_self) {
+                    let to = (piece.x.checked_add_signed(dx).unwrap(), piece.y.checked_add_signed(dy).unwrap());
+                    if piece.is_move_playable(to, &self.pieces) {
+                        moves.push(Move { from: (piece.x, piece.y), to });
+                    }
+                }
+            }
+        }
+        moves
+    }
+
+    pub fn is_legal(&self, mv: Move) -> bool {
+        match self.pieces.iter().find(|piece| (piece.x, piece.y) == mv.from) {
+            Some(piece) => piece.colour == self.turn && piece.is_move_playable(mv.to, &self.pieces),
+            None => false,
+        }
+    }
+
+    pub fn in_check(&self) -> bool {
+        is_colour_in_check(self.turn, &self.pieces)
+    }
+
+    /// The result of the game if the side to move has no legal moves.
+    pub fn outcome(&self) -> Option<Outcome> {
+        if !self.legal_moves().is_empty() {
+            None
+        } else if self.in_check() {
+            Some(Outcome::Checkmate(self.turn))
+        } else {
+            Some(Outcome::Stalemate)
+        }
+    }
+
+    pub fn repetitions(&self) -> usize {
+        self.history.iter().filter(|&&hash| hash == self.hash).count()
+    }
+
+    /// Plays a move, which must be legal.
+    pub fn make_move(&mut self, mv: Move) {
+        let index = self.anchor_index(mv.from).expect("no piece on the square moved from");
+        let captured = self.captured_by(mv);
+        self.history.push(self.hash);
+
+        let mut piece = self.pieces[index].clone();
+        self.hash ^= piece_hash(&piece) ^ castling_hash(&self.pieces) ^ en_passant_hash(&self.pieces);
+        for &i in &captured {
+            self.hash ^= piece_hash(&self.pieces[i]);
+        }
+
+        if !captured.is_empty() {
+            piece.consume_piece(mv.to.0, mv.to.1);
+        }
+        piece.transform.translation = Vec3::new(mv.to.0 as f32, 0., mv.to.1 as f32) + piece.offset;
+
+        let castle_rook = if piece.piece_type == PieceType::King && piece.x.abs_diff(mv.to.0) == 2 {
+            if mv.to.0 < piece.x { Some((0, 2)) } else { Some((7, 4)) }
+        } else {
+            None
+        };
+        if piece.piece_type == PieceType::Pawn && piece.y.abs_diff(mv.to.1) == 2 {
+            piece.can_en_passant = true;
+        }
+        piece.x = mv.to.0;
+        piece.y = mv.to.1;
+        piece.has_moved = true;
+        if piece.piece_type == PieceType::Pawn && (
+            (piece.colour == PieceColour::White && piece.occupies_row(7))
+            || (piece.colour == PieceColour::Black && piece.occupies_row(0))) {
+            piece.piece_type = PieceType::Queen;
+        }
+        self.hash ^= piece_hash(&piece);
+        if piece.can_en_passant {
+            self.hash ^= en_passant_file_hash(piece.x);
+        }
+        self.pieces[index] = piece;
+
+        // the moved piece is the only one that keeps en passant (see disable_en_passant)
+        for (i, other_piece) in self.pieces.iter_mut().enumerate() {
+            if i != index {
+                other_piece.can_en_passant = false;
+            }
+            other_piece.just_moved = false;
+        }
+
+        if let Some((rook_x, new_rook_x)) = castle_rook {
+            let colour = self.turn;
+            for rook in self.pieces.iter_mut() {
+                if rook.colour == colour && rook.piece_type == PieceType::Rook && rook.x == rook_x {
+                    self.hash ^= piece_hash(rook);
+                    rook.x = new_rook_x;
+                    rook.has_moved = true;
+                    rook.transform.translation = Vec3::new(rook.x as f32, 0., rook.y as f32) + rook.offset;
+                    self.hash ^= piece_hash(rook);
+                }
+            }
+        }
+
+        let mut captured = captured;
+        captured.sort_unstable();
+        for i in captured.into_iter().rev() {
+            self.pieces.remove(i);
+        }
+
+        self.hash ^= castling_hash(&self.pieces) ^ turn_hash(self.turn);
+        self.turn = self.turn.opposite();
+        self.hash ^= turn_hash(self.turn);
+        debug_assert_eq!(self.hash, position_hash(&self.pieces, self.turn));
+    }
+}
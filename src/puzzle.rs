@@ -0,0 +1,238 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::position::{Move, Outcome, Position};
+use crate::record::GameRecord;
+use crate::search::{growth, SearchConfig, SearchLimits, Searcher, MATE_SCORE};
+
+const STANDARD_PUZZLES: &str = include_str!("../assets/puzzles.txt");
+
+// what a move has to be worth, for the side playing it, to count as winning: three pawns
+const WINNING: i32 = 300;
+
+/// A position with a winning line to find. The player moves first, with the side to move in
+/// the setup, and the moves after that alternate between the player's and the replies.
+/// Written as game records with a `Setup` tag, the puzzle's name in `Event` and how hard it
+/// is in `Rating`:
+///
+/// ```text
+/// [Event "Family fork"]
+/// [Rating "1000"]
+/// [Setup "Kg1 Nd5 Pf2 Pg2 Ph2 ke8 ra8 pc7 pa7 ph7 w"]
+///
+/// 1. d5c7 e8f7 2. c7a8 *
+/// ```
+#[derive(Clone)]
+pub struct Puzzle {
+    pub name: String,
+    pub rating: u32,
+    pub position: Position,
+    pub solution: Vec<Move>,
+}
+
+impl Puzzle {
+    /// The puzzles in assets/puzzles.txt, built into the game.
+    pub fn standard() -> &'static [Puzzle] {
+        static PUZZLES: OnceLock<Vec<Puzzle>> = OnceLock::new();
+        PUZZLES.get_or_init(|| Puzzle::parse_all(STANDARD_PUZZLES).expect("bad standard puzzles"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Vec<Self>, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("can't read {}: {err}", path.display()))?;
+        Self::parse_all(&text)
+    }
+
+    pub fn parse_all(text: &str) -> Result<Vec<Self>, String> {
+        GameRecord::parse_all(text)?.iter().map(Self::from_record).collect()
+    }
+
+    /// Checks the solution is legal and has a move for the player in it.
+    pub fn from_record(record: &GameRecord) -> Result<Self, String> {
+        let name = record.tag("Event").unwrap_or("?").to_string();
+        let error = |message: String| format!("puzzle {name}: {message}");
+        if record.tag("Setup").is_none() {
+            return Err(error("no Setup tag".to_string()));
+        }
+        let rating = match record.tag("Rating") {
+            Some(rating) => rating.parse().map_err(|_| error(format!("bad rating {rating}")))?,
+            None => 0,
+        };
+        record.position().map_err(error)?;
+        // it ends on the player's move
+        if record.moves.len().is_multiple_of(2) {
+            return Err(error("the solution should end with a move for the player".to_string()));
+        }
+        Ok(Self {
+            name: name.clone(),
+            rating,
+            position: record.start_position().map_err(error)?,
+            solution: record.moves.clone(),
+        })
+    }
+
+    pub fn to_record(&self) -> GameRecord {
+        let mut record = GameRecord { moves: self.solution.clone(), ..Default::default() };
+        record.set_tag("Event", self.name.clone());
+        record.set_tag("Rating", self.rating.to_string());
+        record.set_tag("Setup", self.position.setup());
+        record
+    }
+
+    /// Whether `mv`, played `ply` plies into the solution from `position`, is right: the
+    /// solution's move, or any move that checkmates.
+    pub fn is_solution_move(&self, ply: usize, position: &Position, mv: Move) -> bool {
+        if self.solution.get(ply) == Some(&mv) {
+            return true;
+        }
+        if !position.is_legal(mv) {
+            return false;
+        }
+        let mut after = position.clone();
+        after.make_move(mv);
+        matches!(after.outcome(), Some(Outcome::Checkmate(_)))
+    }
+}
+
+/// How hard `find_puzzle` looks.
+#[derive(Clone, Copy, Debug)]
+pub struct FinderSettings {
+    // every move in the solution has to be the only winning one at this depth
+    pub depth: u8,
+    // stops any one search after this many nodes, since some positions take far longer than
+    // others
+    pub nodes: Option<u64>,
+    // and this much better than the next best move, in centipawns
+    pub margin: i32,
+    // moves for the player, at most
+    pub max_moves: usize,
+}
+
+impl Default for FinderSettings {
+    fn default() -> Self {
+        Self { depth: 3, nodes: Some(10_000), margin: 300, max_moves: 3 }
+    }
+}
+
+impl FinderSettings {
+    fn limits(&self, depth: u8) -> SearchLimits {
+        SearchLimits { depth, nodes: self.nodes, time: None }
+    }
+}
+
+// every legal move with its score for the side playing it and the expected reply, best first
+fn score_moves(searcher: &mut Searcher, position: &Position, settings: &FinderSettings) -> Vec<(Move, i32, Option<Move>)> {
+    let mut scores: Vec<(Move, i32, Option<Move>)> = position.legal_moves().into_iter()
+        .map(|mv| {
+            let mut after = position.clone();
+            after.make_move(mv);
+            let result = searcher.search(&after, settings.limits(settings.depth.saturating_sub(1).max(1)));
+            let score = match after.outcome() {
+                Some(Outcome::Checkmate(_)) => MATE_SCORE,
+                Some(Outcome::Stalemate) => 0,
+                None => -result.score,
+            };
+            (mv, score, result.pv.first().copied())
+        })
+        .collect();
+    scores.sort_by_key(|&(_, score, _)| -score);
+    scores
+}
+
+// the move that wins when nothing else does, and the reply to it
+fn only_winning_move(searcher: &mut Searcher, position: &Position, settings: &FinderSettings) -> Option<(Move, Option<Move>)> {
+    let scores = score_moves(searcher, position, settings);
+    let &(mv, best, reply) = scores.first()?;
+    let second = scores.get(1).map_or(-MATE_SCORE, |&(_, score, _)| score);
+    (best >= WINNING && second < WINNING && best - second >= settings.margin).then_some((mv, reply))
+}
+
+/// Whether a move captures and makes the piece that plays it bigger, the tactic this variant
+/// has that chess doesn't.
+pub fn is_growth_capture(position: &Position, mv: Move) -> bool {
+    position.is_capture(mv) && position.piece_at(mv.from).is_some_and(|piece| growth(piece, mv.to) > 0)
+}
+
+/// Looks for a puzzle starting in `position`: a move that wins where nothing else does, then
+/// the engine's reply and the next only winning move, for up to `max_moves` moves. The
+/// position is written down as a setup and read back first, so the puzzle is checked in the
+/// position it will be played from, where nobody can castle. The puzzle's name is left for
+/// the caller.
+pub fn find_puzzle(position: &Position, settings: &FinderSettings) -> Option<Puzzle> {
+    let start = Position::from_setup(&position.setup()).ok()?;
+    let mut searcher = Searcher::new(SearchConfig::default());
+    let mut position = start.clone();
+    let mut solution = Vec::new();
+    while let Some((mv, reply)) = only_winning_move(&mut searcher, &position, settings) {
+        solution.push(mv);
+        position.make_move(mv);
+        let Some(reply) = reply.filter(|_| position.outcome().is_none() && solution.len() < settings.max_moves * 2 - 1) else {
+            break;
+        };
+        // the line goes on only if the player has another only winning move after the reply
+        let mut after_reply = position.clone();
+        after_reply.make_move(reply);
+        if only_winning_move(&mut searcher, &after_reply, settings).is_none() {
+            break;
+        }
+        solution.push(reply);
+        position = after_reply;
+    }
+    if solution.is_empty() {
+        return None;
+    }
+    let rating = rating(&mut searcher, &start, &solution);
+    Some(Puzzle { name: String::new(), rating, position: start, solution })
+}
+
+/// Finds puzzles in a game: the positions where the side to move has just been handed a win
+/// (it wasn't winning before the other side's move) and `find_puzzle` finds one way to take
+/// it. Each comes with the number of plies into the game it's from. Positions in the first
+/// `min_ply` plies are skipped.
+pub fn find_puzzles(record: &GameRecord, settings: &FinderSettings, min_ply: usize) -> Result<Vec<(usize, Puzzle)>, String> {
+    let mut searcher = Searcher::new(SearchConfig::default());
+    let mut position = record.start_position()?;
+    let mut puzzles = Vec::new();
+    // the score for the side to move before the last move was played
+    let mut last_score = 0;
+    // where the last puzzle's line ends, so the same win isn't found again
+    let mut skip_until = min_ply;
+    for (ply, &mv) in record.moves.iter().enumerate() {
+        if position.outcome().is_some() {
+            break;
+        }
+        let score = searcher.search(&position, settings.limits(settings.depth)).score;
+        if ply >= skip_until && score >= WINNING && -last_score < WINNING {
+            if let Some(puzzle) = find_puzzle(&position, settings) {
+                skip_until = ply + puzzle.solution.len() + 1;
+                puzzles.push((ply, puzzle));
+            }
+        }
+        last_score = score;
+        if !position.is_legal(mv) {
+            return Err(format!("illegal move {mv} at ply {}", ply + 1));
+        }
+        position.make_move(mv);
+    }
+    Ok(puzzles)
+}
+
+// a rough guess at how hard a puzzle is: longer lines are harder, and so are first moves that
+// don't capture or check, or that a one ply search wouldn't find
+fn rating(searcher: &mut Searcher, start: &Position, solution: &[Move]) -> u32 {
+    let first = solution[0];
+    let mut rating = 800 + 250 * (solution.len() as u32 / 2);
+    let mut after = start.clone();
+    after.make_move(first);
+    if !start.is_capture(first) && !after.in_check() {
+        rating += 300;
+    }
+    if searcher.search(start, SearchLimits::depth(1)).best_move != Some(first) {
+        rating += 200;
+    }
+    if matches!(after.outcome(), Some(Outcome::Checkmate(_))) && solution.len() == 1 {
+        rating -= 200;
+    }
+    rating
+}
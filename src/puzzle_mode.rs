@@ -0,0 +1,332 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use gmtk2024::ai::{AiTask, PlayerKind, Players};
+use gmtk2024::history::GameHistory;
+use gmtk2024::position::Outcome;
+use gmtk2024::puzzle::Puzzle;
+use gmtk2024::square::{PlayMoveEvent, SetPositionEvent};
+
+use crate::new_game::{setup_new_game_screen, spawn_button, NewGameScreen};
+
+const PROGRESS_FILE: &str = "puzzle_progress.txt";
+
+/// On the new game screen, under "Start".
+#[derive(Component)]
+pub struct PuzzlesButton;
+
+#[derive(Component)]
+struct PuzzlePanel;
+
+#[derive(Component)]
+struct PuzzleTitleText;
+
+#[derive(Component)]
+struct PuzzleStatusText;
+
+#[derive(Component)]
+struct RetryPuzzleButton;
+
+#[derive(Component)]
+struct NextPuzzleButton;
+
+#[derive(Component)]
+struct LeavePuzzlesButton;
+
+/// Sets up the board for one of the standard puzzles.
+#[derive(Event)]
+struct StartPuzzleEvent(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum PuzzleState {
+    #[default]
+    Solving,
+    Solved,
+    Failed,
+}
+
+/// The puzzle on the board, and how far into its solution the moves played have got.
+#[derive(Resource, Default)]
+pub struct PuzzleMode {
+    active: bool,
+    index: usize,
+    ply: usize,
+    state: PuzzleState,
+    // who was playing before, put back when leaving
+    players: Players,
+    // counts down to the reply being played, so the player sees their move land first
+    reply_timer: Option<Timer>,
+}
+
+/// The puzzles solved and failed so far, by name, kept in puzzle_progress.txt between
+/// sessions. Solving a puzzle that was failed before takes it off the failed list.
+#[derive(Resource, Default)]
+struct PuzzleProgress {
+    solved: HashSet<String>,
+    failed: HashSet<String>,
+}
+
+impl PuzzleProgress {
+    // `solved <name>` and `failed <name>` lines
+    fn load() -> Self {
+        let mut progress = Self::default();
+        let Ok(text) = std::fs::read_to_string(PROGRESS_FILE) else {
+            return progress;
+        };
+        for line in text.lines() {
+            match line.split_once(' ') {
+                Some(("solved", name)) => { progress.solved.insert(name.to_string()); },
+                Some(("failed", name)) => { progress.failed.insert(name.to_string()); },
+                _ => warn!("ignoring line in {PROGRESS_FILE}: {line}"),
+            }
+        }
+        progress
+    }
+
+    fn save(&self) {
+        let mut lines: Vec<String> = self.solved.iter().map(|name| format!("solved {name}"))
+            .chain(self.failed.iter().map(|name| format!("failed {name}")))
+            .collect();
+        lines.sort();
+        if let Err(err) = std::fs::write(PROGRESS_FILE, lines.join("\n") + "\n") {
+            warn!("can't write {PROGRESS_FILE}: {err}");
+        }
+    }
+
+    fn record(&mut self, name: &str, solved: bool) {
+        if solved {
+            self.failed.remove(name);
+            self.solved.insert(name.to_string());
+        } else if !self.solved.contains(name) {
+            self.failed.insert(name.to_string());
+        }
+        self.save();
+    }
+}
+
+pub fn in_puzzle(puzzle_mode: Res<PuzzleMode>) -> bool {
+    puzzle_mode.active
+}
+
+fn setup_puzzle_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 24.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        PuzzlePanel,
+    )).with_children(|column| {
+        column.spawn((TextBundle::from_section("", text_style.clone()), PuzzleTitleText));
+        column.spawn((
+            TextBundle::from_section("", TextStyle { font_size: 20.0, ..text_style }),
+            PuzzleStatusText,
+        ));
+        column.spawn(NodeBundle {
+            style: Style { column_gap: Val::Px(10.0), ..default() },
+            ..default()
+        }).with_children(|row| {
+            spawn_button(row, &asset_server, 120.0, "Retry", RetryPuzzleButton);
+            spawn_button(row, &asset_server, 120.0, "Next", NextPuzzleButton);
+            spawn_button(row, &asset_server, 120.0, "Leave", LeavePuzzlesButton);
+        });
+    });
+}
+
+type PuzzleButtons<'a> = (&'a Interaction, Has<PuzzlesButton>, Has<RetryPuzzleButton>, Has<NextPuzzleButton>);
+
+fn puzzle_buttons(
+    interaction_query: Query<PuzzleButtons, Changed<Interaction>>,
+    puzzle_mode: Res<PuzzleMode>,
+    progress: Res<PuzzleProgress>,
+    mut new_game_visibility: Query<&mut Visibility, With<NewGameScreen>>,
+    mut start_puzzle_writer: EventWriter<StartPuzzleEvent>,
+) {
+    let puzzles = Puzzle::standard();
+    for (interaction, puzzles_button, retry_button, next_button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if puzzles_button {
+            *new_game_visibility.single_mut() = Visibility::Hidden;
+            // carry on from the first one that hasn't been solved
+            let index = puzzles.iter().position(|puzzle| !progress.solved.contains(&puzzle.name)).unwrap_or(0);
+            start_puzzle_writer.send(StartPuzzleEvent(index));
+        }
+        if retry_button {
+            start_puzzle_writer.send(StartPuzzleEvent(puzzle_mode.index));
+        }
+        if next_button {
+            start_puzzle_writer.send(StartPuzzleEvent((puzzle_mode.index + 1) % puzzles.len()));
+        }
+    }
+}
+
+fn leave_puzzles_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<LeavePuzzlesButton>)>,
+    mut puzzle_mode: ResMut<PuzzleMode>,
+    mut players: ResMut<Players>,
+    mut panel_visibility: Query<&mut Visibility, With<PuzzlePanel>>,
+    mut new_game_visibility: Query<&mut Visibility, (With<NewGameScreen>, Without<PuzzlePanel>)>,
+) {
+    if interaction_query.iter().all(|interaction| *interaction != Interaction::Pressed) {
+        return;
+    }
+    *panel_visibility.single_mut() = Visibility::Hidden;
+    *new_game_visibility.single_mut() = Visibility::Visible;
+    *players = puzzle_mode.players;
+    *puzzle_mode = PuzzleMode::default();
+}
+
+fn start_puzzle(
+    mut start_puzzle_events: EventReader<StartPuzzleEvent>,
+    mut puzzle_mode: ResMut<PuzzleMode>,
+    (mut players, mut ai_task): (ResMut<Players>, ResMut<AiTask>),
+    mut history: ResMut<GameHistory>,
+    mut set_position_writer: EventWriter<SetPositionEvent>,
+    mut panel_visibility: Query<&mut Visibility, With<PuzzlePanel>>,
+) {
+    let Some(&StartPuzzleEvent(index)) = start_puzzle_events.read().last() else {
+        return;
+    };
+    let puzzle = &Puzzle::standard()[index];
+    if !puzzle_mode.active {
+        puzzle_mode.players = *players;
+    }
+    puzzle_mode.active = true;
+    puzzle_mode.index = index;
+    puzzle_mode.ply = 0;
+    puzzle_mode.state = PuzzleState::Solving;
+    puzzle_mode.reply_timer = None;
+    // the replies come from the solution, not the engine
+    let turn = puzzle.position.turn;
+    *players.get_mut(turn) = PlayerKind::Human;
+    *players.get_mut(turn.opposite()) = PlayerKind::Scripted;
+    ai_task.cancel();
+    *history = GameHistory::from_position(puzzle.position.clone());
+    set_position_writer.send(SetPositionEvent(puzzle.position.clone()));
+    *panel_visibility.single_mut() = Visibility::Visible;
+}
+
+// follows the moves played against the solution: the player's have to match it, and each
+// one that does gets the next reply lined up
+fn check_puzzle_moves(
+    history: Res<GameHistory>,
+    mut puzzle_mode: ResMut<PuzzleMode>,
+    mut progress: ResMut<PuzzleProgress>,
+    mut players: ResMut<Players>,
+) {
+    if puzzle_mode.state != PuzzleState::Solving {
+        return;
+    }
+    let puzzle = &Puzzle::standard()[puzzle_mode.index];
+    let line = history.line();
+    let mut position = history.start_position();
+    for &mv in &line[..puzzle_mode.ply.min(line.len())] {
+        position.make_move(mv);
+    }
+    while puzzle_mode.ply < line.len() {
+        let ply = puzzle_mode.ply;
+        let mv = line[ply];
+        let players_move = ply.is_multiple_of(2);
+        if players_move && !puzzle.is_solution_move(ply, &position, mv) {
+            puzzle_mode.state = PuzzleState::Failed;
+        }
+        position.make_move(mv);
+        puzzle_mode.ply += 1;
+        if puzzle_mode.state == PuzzleState::Solving && players_move {
+            if puzzle_mode.ply >= puzzle.solution.len() || matches!(position.outcome(), Some(Outcome::Checkmate(_))) {
+                puzzle_mode.state = PuzzleState::Solved;
+            } else {
+                puzzle_mode.reply_timer = Some(Timer::from_seconds(0.5, TimerMode::Once));
+            }
+        }
+        if puzzle_mode.state != PuzzleState::Solving {
+            progress.record(&puzzle.name, puzzle_mode.state == PuzzleState::Solved);
+            // nothing more gets played until the next puzzle
+            *players = Players { white: PlayerKind::Scripted, black: PlayerKind::Scripted };
+            return;
+        }
+    }
+}
+
+fn play_puzzle_reply(
+    time: Res<Time>,
+    mut puzzle_mode: ResMut<PuzzleMode>,
+    mut play_move_writer: EventWriter<PlayMoveEvent>,
+) {
+    let Some(timer) = puzzle_mode.reply_timer.as_mut() else {
+        return;
+    };
+    if !timer.tick(time.delta()).finished() {
+        return;
+    }
+    puzzle_mode.reply_timer = None;
+    let puzzle = &Puzzle::standard()[puzzle_mode.index];
+    if let Some(&reply) = puzzle.solution.get(puzzle_mode.ply) {
+        play_move_writer.send(PlayMoveEvent(reply));
+    }
+}
+
+fn update_puzzle_panel(
+    puzzle_mode: Res<PuzzleMode>,
+    progress: Res<PuzzleProgress>,
+    mut title_text: Query<&mut Text, With<PuzzleTitleText>>,
+    mut status_text: Query<&mut Text, (With<PuzzleStatusText>, Without<PuzzleTitleText>)>,
+) {
+    let puzzles = Puzzle::standard();
+    let puzzle = &puzzles[puzzle_mode.index];
+    title_text.single_mut().sections[0].value = format!(
+        "Puzzle {} of {}: {} ({})",
+        puzzle_mode.index + 1,
+        puzzles.len(),
+        puzzle.name,
+        puzzle.rating,
+    );
+    let status = match puzzle_mode.state {
+        PuzzleState::Solving if puzzle_mode.ply == 0 => {
+            format!("{} to play and win", puzzle.position.turn.name())
+        }
+        PuzzleState::Solving => "Right, keep going".to_string(),
+        PuzzleState::Solved => "Solved!".to_string(),
+        PuzzleState::Failed => {
+            // the player's move that was wanted instead
+            let wanted = puzzle.solution[puzzle_mode.ply - 1];
+            format!("Not quite, the move was {wanted}")
+        }
+    };
+    let solved = puzzles.iter().filter(|puzzle| progress.solved.contains(&puzzle.name)).count();
+    let failed = puzzles.iter().filter(|puzzle| progress.failed.contains(&puzzle.name)).count();
+    status_text.single_mut().sections[0].value = format!("{status}\nSolved {solved}, failed {failed} of {}", puzzles.len());
+}
+
+pub struct PuzzleModePlugin;
+impl Plugin for PuzzleModePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(PuzzleMode::default())
+            .insert_resource(PuzzleProgress::load())
+            .add_event::<StartPuzzleEvent>()
+            // under the new game screen
+            .add_systems(Startup, setup_puzzle_panel.before(setup_new_game_screen))
+            .add_systems(Update, (puzzle_buttons, start_puzzle).chain())
+            .add_systems(Update, (
+                leave_puzzles_button,
+                check_puzzle_moves.run_if(resource_changed::<GameHistory>),
+                play_puzzle_reply,
+                update_puzzle_panel.run_if(resource_changed::<PuzzleMode>.or_else(resource_changed::<PuzzleProgress>)),
+            ).chain().run_if(in_puzzle));
+    }
+}
@@ -0,0 +1,467 @@
+use std::fmt;
+
+use crate::position::{Move, Outcome, Position};
+use crate::piece::PieceColour;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    #[default]
+    Unfinished,
+}
+
+impl GameResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Unfinished => "*",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "1-0" => Some(GameResult::WhiteWins),
+            "0-1" => Some(GameResult::BlackWins),
+            "1/2-1/2" => Some(GameResult::Draw),
+            "*" => Some(GameResult::Unfinished),
+            _ => None,
+        }
+    }
+
+    /// The result when `colour` wins, however they won.
+    pub fn win_for(colour: PieceColour) -> Self {
+        match colour {
+            PieceColour::White => GameResult::WhiteWins,
+            PieceColour::Black => GameResult::BlackWins,
+        }
+    }
+
+    pub fn from_outcome(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Checkmate(PieceColour::White) => GameResult::BlackWins,
+            Outcome::Checkmate(PieceColour::Black) => GameResult::WhiteWins,
+            Outcome::Stalemate => GameResult::Draw,
+        }
+    }
+}
+
+/// What somebody thought of a move, written straight after it: `e2e4!`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MoveSymbol {
+    Good,
+    Mistake,
+    Brilliant,
+    Blunder,
+    Interesting,
+    Dubious,
+}
+
+impl MoveSymbol {
+    pub const ALL: [MoveSymbol; 6] = [
+        MoveSymbol::Good,
+        MoveSymbol::Mistake,
+        MoveSymbol::Brilliant,
+        MoveSymbol::Blunder,
+        MoveSymbol::Interesting,
+        MoveSymbol::Dubious,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MoveSymbol::Good => "!",
+            MoveSymbol::Mistake => "?",
+            MoveSymbol::Brilliant => "!!",
+            MoveSymbol::Blunder => "??",
+            MoveSymbol::Interesting => "!?",
+            MoveSymbol::Dubious => "?!",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|symbol| symbol.as_str() == text)
+    }
+}
+
+/// A game written down like PGN: `[Name "value"]` tag lines, then the moves in coordinate
+/// notation with move numbers, ending in the result. Games start from the usual starting
+/// position unless a `Setup` tag gives another one (see `Position::from_setup`). Variations
+/// go in brackets straight after the move they could have been played instead of, and can
+/// have variations of their own: `1. e2e4 e7e5 (1... c7c5 2. g1f3 (2. b1c3)) 2. g1f3`. Moves
+/// can have a symbol on the end, and comments go in braces after the move whose position
+/// they're about, or before the first move for the starting position:
+/// `1. e2e4! {the best by test} e7e5`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameRecord {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<Move>,
+    // each with the index of the move in `moves` it's an alternative to
+    pub variations: Vec<(usize, Line)>,
+    // each with the index of the move it's on
+    pub symbols: Vec<(usize, MoveSymbol)>,
+    // each with how many of `moves` come before it, so 0 is before the first move
+    pub comments: Vec<(usize, String)>,
+    pub result: GameResult,
+}
+
+/// A variation: its moves, and the variations, symbols and comments on those, the same way
+/// `GameRecord` has them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    pub moves: Vec<Move>,
+    pub variations: Vec<(usize, Line)>,
+    pub symbols: Vec<(usize, MoveSymbol)>,
+    pub comments: Vec<(usize, String)>,
+}
+
+/// Reads moves in coordinate notation separated by whitespace, e.g. `e2e4 e7e5`.
+pub fn parse_moves(text: &str) -> Result<Vec<Move>, String> {
+    text.split_whitespace()
+        .map(|notation| Move::from_notation(notation).ok_or(format!("can't read move {notation}")))
+        .collect()
+}
+
+// move numbers and moves, starting `first_ply` plies into the game: `3. e2e4 e7e5`, or
+// `3... e7e5` if black moves first
+fn numbered_moves(first_ply: usize, moves: &[Move]) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for (i, mv) in moves.iter().enumerate() {
+        let ply = first_ply + i;
+        if ply.is_multiple_of(2) {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        } else if i == 0 {
+            tokens.push(format!("{}...", ply / 2 + 1));
+        }
+        tokens.push(mv.to_string());
+    }
+    tokens
+}
+
+// a comment as words, so it can be wrapped: `{the`, `best`, `by`, `test}`. Braces in it
+// would end it early, so they're swapped for brackets
+fn comment_tokens(comment: &str) -> Vec<String> {
+    let comment = comment.replace('{', "(").replace('}', ")");
+    let mut tokens: Vec<String> = comment.split_whitespace().map(str::to_string).collect();
+    if tokens.is_empty() {
+        return tokens;
+    }
+    tokens[0].insert(0, '{');
+    tokens.last_mut().unwrap().push('}');
+    tokens
+}
+
+// like numbered_moves, with the symbols, comments after the moves they follow, and each
+// variation in brackets after the move it replaces. The move after a comment or variation
+// gets its number again, the way PGN does it
+fn numbered_line(first_ply: usize, line: &Line) -> Vec<String> {
+    let comments_at = |at: usize| line.comments.iter()
+        .filter(move |(i, _)| *i == at)
+        .flat_map(|(_, comment)| comment_tokens(comment));
+    let mut tokens: Vec<String> = comments_at(0).collect();
+    let mut interrupted = false;
+    for (i, mv) in line.moves.iter().enumerate() {
+        let ply = first_ply + i;
+        if ply.is_multiple_of(2) {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        } else if i == 0 || interrupted {
+            tokens.push(format!("{}...", ply / 2 + 1));
+        }
+        let symbol = line.symbols.iter().find(|(at, _)| *at == i).map_or("", |(_, symbol)| symbol.as_str());
+        tokens.push(format!("{mv}{symbol}"));
+        let before = tokens.len();
+        tokens.extend(comments_at(i + 1));
+        for (_, variation) in line.variations.iter().filter(|(at, _)| *at == i) {
+            let mut variation = numbered_line(ply, variation);
+            if let Some(first) = variation.first_mut() {
+                first.insert(0, '(');
+            }
+            if let Some(last) = variation.last_mut() {
+                last.push(')');
+            }
+            tokens.extend(variation);
+        }
+        interrupted = tokens.len() > before;
+    }
+    tokens
+}
+
+/// Where the move numbers of a game from `start` begin, for `move_text` and `line_text`: a ply
+/// in if black moves first, so its first move is `1...`.
+pub fn first_ply(start: &Position) -> usize {
+    match start.turn {
+        PieceColour::White => 0,
+        PieceColour::Black => 1,
+    }
+}
+
+/// Moves with their move numbers, for showing a line that starts `first_ply` plies into a
+/// game.
+pub fn move_text(first_ply: usize, moves: &[Move]) -> String {
+    numbered_moves(first_ply, moves).join(" ")
+}
+
+/// Like `move_text`, with the line's symbols, comments and variations.
+pub fn line_text(first_ply: usize, line: &Line) -> String {
+    numbered_line(first_ply, line).join(" ")
+}
+
+impl GameRecord {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old_value)) => *old_value = value,
+            None => self.tags.push((name.to_string(), value)),
+        }
+    }
+
+    /// The position the moves start from.
+    pub fn start_position(&self) -> Result<Position, String> {
+        match self.tag("Setup") {
+            Some(setup) => Position::from_setup(setup),
+            None => Ok(Position::default()),
+        }
+    }
+
+    /// Plays through the moves, checking each one is legal.
+    pub fn position(&self) -> Result<Position, String> {
+        let mut position = self.start_position()?;
+        for (ply, &mv) in self.moves.iter().enumerate() {
+            if !position.is_legal(mv) {
+                return Err(format!("illegal move {mv} at ply {}", ply + 1));
+            }
+            position.make_move(mv);
+        }
+        Ok(position)
+    }
+
+    /// Reads every game in a file.
+    pub fn parse_all(text: &str) -> Result<Vec<Self>, String> {
+        let mut games = Vec::new();
+        let mut game = GameRecord::default();
+        // set once the current game has moves, so the next tag line starts a new game
+        let mut in_moves = false;
+        // variations whose closing bracket hasn't come yet, innermost last
+        let mut open: Vec<(usize, Line)> = Vec::new();
+        // the words so far of a comment that hasn't been closed yet
+        let mut comment: Option<Vec<String>> = None;
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: String| format!("line {}: {message}", line_number + 1);
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(tag) = line.strip_prefix('[').filter(|_| comment.is_none()) {
+                if in_moves {
+                    games.push(std::mem::take(&mut game));
+                    in_moves = false;
+                }
+                let (name, value) = parse_tag(tag).ok_or_else(|| error(format!("can't read tag {line}")))?;
+                game.set_tag(&name, value);
+                continue;
+            }
+            in_moves = true;
+            for token in tokenize(line, &mut comment) {
+                let token = match token {
+                    Token::Comment(text) => {
+                        match open.last_mut() {
+                            Some((_, line)) => line.comments.push((line.moves.len(), text)),
+                            None => game.comments.push((game.moves.len(), text)),
+                        }
+                        continue;
+                    }
+                    Token::Word(token) => token,
+                };
+                if let Some(result) = GameResult::parse(token) {
+                    if !open.is_empty() {
+                        return Err(error("variation not closed before the result".to_string()));
+                    }
+                    game.result = result;
+                    games.push(std::mem::take(&mut game));
+                    in_moves = false;
+                } else if token.ends_with('.') && token[..token.len() - 1].bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+                    // move number
+                } else if token == "(" {
+                    let moves = open.last().map_or(&game.moves, |(_, line)| &line.moves);
+                    let at = moves.len().checked_sub(1)
+                        .ok_or_else(|| error("variation before the move it replaces".to_string()))?;
+                    open.push((at, Line::default()));
+                } else if token == ")" {
+                    let variation = open.pop().ok_or_else(|| error("unmatched )".to_string()))?;
+                    match open.last_mut() {
+                        Some((_, line)) => line.variations.push(variation),
+                        None => game.variations.push(variation),
+                    }
+                } else {
+                    let notation = token.trim_end_matches(['!', '?']);
+                    let mv = Move::from_notation(notation).ok_or_else(|| error(format!("can't read move {token}")))?;
+                    let symbol = match &token[notation.len()..] {
+                        "" => None,
+                        symbol => Some(MoveSymbol::parse(symbol).ok_or_else(|| error(format!("can't read symbol {symbol}")))?),
+                    };
+                    let (moves, symbols) = match open.last_mut() {
+                        Some((_, line)) => (&mut line.moves, &mut line.symbols),
+                        None => (&mut game.moves, &mut game.symbols),
+                    };
+                    symbols.extend(symbol.map(|symbol| (moves.len(), symbol)));
+                    moves.push(mv);
+                }
+            }
+        }
+        if comment.is_some() {
+            return Err("comment not closed at the end".to_string());
+        }
+        if !open.is_empty() {
+            return Err("variation not closed at the end".to_string());
+        }
+        if in_moves || !game.tags.is_empty() {
+            games.push(game);
+        }
+        Ok(games)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut games = Self::parse_all(text)?;
+        match games.len() {
+            1 => Ok(games.remove(0)),
+            0 => Err("no game found".to_string()),
+            n => Err(format!("expected one game but found {n}")),
+        }
+    }
+}
+
+enum Token<'a> {
+    Word(&'a str),
+    Comment(String),
+}
+
+// splits a line of moves into words, brackets and comments. `comment` holds the words of a
+// comment that carries on past the end of the line, for the next one to finish
+fn tokenize<'a>(mut line: &'a str, comment: &mut Option<Vec<String>>) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    loop {
+        if let Some(words) = comment.as_mut() {
+            let (inside, rest) = line.split_once('}').unwrap_or((line, ""));
+            words.extend(inside.split_whitespace().map(str::to_string));
+            if !line.contains('}') {
+                return tokens;
+            }
+            tokens.push(Token::Comment(comment.take().unwrap().join(" ")));
+            line = rest;
+        }
+        let (moves, rest) = match line.split_once('{') {
+            Some((moves, rest)) => (moves, Some(rest)),
+            None => (line, None),
+        };
+        for word in moves.split_whitespace() {
+            // brackets can be up against the moves: `(1... c7c5)`
+            let mut word = word;
+            while let Some(rest) = word.strip_prefix('(') {
+                tokens.push(Token::Word("("));
+                word = rest;
+            }
+            let closing = word.len() - word.trim_end_matches(')').len();
+            word = &word[..word.len() - closing];
+            if !word.is_empty() {
+                tokens.push(Token::Word(word));
+            }
+            tokens.extend((0..closing).map(|_| Token::Word(")")));
+        }
+        match rest {
+            Some(rest) => {
+                *comment = Some(Vec::new());
+                line = rest;
+            }
+            None => return tokens,
+        }
+    }
+}
+
+// `Name "value"]`, with the opening bracket already taken off
+fn parse_tag(tag: &str) -> Option<(String, String)> {
+    let tag = tag.strip_suffix(']')?;
+    let (name, value) = tag.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{name} \"{}\"]", value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f)?;
+
+        // wrap the moves like PGN does, at 80 columns
+        let mut line = String::new();
+        let moves = Line {
+            moves: self.moves.clone(),
+            variations: self.variations.clone(),
+            symbols: self.symbols.clone(),
+            comments: self.comments.clone(),
+        };
+        let start = self.start_position().map_or(0, |position| first_ply(&position));
+        let mut tokens = numbered_line(start, &moves);
+        tokens.push(self.result.as_str().to_string());
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > 80 {
+                writeln!(f, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        writeln!(f, "{line}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // parses `text`, writes it out and parses that, which has to give the same record back
+    fn round_trip(text: &str) -> GameRecord {
+        let record = GameRecord::parse(text).unwrap();
+        let written = record.to_string();
+        assert_eq!(GameRecord::parse(&written).unwrap(), record, "written as:\n{written}");
+        record
+    }
+
+    #[test]
+    fn variations_symbols_and_comments_round_trip() {
+        let record = round_trip(r#"[Event "Round \"trip\""]
+
+{before the first move} 1. e2e4! e7e5 (1... c7c5 {the
+Sicilian} 2. g1f3 (2. b1c3?! b8c6) d7d6) 2. g1f3 {a comment
+over two lines} b8c6?? 1-0
+"#);
+        assert_eq!(record.tag("Event"), Some("Round \"trip\""));
+        assert_eq!(record.moves.len(), 4);
+        assert_eq!(record.result, GameResult::WhiteWins);
+        assert_eq!(record.symbols.len(), 2);
+        assert_eq!(record.comments, vec![(0, "before the first move".to_string()), (3, "a comment over two lines".to_string())]);
+        let (at, sicilian) = &record.variations[0];
+        assert_eq!(*at, 1);
+        assert_eq!(sicilian.comments, vec![(1, "the Sicilian".to_string())]);
+        assert_eq!(sicilian.variations[0].1.symbols.len(), 1);
+    }
+
+    #[test]
+    fn black_to_move_round_trips() {
+        let record = round_trip(r#"[Setup "Ke1 Ra1 ke8 b"]
+
+1... e8d7 2. a1a7 d7d6 *
+"#);
+        assert_eq!(record.moves.len(), 3);
+        assert!(record.to_string().contains("1... e8d7 2. a1a7"));
+    }
+}
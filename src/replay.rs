@@ -0,0 +1,408 @@
+use bevy::prelude::*;
+use bevy::ui::RelativeCursorPosition;
+use gmtk2024::ai::{AiTask, PlayerKind, Players};
+use gmtk2024::history::GameHistory;
+use gmtk2024::piece::PieceColour;
+use gmtk2024::position::Move;
+use gmtk2024::record::{first_ply, move_text, GameRecord, GameResult};
+use gmtk2024::square::{CurrentPosition, PlayMoveEvent, SetPositionEvent};
+
+use crate::new_game::{setup_new_game_screen, spawn_button, NewGameScreen};
+
+const REPLAY_FILE: &str = "replay.txt";
+// seconds between moves at normal speed
+const MOVE_INTERVAL: f32 = 1.2;
+// in the order the speed button goes through them
+const SPEEDS: [f32; 5] = [1.0, 2.0, 4.0, 8.0, 0.5];
+
+/// On the new game screen, under "Correspondence".
+#[derive(Component)]
+pub struct ReplayButton;
+
+#[derive(Component)]
+struct ReplayPanel;
+
+#[derive(Component)]
+struct ReplayTitleText;
+
+#[derive(Component)]
+struct ReplayMoveText;
+
+// the bar along the bottom: how far through the game, and clicking it goes there
+#[derive(Component)]
+struct SeekBar;
+
+#[derive(Component)]
+struct SeekBarFill;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+enum ReplayControl {
+    Start,
+    Back,
+    PlayPause,
+    Forward,
+    End,
+    Speed,
+    Camera,
+    NextGame,
+    Leave,
+}
+
+/// Where to take the replay, from the buttons, the keys or the seek bar.
+#[derive(Event, Clone, Copy)]
+enum ReplayEvent {
+    Open,
+    Control(ReplayControl),
+    Seek(usize),
+}
+
+/// The games in replay.txt, and how far through the one on the board the replay is.
+#[derive(Resource, Default)]
+pub struct Replay {
+    active: bool,
+    games: Vec<GameRecord>,
+    game: usize,
+    // the main line of the game on the board
+    moves: Vec<Move>,
+    playing: bool,
+    // into SPEEDS
+    speed: usize,
+    // counts down to the next move while playing
+    timer: Timer,
+    // on White's side the whole time, rather than swivelling to whoever's move it is
+    fixed_camera: bool,
+    // the hash of the position the board is about to show. Nothing more is played until it
+    // does, so each move lands on the position it was played in
+    awaiting: Option<u64>,
+    // who was playing before, put back when leaving
+    players: Players,
+    error: Option<String>,
+}
+
+impl Replay {
+    /// The side the camera stays on, if it isn't swivelling.
+    pub fn camera_side(&self) -> Option<PieceColour> {
+        Some(PieceColour::White).filter(|_| self.active && self.fixed_camera)
+    }
+
+    fn interval(&self) -> f32 {
+        MOVE_INTERVAL / SPEEDS[self.speed]
+    }
+}
+
+pub fn in_replay(replay: Res<Replay>) -> bool {
+    replay.active
+}
+
+fn speed_label(speed: f32) -> String {
+    format!("Speed {speed}x")
+}
+
+fn setup_replay_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 24.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                right: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        ReplayPanel,
+    )).with_children(|column| {
+        column.spawn((TextBundle::from_section("", text_style.clone()), ReplayTitleText));
+        column.spawn((
+            TextBundle::from_section("", TextStyle { font_size: 20.0, ..text_style }),
+            ReplayMoveText,
+        ));
+        column.spawn((
+            ButtonBundle {
+                style: Style { height: Val::Px(12.0), ..default() },
+                background_color: Color::srgba(0.9, 0.9, 0.9, 0.2).into(),
+                border_radius: BorderRadius::all(Val::Px(6.0)),
+                ..default()
+            },
+            RelativeCursorPosition::default(),
+            SeekBar,
+        )).with_children(|bar| {
+            bar.spawn((
+                NodeBundle {
+                    style: Style { width: Val::Percent(0.0), height: Val::Percent(100.0), ..default() },
+                    background_color: Color::srgb(0.9, 0.9, 0.9).into(),
+                    border_radius: BorderRadius::all(Val::Px(6.0)),
+                    ..default()
+                },
+                SeekBarFill,
+            ));
+        });
+        for controls in [
+            [(ReplayControl::Start, "|<", 70.0), (ReplayControl::Back, "<", 70.0), (ReplayControl::PlayPause, "", 100.0),
+                (ReplayControl::Forward, ">", 70.0), (ReplayControl::End, ">|", 70.0)].as_slice(),
+            [(ReplayControl::Speed, "", 160.0), (ReplayControl::Camera, "", 200.0), (ReplayControl::NextGame, "Next game", 160.0),
+                (ReplayControl::Leave, "Leave", 120.0)].as_slice(),
+        ] {
+            column.spawn(NodeBundle {
+                style: Style { column_gap: Val::Px(10.0), ..default() },
+                ..default()
+            }).with_children(|row| {
+                // the labels left empty are filled in by update_replay_panel
+                for &(control, label, width) in controls {
+                    spawn_button(row, &asset_server, width, label, control);
+                }
+            });
+        }
+    });
+}
+
+type ReplayButtonFilter = (Changed<Interaction>, Or<(With<ReplayButton>, With<ReplayControl>)>);
+
+fn replay_buttons(
+    interaction_query: Query<(&Interaction, Option<&ReplayControl>, Has<ReplayButton>), ReplayButtonFilter>,
+    seek_bar: Query<(&Interaction, &RelativeCursorPosition), With<SeekBar>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    replay: Res<Replay>,
+    mut new_game_visibility: Query<&mut Visibility, With<NewGameScreen>>,
+    mut replay_writer: EventWriter<ReplayEvent>,
+) {
+    for (interaction, control, replay_button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if replay_button {
+            *new_game_visibility.single_mut() = Visibility::Hidden;
+            replay_writer.send(ReplayEvent::Open);
+        }
+        if let Some(&control) = control {
+            replay_writer.send(ReplayEvent::Control(control));
+        }
+    }
+    if !replay.active {
+        return;
+    }
+    // held down, so dragging along the bar seeks as it goes
+    if let Ok((Interaction::Pressed, cursor)) = seek_bar.get_single() {
+        if let Some(position) = cursor.normalized {
+            let ply = (position.x.clamp(0.0, 1.0) * replay.moves.len() as f32).round() as usize;
+            replay_writer.send(ReplayEvent::Seek(ply));
+        }
+    }
+    for (key, control) in [
+        (KeyCode::Space, ReplayControl::PlayPause),
+        (KeyCode::ArrowLeft, ReplayControl::Back),
+        (KeyCode::ArrowRight, ReplayControl::Forward),
+        (KeyCode::Home, ReplayControl::Start),
+        (KeyCode::End, ReplayControl::End),
+    ] {
+        if keys.just_pressed(key) {
+            replay_writer.send(ReplayEvent::Control(control));
+        }
+    }
+}
+
+fn read_games() -> Result<Vec<GameRecord>, String> {
+    let text = std::fs::read_to_string(REPLAY_FILE).map_err(|err| format!("Can't read {REPLAY_FILE}: {err}"))?;
+    let games = GameRecord::parse_all(&text).map_err(|err| format!("Can't read {REPLAY_FILE}: {err}"))?;
+    if games.is_empty() {
+        return Err(format!("There are no games in {REPLAY_FILE}"));
+    }
+    Ok(games)
+}
+
+// puts game `index` on the board at its start
+fn load_game(replay: &mut Replay, index: usize, history: &mut GameHistory) -> Result<(), String> {
+    let record = &replay.games[index];
+    *history = GameHistory::from_record(record).map_err(|err| format!("Can't replay game {}: {err}", index + 1))?;
+    replay.game = index;
+    replay.moves = history.main_line();
+    replay.playing = false;
+    Ok(())
+}
+
+fn run_replay(
+    mut replay_events: EventReader<ReplayEvent>,
+    mut replay: ResMut<Replay>,
+    (mut players, mut ai_task): (ResMut<Players>, ResMut<AiTask>),
+    (time, position, mut history): (Res<Time>, Res<CurrentPosition>, ResMut<GameHistory>),
+    (mut set_position_writer, mut play_move_writer): (EventWriter<SetPositionEvent>, EventWriter<PlayMoveEvent>),
+    mut panel_visibility: Query<&mut Visibility, With<ReplayPanel>>,
+    mut new_game_visibility: Query<&mut Visibility, (With<NewGameScreen>, Without<ReplayPanel>)>,
+) {
+    // the ply to go to, if it's anywhere but one move on
+    let mut seek = None;
+    // a different game's been loaded, so the board needs setting up even at the same ply
+    let mut loaded = false;
+    let mut step = false;
+    for &event in replay_events.read() {
+        match event {
+            ReplayEvent::Open => {
+                if !replay.active {
+                    replay.players = *players;
+                }
+                replay.active = true;
+                // the moves all come from the record
+                *players = Players { white: PlayerKind::Scripted, black: PlayerKind::Scripted };
+                ai_task.cancel();
+                *panel_visibility.single_mut() = Visibility::Visible;
+                replay.timer = Timer::from_seconds(replay.interval(), TimerMode::Repeating);
+                let opened = read_games().and_then(|games| {
+                    replay.games = games;
+                    load_game(&mut replay, 0, &mut history)
+                });
+                replay.error = opened.err();
+                seek = Some(0);
+                loaded = true;
+            }
+            _ if replay.error.is_some() && !matches!(event, ReplayEvent::Control(ReplayControl::Leave)) => {}
+            ReplayEvent::Control(ReplayControl::Start) => seek = Some(0),
+            ReplayEvent::Control(ReplayControl::Back) => seek = Some(history.ply().saturating_sub(1)),
+            ReplayEvent::Control(ReplayControl::Forward) => step = true,
+            ReplayEvent::Control(ReplayControl::End) => seek = Some(replay.moves.len()),
+            ReplayEvent::Control(ReplayControl::PlayPause) => {
+                replay.playing = !replay.playing;
+                // from the start again once it's over
+                if replay.playing && history.ply() == replay.moves.len() {
+                    seek = Some(0);
+                }
+                replay.timer.reset();
+            }
+            ReplayEvent::Control(ReplayControl::Speed) => {
+                replay.speed = (replay.speed + 1) % SPEEDS.len();
+                let interval = replay.interval();
+                replay.timer.set_duration(std::time::Duration::from_secs_f32(interval));
+            }
+            ReplayEvent::Control(ReplayControl::Camera) => replay.fixed_camera = !replay.fixed_camera,
+            ReplayEvent::Control(ReplayControl::NextGame) => {
+                let next = (replay.game + 1) % replay.games.len();
+                replay.error = load_game(&mut replay, next, &mut history).err();
+                seek = Some(0);
+                loaded = true;
+            }
+            ReplayEvent::Control(ReplayControl::Leave) => {
+                *players = replay.players;
+                *replay = Replay::default();
+                *panel_visibility.single_mut() = Visibility::Hidden;
+                *new_game_visibility.single_mut() = Visibility::Visible;
+                return;
+            }
+            ReplayEvent::Seek(ply) => seek = Some(ply),
+        }
+    }
+    if !replay.active || replay.error.is_some() {
+        return;
+    }
+
+    // nothing new until the board has caught up with the last move or jump
+    if replay.awaiting.is_some_and(|hash| hash != position.0.hash) {
+        return;
+    }
+    replay.awaiting = None;
+    if let Some(ply) = seek.filter(|&ply| loaded || ply != history.ply()) {
+        history.to_start();
+        for _ in 0..ply.min(replay.moves.len()) {
+            history.forward();
+        }
+        let position = history.position();
+        replay.awaiting = Some(position.hash);
+        set_position_writer.send(SetPositionEvent(position));
+        return;
+    }
+    if replay.playing && replay.timer.tick(time.delta()).just_finished() {
+        step = true;
+    }
+    if !step {
+        return;
+    }
+    match replay.moves.get(history.ply()) {
+        // played on the board like any other move, so the pieces slide and grow
+        Some(&mv) => {
+            let mut after = position.0.clone();
+            after.make_move(mv);
+            replay.awaiting = Some(after.hash);
+            play_move_writer.send(PlayMoveEvent(mv));
+        }
+        None => replay.playing = false,
+    }
+}
+
+fn update_replay_panel(
+    replay: Res<Replay>,
+    history: Res<GameHistory>,
+    mut title_text: Query<&mut Text, With<ReplayTitleText>>,
+    mut move_text_query: Query<&mut Text, (With<ReplayMoveText>, Without<ReplayTitleText>)>,
+    mut seek_fill: Query<&mut Style, With<SeekBarFill>>,
+    buttons: Query<(&ReplayControl, &Children)>,
+    mut texts: Query<&mut Text, (Without<ReplayTitleText>, Without<ReplayMoveText>)>,
+) {
+    if let Some(error) = &replay.error {
+        title_text.single_mut().sections[0].value.clone_from(error);
+        move_text_query.single_mut().sections[0].value = format!("Put the games to replay in {REPLAY_FILE}");
+        return;
+    }
+    let Some(record) = replay.games.get(replay.game) else {
+        return;
+    };
+    let players = match (record.tag("White"), record.tag("Black")) {
+        (Some(white), Some(black)) => format!(", {white} against {black}"),
+        _ => String::new(),
+    };
+    let result = match history.result() {
+        GameResult::Unfinished => String::new(),
+        result => format!(" ({})", result.as_str()),
+    };
+    title_text.single_mut().sections[0].value = format!(
+        "Replay: game {} of {}{players}{result}",
+        replay.game + 1,
+        replay.games.len(),
+    );
+    let ply = history.ply();
+    let first_ply = first_ply(&history.start_position());
+    move_text_query.single_mut().sections[0].value = match ply {
+        0 => format!("Start, {} moves to go", (first_ply + replay.moves.len()).div_ceil(2)),
+        _ => format!(
+            "{}  ({ply} of {})",
+            move_text(first_ply + ply - 1, &replay.moves[ply - 1..ply]),
+            replay.moves.len(),
+        ),
+    };
+    seek_fill.single_mut().width = Val::Percent(100.0 * ply as f32 / replay.moves.len().max(1) as f32);
+    for (control, children) in buttons.iter() {
+        let label = match control {
+            ReplayControl::PlayPause if replay.playing => "Pause".to_string(),
+            ReplayControl::PlayPause => "Play".to_string(),
+            ReplayControl::Speed => speed_label(SPEEDS[replay.speed]),
+            ReplayControl::Camera if replay.fixed_camera => "Camera: fixed".to_string(),
+            ReplayControl::Camera => "Camera: swivel".to_string(),
+            _ => continue,
+        };
+        if let Ok(mut text) = texts.get_mut(children[0]) {
+            text.sections[0].value = label;
+        }
+    }
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Replay::default())
+            .add_event::<ReplayEvent>()
+            // under the new game screen
+            .add_systems(Startup, setup_replay_panel.before(setup_new_game_screen))
+            .add_systems(Update, (
+                replay_buttons,
+                run_replay,
+                update_replay_panel.run_if(in_replay.and_then(resource_changed::<Replay>.or_else(resource_changed::<GameHistory>))),
+            ).chain());
+    }
+}
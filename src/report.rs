@@ -0,0 +1,142 @@
+use crate::piece::{PieceColour, PieceType};
+use crate::position::{Move, Position};
+use crate::search::{SearchConfig, SearchLimits, Searcher, MATE_SCORE};
+
+// how far a move has to drop its side's evaluation, in centipawns, to count as a blunder
+const BLUNDER_SWING: i32 = 300;
+// mates count as this much either way, so finding one isn't a blunder of thousands of pawns
+const SCORE_CAP: i32 = 2000;
+
+/// How one side did over a game.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SideReport {
+    pub captures: u32,
+    // the piece that got the biggest footprint, in squares, and the ply it got there. None if
+    // nothing grew
+    pub biggest: Option<(PieceType, usize, usize)>,
+}
+
+/// A move that made a piece bigger.
+#[derive(Clone, Copy, Debug)]
+pub struct Growth {
+    // plies played before it
+    pub ply: usize,
+    pub mv: Move,
+    pub colour: PieceColour,
+    pub piece_type: PieceType,
+    // squares it added
+    pub squares: usize,
+}
+
+/// A move the engine thinks threw a lot away.
+#[derive(Clone, Copy, Debug)]
+pub struct Blunder {
+    // plies played before it
+    pub ply: usize,
+    pub mv: Move,
+    pub colour: PieceColour,
+    // how much worse it left things for the side that played it, in centipawns
+    pub swing: i32,
+}
+
+/// The summary shown once a game is over.
+#[derive(Clone, Debug, Default)]
+pub struct GameReport {
+    pub plies: usize,
+    // white's, then black's
+    pub sides: [SideReport; 2],
+    pub largest_growth: Option<Growth>,
+}
+
+fn side_index(colour: PieceColour) -> usize {
+    match colour {
+        PieceColour::White => 0,
+        PieceColour::Black => 1,
+    }
+}
+
+impl GameReport {
+    /// Replays the moves from `start`, where the game began.
+    pub fn from_moves(start: &Position, moves: &[Move]) -> Result<Self, String> {
+        let mut report = GameReport { plies: moves.len(), ..Default::default() };
+        let mut position = start.clone();
+        for (ply, &mv) in moves.iter().enumerate() {
+            if !position.is_legal(mv) {
+                return Err(format!("illegal move {mv} at ply {}", ply + 1));
+            }
+            let piece = position.piece_at(mv.from).ok_or(format!("no piece to move for {mv}"))?.clone();
+            let side = &mut report.sides[side_index(piece.colour)];
+            if position.is_capture(mv) {
+                side.captures += 1;
+            }
+            position.make_move(mv);
+
+            let moved = position.pieces.iter().find(|p| (p.x, p.y) == mv.to).ok_or(format!("{mv} lost the piece"))?;
+            let size = moved.squares_occupied.len();
+            let squares = size - piece.squares_occupied.len();
+            if squares > 0 {
+                if side.biggest.is_none_or(|(_, biggest, _)| size > biggest) {
+                    side.biggest = Some((moved.piece_type, size, ply + 1));
+                }
+                if report.largest_growth.is_none_or(|growth| squares > growth.squares) {
+                    let piece_type = moved.piece_type;
+                    report.largest_growth = Some(Growth { ply, mv, colour: piece.colour, piece_type, squares });
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+// from the side to move's point of view, with mates capped
+fn capped_score(searcher: &mut Searcher, position: &Position, limits: SearchLimits) -> i32 {
+    let score = searcher.search(position, limits).score;
+    if score.abs() > MATE_SCORE - 1000 {
+        score.signum() * SCORE_CAP
+    } else {
+        score.clamp(-SCORE_CAP, SCORE_CAP)
+    }
+}
+
+/// Searches every position of a game from `start` to find the moves that dropped their side's
+/// evaluation the most. Takes a while, so it's best run off the main thread.
+pub fn find_blunders(start: &Position, moves: &[Move], limits: SearchLimits) -> Vec<Blunder> {
+    let mut searcher = Searcher::new(SearchConfig::default());
+    let mut position = start.clone();
+    let mut blunders = Vec::new();
+    // the score for the side to move in `position`
+    let mut score = capped_score(&mut searcher, &position, limits);
+    for (ply, &mv) in moves.iter().enumerate() {
+        let colour = position.turn;
+        position.make_move(mv);
+        let next_score = match position.outcome() {
+            // checkmated, or stalemate
+            Some(_) if position.in_check() => -SCORE_CAP,
+            Some(_) => 0,
+            None => capped_score(&mut searcher, &position, limits),
+        };
+        let swing = score + next_score;
+        if swing >= BLUNDER_SWING {
+            blunders.push(Blunder { ply, mv, colour, swing });
+        }
+        score = next_score;
+    }
+    blunders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::parse_moves;
+
+    #[test]
+    fn reports_on_a_game_from_a_set_up_start() {
+        let start = Position::from_setup("Kg6 Ra1 kh8 b").unwrap();
+        let moves = parse_moves("h8g8 a1a8").unwrap();
+        let report = GameReport::from_moves(&start, &moves).unwrap();
+        assert_eq!(report.plies, 2);
+        assert_eq!(report.sides.map(|side| side.captures), [0, 0]);
+        // the same moves from the usual start aren't a game
+        assert!(GameReport::from_moves(&Position::default(), &moves).is_err());
+    }
+}
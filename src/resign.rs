@@ -0,0 +1,340 @@
+use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use gmtk2024::ai::{PlayerKind, Players};
+use gmtk2024::clock::ChessClock;
+use gmtk2024::history::GameHistory;
+use gmtk2024::piece::PieceColour;
+use gmtk2024::position::Position;
+use gmtk2024::record::GameResult;
+use gmtk2024::search::{SearchConfig, SearchLimits, Searcher};
+use gmtk2024::square::{CurrentPosition, MoveEvent};
+
+use crate::analysis::analysing;
+use crate::correspondence_mode::in_correspondence;
+use crate::new_game::{setup_new_game_screen, spawn_button, NewGameEvent};
+use crate::puzzle_mode::in_puzzle;
+
+#[derive(Component)]
+struct ResignButton;
+
+#[derive(Component)]
+struct OfferDrawButton;
+
+// says what's happened to the last draw offer
+#[derive(Component)]
+struct OfferText;
+
+/// Asks whether to resign, or to take a draw that's been offered.
+#[derive(Component)]
+pub struct QuestionDialog;
+
+#[derive(Component)]
+struct QuestionText;
+
+#[derive(Component)]
+struct YesButton;
+
+#[derive(Component)]
+struct NoButton;
+
+/// Resigning, and offering, accepting and declining draws, by the side named. Sent by the
+/// buttons and the dialog, and for the other player in a game over the network.
+#[derive(Event, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResignOrDrawEvent {
+    Resign(PieceColour),
+    OfferDraw(PieceColour),
+    AcceptDraw(PieceColour),
+    DeclineDraw(PieceColour),
+}
+
+/// Sent when the game ends with a result agreed off the board: a resignation or a draw.
+#[derive(Event)]
+pub struct AgreedResultEvent(pub GameResult);
+
+// what the dialog is asking, and of which side
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Question {
+    Resign(PieceColour),
+    AcceptDraw(PieceColour),
+}
+
+/// The draw offer standing, if there is one, and whatever's being asked in the dialog.
+#[derive(Resource, Default)]
+pub struct Offers {
+    // the side whose draw offer stands, until the next move
+    draw: Option<PieceColour>,
+    // None while the dialog is closed
+    question: Option<Question>,
+    note: String,
+    // a computer making up its mind about a draw offer, and the side it's playing
+    computer_answer: Option<(PieceColour, Task<bool>)>,
+}
+
+// the side the buttons are for: the only one played here, or with both, the one to move
+fn side_played_here(players: &Players, position: &Position) -> Option<PieceColour> {
+    match (players.is_human(PieceColour::White), players.is_human(PieceColour::Black)) {
+        (true, false) => Some(PieceColour::White),
+        (false, true) => Some(PieceColour::Black),
+        (true, true) => Some(position.turn),
+        (false, false) => None,
+    }
+}
+
+// a computer takes a draw when it doesn't think it's any better off
+fn computer_accepts_draw(position: &Position, colour: PieceColour) -> bool {
+    let limits = SearchLimits { depth: 2, nodes: Some(20_000), time: None };
+    let score = Searcher::new(SearchConfig::default()).search(position, limits).score;
+    let score = if position.turn == colour { score } else { -score };
+    score <= 0
+}
+
+fn setup_resign_buttons(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 20.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+
+    // under the hint button
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(70.0),
+            right: Val::Px(10.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::End,
+            row_gap: Val::Px(10.0),
+            ..default()
+        },
+        ..default()
+    }).with_children(|column| {
+        spawn_button(column, &asset_server, 160.0, "Resign", ResignButton);
+        spawn_button(column, &asset_server, 160.0, "Offer draw", OfferDrawButton);
+        column.spawn((TextBundle::from_section("", text_style.clone()), OfferText));
+    });
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        QuestionDialog,
+    )).with_children(|parent| {
+        parent.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(16.0),
+                padding: UiRect::all(Val::Px(20.0)),
+                ..default()
+            },
+            background_color: Color::srgba(0.12, 0.1, 0.15, 0.9).into(),
+            border_radius: BorderRadius::all(Val::Px(5.0)),
+            ..default()
+        }).with_children(|column| {
+            column.spawn((
+                TextBundle::from_section("", TextStyle { font_size: 28.0, ..text_style }),
+                QuestionText,
+            ));
+            column.spawn(NodeBundle {
+                style: Style { column_gap: Val::Px(10.0), ..default() },
+                ..default()
+            }).with_children(|row| {
+                spawn_button(row, &asset_server, 120.0, "Yes", YesButton);
+                spawn_button(row, &asset_server, 120.0, "No", NoButton);
+            });
+        });
+    });
+}
+
+type ResignButtonFilter = (Changed<Interaction>, Or<(With<ResignButton>, With<OfferDrawButton>)>);
+type QuestionButtonFilter = (Changed<Interaction>, Or<(With<YesButton>, With<NoButton>)>);
+
+fn game_over(history: &GameHistory, clock: &ChessClock) -> bool {
+    history.result() != GameResult::Unfinished || clock.flagged().is_some()
+}
+
+fn resign_buttons(
+    interaction_query: Query<(&Interaction, Has<ResignButton>), ResignButtonFilter>,
+    (players, position): (Res<Players>, Res<CurrentPosition>),
+    (history, clock): (Res<GameHistory>, Res<ChessClock>),
+    mut offers: ResMut<Offers>,
+    mut resign_or_draw_writer: EventWriter<ResignOrDrawEvent>,
+) {
+    for (interaction, resign_button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed || game_over(&history, &clock) {
+            continue;
+        }
+        let Some(colour) = side_played_here(&players, &position.0) else {
+            continue;
+        };
+        if resign_button {
+            offers.question = Some(Question::Resign(colour));
+        } else if offers.draw.is_none() {
+            resign_or_draw_writer.send(ResignOrDrawEvent::OfferDraw(colour));
+        }
+    }
+}
+
+fn question_buttons(
+    interaction_query: Query<(&Interaction, Has<YesButton>), QuestionButtonFilter>,
+    mut offers: ResMut<Offers>,
+    mut resign_or_draw_writer: EventWriter<ResignOrDrawEvent>,
+) {
+    for (interaction, yes) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match offers.question.take() {
+            Some(Question::Resign(colour)) if yes => { resign_or_draw_writer.send(ResignOrDrawEvent::Resign(colour)); },
+            Some(Question::AcceptDraw(colour)) if yes => { resign_or_draw_writer.send(ResignOrDrawEvent::AcceptDraw(colour)); },
+            Some(Question::AcceptDraw(colour)) => { resign_or_draw_writer.send(ResignOrDrawEvent::DeclineDraw(colour)); },
+            _ => {},
+        }
+    }
+}
+
+pub fn resign_or_draw(
+    mut resign_or_draw_events: EventReader<ResignOrDrawEvent>,
+    mut offers: ResMut<Offers>,
+    players: Res<Players>,
+    position: Res<CurrentPosition>,
+    (mut history, mut clock): (ResMut<GameHistory>, ResMut<ChessClock>),
+    mut agreed_result_writer: EventWriter<AgreedResultEvent>,
+) {
+    for &event in resign_or_draw_events.read() {
+        if game_over(&history, &clock) {
+            continue;
+        }
+        let result = match event {
+            ResignOrDrawEvent::Resign(colour) => Some(GameResult::win_for(colour.opposite())),
+            // offers both ways at once agree on it
+            ResignOrDrawEvent::AcceptDraw(colour) | ResignOrDrawEvent::OfferDraw(colour) if offers.draw == Some(colour.opposite()) => {
+                Some(GameResult::Draw)
+            }
+            ResignOrDrawEvent::OfferDraw(colour) => {
+                let other = colour.opposite();
+                offers.draw = Some(colour);
+                offers.note = format!("{} offers a draw", colour.name());
+                match players.get(other) {
+                    PlayerKind::Human => {
+                        offers.question = Some(Question::AcceptDraw(other));
+                        None
+                    }
+                    // it answers once it's searched, like any other player
+                    PlayerKind::Computer(_) => {
+                        let position = position.0.clone();
+                        let task = AsyncComputeTaskPool::get().spawn(async move { computer_accepts_draw(&position, other) });
+                        offers.computer_answer = Some((other, task));
+                        None
+                    }
+                    // someone on another computer, who answers over the network
+                    PlayerKind::Scripted => None,
+                }
+            }
+            ResignOrDrawEvent::DeclineDraw(colour) if offers.draw == Some(colour.opposite()) => {
+                offers.draw = None;
+                offers.note = format!("{} declines the draw", colour.name());
+                None
+            }
+            ResignOrDrawEvent::AcceptDraw(_) | ResignOrDrawEvent::DeclineDraw(_) => None,
+        };
+        if let Some(result) = result {
+            history.set_result(result);
+            clock.stop();
+            *offers = Offers::default();
+            agreed_result_writer.send(AgreedResultEvent(result));
+        }
+    }
+}
+
+// the computer's answer to a draw offer, once it has one
+fn answer_draw_offer(mut offers: ResMut<Offers>, mut resign_or_draw_writer: EventWriter<ResignOrDrawEvent>) {
+    // polling isn't a change, the note only changes with the answer
+    let Some((colour, task)) = offers.bypass_change_detection().computer_answer.as_mut() else {
+        return;
+    };
+    let Some(accepts) = block_on(future::poll_once(task)) else {
+        return;
+    };
+    let colour = *colour;
+    offers.computer_answer = None;
+    resign_or_draw_writer.send(if accepts {
+        ResignOrDrawEvent::AcceptDraw(colour)
+    } else {
+        ResignOrDrawEvent::DeclineDraw(colour)
+    });
+}
+
+// a draw offer only stands until the next move, and a new game starts with none
+fn withdraw_offers(
+    mut move_events: EventReader<MoveEvent>,
+    mut new_game_events: EventReader<NewGameEvent>,
+    mut offers: ResMut<Offers>,
+) {
+    if new_game_events.read().count() > 0 {
+        *offers = Offers::default();
+    }
+    if move_events.read().count() == 0 {
+        return;
+    }
+    if offers.draw.is_some() || !offers.note.is_empty() {
+        offers.draw = None;
+        offers.note.clear();
+        offers.computer_answer = None;
+    }
+    if matches!(offers.question, Some(Question::AcceptDraw(_))) {
+        offers.question = None;
+    }
+}
+
+fn update_offers(
+    offers: Res<Offers>,
+    mut dialog_visibility: Query<&mut Visibility, With<QuestionDialog>>,
+    mut question_text: Query<&mut Text, With<QuestionText>>,
+    mut offer_text: Query<&mut Text, (With<OfferText>, Without<QuestionText>)>,
+) {
+    *dialog_visibility.single_mut() = match offers.question {
+        Some(_) => Visibility::Visible,
+        None => Visibility::Hidden,
+    };
+    if let Some(question) = offers.question {
+        question_text.single_mut().sections[0].value = match question {
+            Question::Resign(colour) => format!("Resign the game as {}?", colour.name()),
+            Question::AcceptDraw(colour) => format!(
+                "{} offers a draw. Accept it, {}?",
+                colour.opposite().name(),
+                colour.name(),
+            ),
+        };
+    }
+    offer_text.single_mut().sections[0].value.clone_from(&offers.note);
+}
+
+pub struct ResignPlugin;
+impl Plugin for ResignPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Offers::default())
+            .add_event::<ResignOrDrawEvent>()
+            .add_event::<AgreedResultEvent>()
+            // under the new game screen
+            .add_systems(Startup, setup_resign_buttons.before(setup_new_game_screen))
+            .add_systems(Update, (
+                resign_buttons.run_if(not(analysing).and_then(not(in_puzzle)).and_then(not(in_correspondence))),
+                question_buttons,
+                answer_draw_offer,
+                resign_or_draw,
+                withdraw_offers,
+                update_offers.run_if(resource_changed::<Offers>),
+            ).chain());
+    }
+}
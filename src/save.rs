@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use crate::ai::{AiSettings, Difficulty, Personality, PlayerKind, Players};
+use crate::clock::{ChessClock, TimeControl};
+use crate::history::GameHistory;
+use crate::piece::PieceColour;
+use crate::record::GameRecord;
+
+/// The version written into new saves. Bump it when the format changes, and teach
+/// `SavedGame::parse` to read the versions before.
+pub const SAVE_VERSION: u32 = 1;
+
+/// A game put aside to be carried on later: its moves, who's playing and their clocks. Saved as
+/// a game record with the rest in tags:
+///
+/// ```text
+/// [SaveVersion "1"]
+/// [WhitePlayer "human"]
+/// [BlackPlayer "computer club glutton"]
+/// [TimeControl "5+0"]
+/// [WhiteTime "287400"]
+/// [BlackTime "291050"]
+///
+/// 1. e2e4 e7e5 *
+/// ```
+///
+/// with the times left in milliseconds. A record without a `SaveVersion` tag, like
+/// analysis.txt, is read as a game between two people without a clock.
+#[derive(Clone)]
+pub struct SavedGame {
+    pub history: GameHistory,
+    pub players: Players,
+    pub control: Option<TimeControl>,
+    // white's, then black's
+    pub remaining: [Duration; 2],
+}
+
+fn player_text(kind: PlayerKind) -> Option<String> {
+    match kind {
+        PlayerKind::Human => Some("human".to_string()),
+        PlayerKind::Computer(settings) => {
+            Some(format!("computer {} {}", settings.difficulty.name(), settings.personality.name()))
+        }
+        // its moves came from somewhere that won't be there next time
+        PlayerKind::Scripted => None,
+    }
+}
+
+fn parse_player(text: &str) -> Result<PlayerKind, String> {
+    match text.split_whitespace().collect::<Vec<_>>()[..] {
+        ["human"] => Ok(PlayerKind::Human),
+        ["computer", difficulty, personality] => {
+            let difficulty = Difficulty::from_name(difficulty).ok_or(format!("unknown difficulty {difficulty}"))?;
+            let personality = Personality::from_name(personality).ok_or(format!("unknown personality {personality}"))?;
+            Ok(PlayerKind::Computer(AiSettings { difficulty, personality }))
+        }
+        _ => Err(format!("can't read player {text:?}")),
+    }
+}
+
+impl SavedGame {
+    /// The game on the board, from where the clock has got to. None if one of the players
+    /// isn't at this computer.
+    pub fn new(history: &GameHistory, players: Players, clock: &ChessClock) -> Option<Self> {
+        player_text(players.white)?;
+        player_text(players.black)?;
+        Some(Self {
+            history: history.clone(),
+            players,
+            control: clock.control(),
+            remaining: [clock.remaining(PieceColour::White), clock.remaining(PieceColour::Black)],
+        })
+    }
+
+    /// A clock like the one the game was saved with, running for the side to move unless the
+    /// game is over.
+    pub fn clock(&self) -> ChessClock {
+        let mut clock = ChessClock::new(self.control);
+        for (colour, remaining) in [(PieceColour::White, self.remaining[0]), (PieceColour::Black, self.remaining[1])] {
+            clock.set_remaining(colour, remaining);
+        }
+        if self.history.position().outcome().is_none() {
+            clock.start(self.history.position().turn);
+        }
+        clock
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut record = self.history.to_record();
+        let mut tags = vec![("SaveVersion".to_string(), SAVE_VERSION.to_string())];
+        for colour in [PieceColour::White, PieceColour::Black] {
+            let player = player_text(self.players.get(colour)).unwrap_or_else(|| "human".to_string());
+            tags.push((format!("{}Player", colour.name()), player));
+        }
+        if let Some(control) = self.control {
+            tags.push(("TimeControl".to_string(), control.name()));
+            tags.push(("WhiteTime".to_string(), self.remaining[0].as_millis().to_string()));
+            tags.push(("BlackTime".to_string(), self.remaining[1].as_millis().to_string()));
+        }
+        // ahead of the game's own, so the version is the first thing in the file
+        tags.append(&mut record.tags);
+        record.tags = tags;
+        record.to_string()
+    }
+
+    /// Reads a save written by `to_text`, by this version of the game or an earlier one.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let record = GameRecord::parse(text)?;
+        let version = match record.tag("SaveVersion") {
+            Some(version) => version.parse().map_err(|_| format!("can't read save version {version}"))?,
+            // a plain game record, from before there were saves
+            None => 0,
+        };
+        if version > SAVE_VERSION {
+            return Err(format!("it was saved by a newer version of the game (save version {version})"));
+        }
+        let mut history = GameHistory::from_record(&record)?;
+        history.to_end();
+        let mut saved = Self {
+            history,
+            players: Players::default(),
+            control: None,
+            remaining: [Duration::ZERO; 2],
+        };
+        if version == 0 {
+            return Ok(saved);
+        }
+        for colour in [PieceColour::White, PieceColour::Black] {
+            let tag = format!("{}Player", colour.name());
+            let player = record.tag(&tag).ok_or(format!("the {tag} tag is missing"))?;
+            *saved.players.get_mut(colour) = parse_player(player)?;
+        }
+        if let Some(control) = record.tag("TimeControl") {
+            saved.control = Some(TimeControl::parse(control)?);
+            for (i, tag) in ["WhiteTime", "BlackTime"].into_iter().enumerate() {
+                let time = record.tag(tag).ok_or(format!("the {tag} tag is missing"))?;
+                let millis = time.parse().map_err(|_| format!("can't read {tag} {time}"))?;
+                saved.remaining[i] = Duration::from_millis(millis);
+            }
+        }
+        Ok(saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Move;
+
+    fn moves(moves: &[&str]) -> Vec<Move> {
+        moves.iter().map(|mv| Move::from_notation(mv).unwrap()).collect()
+    }
+
+    #[test]
+    fn plain_record_loads_as_version_zero() {
+        let saved = SavedGame::parse("[White \"Ann\"]\n\n1. e2e4 e7e5 2. g1f3 *\n").unwrap();
+        assert_eq!(saved.history.line(), moves(&["e2e4", "e7e5", "g1f3"]));
+        assert_eq!((saved.players.white, saved.players.black), (PlayerKind::Human, PlayerKind::Human));
+        assert_eq!(saved.control, None);
+    }
+
+    #[test]
+    fn version_one_save_loads() {
+        let text = "[SaveVersion \"1\"]\n[WhitePlayer \"human\"]\n[BlackPlayer \"computer club glutton\"]\n\
+                    [TimeControl \"5+0\"]\n[WhiteTime \"287400\"]\n[BlackTime \"291050\"]\n\n1. e2e4 e7e5 *\n";
+        let saved = SavedGame::parse(text).unwrap();
+        assert_eq!(saved.history.line(), moves(&["e2e4", "e7e5"]));
+        assert_eq!(saved.players.white, PlayerKind::Human);
+        let computer = AiSettings { difficulty: Difficulty::Club, personality: Personality::Glutton };
+        assert_eq!(saved.players.black, PlayerKind::Computer(computer));
+        assert_eq!(saved.control, Some(TimeControl::new(5, 0)));
+        assert_eq!(saved.remaining, [Duration::from_millis(287400), Duration::from_millis(291050)]);
+    }
+
+    #[test]
+    fn save_from_a_newer_version_is_rejected() {
+        let err = SavedGame::parse("[SaveVersion \"2\"]\n[WhitePlayer \"human\"]\n[BlackPlayer \"human\"]\n\n1. e2e4 *\n").err();
+        assert_eq!(err.as_deref(), Some("it was saved by a newer version of the game (save version 2)"));
+    }
+
+    #[test]
+    fn saves_read_back_the_same() {
+        let mut history = GameHistory::default();
+        for mv in moves(&["e2e4", "e7e5", "g1f3"]) {
+            history.play(mv);
+        }
+        let players = Players { white: PlayerKind::Computer(AiSettings::default()), black: PlayerKind::Human };
+        let mut clock = ChessClock::new(Some(TimeControl::with_delay(3, 2)));
+        clock.set_remaining(PieceColour::White, Duration::from_millis(170_500));
+        let saved = SavedGame::new(&history, players, &clock).unwrap();
+        let loaded = SavedGame::parse(&saved.to_text()).unwrap();
+        assert_eq!(loaded.history.line(), history.line());
+        assert_eq!((loaded.players.white, loaded.players.black), (players.white, players.black));
+        assert_eq!(loaded.control, saved.control);
+        assert_eq!(loaded.remaining, saved.remaining);
+    }
+}
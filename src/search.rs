@@ -0,0 +1,634 @@
+use bevy::tasks::ComputeTaskPool;
+use bevy::utils::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::eval::{evaluate, EvalWeights};
+use crate::piece::Piece;
+use crate::position::{Move, Position};
+use crate::tablebase::tablebases;
+
+pub const MATE_SCORE: i32 = 30_000;
+const INFINITY: i32 = MATE_SCORE + 1;
+const MAX_PLY: usize = 64;
+
+// how much one extra square of growth is worth when ordering captures, in centipawns
+const GROWTH_ORDER_VALUE: i32 = 100;
+
+/// How many squares a piece would grow by if it captured with this move.
+pub fn growth(piece: &Piece, to: (u8, u8)) -> usize {
+    let mut grown = piece.clone();
+    grown.consume_piece(to.0, to.1);
+    grown.squares_occupied.len() - piece.squares_occupied.len()
+}
+
+/// MVV-LVA, except a capture that makes the capturing piece bigger is worth trying before an
+/// equal capture that doesn't.
+pub fn capture_order_score(position: &Position, mv: Move, captured: &[usize], weights: &EvalWeights) -> i32 {
+    let attacker = position.pieces.iter()
+        .find(|piece| (piece.x, piece.y) == mv.from)
+        .expect("no piece on the square moved from");
+    let victims: i32 = captured.iter()
+        .map(|&i| weights.material(&position.pieces[i]))
+        .sum();
+    victims * 10 + growth(attacker, mv.to) as i32 * GROWTH_ORDER_VALUE - weights.material(attacker)
+}
+
+fn square_index(square: (u8, u8)) -> usize {
+    square.1 as usize * 8 + square.0 as usize
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Bound {
+    Exact = 0,
+    Lower = 1,
+    Upper = 2,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TtEntry {
+    key: u64,
+    depth: u8,
+    score: i32,
+    bound: Bound,
+    best_move: Option<Move>,
+}
+
+// set on every entry that has been written, so an empty slot never matches a hash
+const TT_ENTRY_VALID: u64 = 1 << 63;
+
+impl TtEntry {
+    // score in the low 32 bits, then depth, bound and the best move
+    fn pack(&self) -> u64 {
+        let best_move = match self.best_move {
+            Some(mv) => 1 << 12 | (square_index(mv.from) as u64) << 6 | square_index(mv.to) as u64,
+            None => 0,
+        };
+        TT_ENTRY_VALID
+            | self.score as u32 as u64
+            | (self.depth as u64) << 32
+            | (self.bound as u64) << 40
+            | best_move << 42
+    }
+
+    fn unpack(key: u64, data: u64) -> Self {
+        let bound = match (data >> 40) & 0b11 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        let best_move = (data >> 42) & 0x1fff;
+        let square = |index: u64| ((index % 8) as u8, (index / 8) as u8);
+        Self {
+            key,
+            depth: (data >> 32) as u8,
+            score: data as u32 as i32,
+            bound,
+            best_move: if best_move & 1 << 12 != 0 {
+                Some(Move { from: square(best_move >> 6 & 0x3f), to: square(best_move & 0x3f) })
+            } else {
+                None
+            },
+        }
+    }
+}
+
+struct TtSlot {
+    // the key is stored xored with the data, so if two threads write the same slot at once
+    // the torn entry just fails to match instead of giving a wrong result
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+/// Fixed-size transposition table indexed by the low bits of the position hash. It's
+/// lockless so every search thread can share one.
+pub struct TranspositionTable {
+    slots: Vec<TtSlot>,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let max_slots = (size_mb * 1024 * 1024 / std::mem::size_of::<TtSlot>()).max(1);
+        // round down to a power of two so the index is just a mask
+        let len = 1 << max_slots.ilog2();
+        Self {
+            slots: (0..len).map(|_| TtSlot { key: AtomicU64::new(0), data: AtomicU64::new(0) }).collect(),
+        }
+    }
+
+    fn slot(&self, key: u64) -> &TtSlot {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+
+    fn probe(&self, key: u64) -> Option<TtEntry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        if data & TT_ENTRY_VALID != 0 && slot.key.load(Ordering::Relaxed) ^ data == key {
+            Some(TtEntry::unpack(key, data))
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, entry: TtEntry) {
+        let slot = self.slot(entry.key);
+        // keep a deeper result for the same position, otherwise always replace
+        if let Some(old) = self.probe(entry.key) {
+            if old.depth > entry.depth {
+                return;
+            }
+        }
+        let data = entry.pack();
+        slot.key.store(entry.key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// mate scores are stored relative to the position they were found in, not the root
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_SCORE - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score < -MATE_SCORE + MAX_PLY as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_SCORE - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score < -MATE_SCORE + MAX_PLY as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// Where the extra threads of a parallel search come from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ThreadPool {
+    /// Plain OS threads, for the headless binaries.
+    Std,
+    /// Bevy's compute task pool, for searching from inside the game.
+    Bevy,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchConfig {
+    pub transposition_table: bool,
+    pub move_ordering: bool,
+    pub tt_size_mb: usize,
+    // 1 searches on the calling thread only, which gives the same result every time
+    pub threads: usize,
+    pub thread_pool: ThreadPool,
+    pub weights: EvalWeights,
+    // up to this many centipawns are added to or taken off every evaluation, picked from the
+    // position hash and the seed, so weaker players don't always find the same move
+    pub eval_noise: i32,
+    pub seed: u64,
+    // look positions up in any endgame tables that have been loaded or generated
+    pub tablebases: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            transposition_table: true,
+            move_ordering: true,
+            tt_size_mb: 16,
+            threads: 1,
+            thread_pool: ThreadPool::Std,
+            weights: EvalWeights::default(),
+            eval_noise: 0,
+            seed: 0,
+            tablebases: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchLimits {
+    pub depth: u8,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u8) -> Self {
+        Self { depth, nodes: None, time: None }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    // centipawns from the point of view of the side to move
+    pub score: i32,
+    pub depth: u8,
+    pub nodes: u64,
+    pub pv: Vec<Move>,
+}
+
+// what all the threads of one search share
+struct SharedState {
+    tt: TranspositionTable,
+    stop: AtomicBool,
+    nodes: AtomicU64,
+}
+
+pub struct Searcher {
+    config: SearchConfig,
+    shared: SharedState,
+    history: Vec<[u32; 64]>,
+}
+
+impl Searcher {
+    pub fn new(config: SearchConfig) -> Self {
+        Self {
+            config,
+            shared: SharedState {
+                tt: TranspositionTable::new(if config.transposition_table { config.tt_size_mb } else { 0 }),
+                stop: AtomicBool::new(false),
+                nodes: AtomicU64::new(0),
+            },
+            history: vec![[0; 64]; 64],
+        }
+    }
+
+    /// Forgets everything learned from earlier searches, e.g. when a new game starts.
+    pub fn clear(&mut self) {
+        self.shared.tt.clear();
+        self.history.fill([0; 64]);
+    }
+
+    /// Iterative deepening up to the depth limit. If the node or time limit runs out the
+    /// result of the last completed depth is returned.
+    ///
+    /// With more than one thread this is a lazy SMP search: helper threads search the same
+    /// position (half of them a ply deeper) and only share the transposition table, and the
+    /// result is whatever the main thread finds.
+    pub fn search(&mut self, position: &Position, limits: SearchLimits) -> SearchResult {
+        self.shared.stop.store(false, Ordering::Relaxed);
+        self.shared.nodes.store(0, Ordering::Relaxed);
+        if self.config.tablebases {
+            if let Some(result) = tablebase_result(position, limits.depth) {
+                return result;
+            }
+        }
+        let start = Instant::now();
+        let threads = self.config.threads.max(1);
+
+        let mut main = Worker::new(&self.config, &self.shared, limits, start, self.history.clone());
+        let helpers: Vec<Worker> = (1..threads)
+            .map(|_| Worker::new(&self.config, &self.shared, limits, start, self.history.clone()))
+            .collect();
+        let stop = &self.shared.stop;
+
+        let mut result = if helpers.is_empty() {
+            main.iterate(position, 1)
+        } else {
+            match self.config.thread_pool {
+                ThreadPool::Std => std::thread::scope(|scope| {
+                    for (id, mut helper) in helpers.into_iter().enumerate() {
+                        scope.spawn(move || helper.iterate(position, 1 + (id % 2) as u8));
+                    }
+                    let result = main.iterate(position, 1);
+                    stop.store(true, Ordering::Relaxed);
+                    result
+                }),
+                ThreadPool::Bevy => {
+                    let main = &mut main;
+                    ComputeTaskPool::get().scope(|scope| {
+                        for (id, mut helper) in helpers.into_iter().enumerate() {
+                            scope.spawn(async move {
+                                helper.iterate(position, 1 + (id % 2) as u8);
+                                None
+                            });
+                        }
+                        scope.spawn(async move {
+                            let result = main.iterate(position, 1);
+                            stop.store(true, Ordering::Relaxed);
+                            Some(result)
+                        });
+                    }).into_iter().flatten().next().unwrap_or_default()
+                },
+            }
+        };
+
+        self.history = main.history;
+        result.nodes = self.shared.nodes.load(Ordering::Relaxed);
+        if result.best_move.is_none() {
+            // stopped before finishing even depth 1
+            result.best_move = position.legal_moves().first().copied();
+        }
+        result
+    }
+}
+
+// a perfect result straight from the endgame tables, with the line to mate as the PV
+fn tablebase_result(position: &Position, depth: u8) -> Option<SearchResult> {
+    let (best_move, probe) = tablebases().best_move(position)?;
+    let mut pv = vec![best_move];
+    let mut line = position.clone();
+    line.make_move(best_move);
+    while pv.len() < MAX_PLY {
+        let Some((mv, _)) = tablebases().best_move(&line) else {
+            break;
+        };
+        pv.push(mv);
+        line.make_move(mv);
+    }
+    Some(SearchResult { best_move: Some(best_move), score: probe.score(0), depth, nodes: 0, pv })
+}
+
+// one thread of a search
+struct Worker<'a> {
+    config: &'a SearchConfig,
+    shared: &'a SharedState,
+    limits: SearchLimits,
+    start: Instant,
+    killers: [[Option<Move>; 2]; MAX_PLY],
+    history: Vec<[u32; 64]>,
+    root_best: Option<Move>,
+}
+
+impl<'a> Worker<'a> {
+    fn new(
+        config: &'a SearchConfig,
+        shared: &'a SharedState,
+        limits: SearchLimits,
+        start: Instant,
+        history: Vec<[u32; 64]>,
+    ) -> Self {
+        Self {
+            config,
+            shared,
+            limits,
+            start,
+            killers: [[None; 2]; MAX_PLY],
+            history,
+            root_best: None,
+        }
+    }
+
+    fn iterate(&mut self, position: &Position, first_depth: u8) -> SearchResult {
+        let mut result = SearchResult::default();
+        for depth in first_depth..=self.limits.depth.max(1) {
+            let mut pv = Vec::new();
+            let score = self.negamax(position, depth, 0, -INFINITY, INFINITY, &mut pv);
+            if self.stopped() {
+                break;
+            }
+            self.root_best = pv.first().copied();
+            result = SearchResult { best_move: self.root_best, score, depth, nodes: 0, pv };
+        }
+        result
+    }
+
+    fn stopped(&self) -> bool {
+        self.shared.stop.load(Ordering::Relaxed)
+    }
+
+    // the same position always gets the same noise within a search, so the transposition
+    // table stays consistent
+    fn eval_noise(&self, hash: u64) -> i32 {
+        let noise = self.config.eval_noise;
+        if noise <= 0 {
+            return 0;
+        }
+        let mut z = hash ^ self.config.seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z % (2 * noise as u64 + 1)) as i32 - noise
+    }
+
+    fn probe_tablebases(&self, position: &Position, ply: usize) -> Option<i32> {
+        if !self.config.tablebases || ply == 0 {
+            return None;
+        }
+        tablebases().probe(position).map(|probe| probe.score(ply))
+    }
+
+    fn should_stop(&self) -> bool {
+        if self.limits.nodes.is_some_and(|nodes| self.shared.nodes.load(Ordering::Relaxed) >= nodes)
+            || self.limits.time.is_some_and(|time| self.start.elapsed() >= time) {
+            self.shared.stop.store(true, Ordering::Relaxed);
+        }
+        self.stopped()
+    }
+
+    fn count_node(&self) {
+        self.shared.nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn negamax(
+        &mut self,
+        position: &Position,
+        depth: u8,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        pv.clear();
+        if self.should_stop() {
+            return 0;
+        }
+        if ply > 0 && position.repetitions() > 0 {
+            return 0;
+        }
+        if let Some(score) = self.probe_tablebases(position, ply) {
+            return score;
+        }
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(position, ply, alpha, beta);
+        }
+        self.count_node();
+
+        let original_alpha = alpha;
+        let mut hash_move = if ply == 0 { self.root_best } else { None };
+        if self.config.transposition_table {
+            if let Some(entry) = self.shared.tt.probe(position.hash) {
+                hash_move = entry.best_move.or(hash_move);
+                if ply > 0 && entry.depth >= depth {
+                    let score = score_from_tt(entry.score, ply);
+                    match entry.bound {
+                        Bound::Exact => return score,
+                        Bound::Lower if score >= beta => return score,
+                        Bound::Upper if score <= alpha => return score,
+                        _ => {},
+                    }
+                }
+            }
+        }
+
+        let moves = position.legal_moves();
+        if moves.is_empty() {
+            return if position.in_check() { -MATE_SCORE + ply as i32 } else { 0 };
+        }
+
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        let mut child_pv = Vec::new();
+        for (mv, is_capture) in self.order_moves(position, moves, hash_move, ply) {
+            let mut child = position.clone();
+            child.make_move(mv);
+            let score = -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+            if self.stopped() {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(mv);
+                    pv.extend_from_slice(&child_pv);
+                }
+            }
+            if alpha >= beta {
+                if !is_capture && self.config.move_ordering {
+                    if self.killers[ply][0] != Some(mv) {
+                        self.killers[ply][1] = self.killers[ply][0];
+                        self.killers[ply][0] = Some(mv);
+                    }
+                    self.history[square_index(mv.from)][square_index(mv.to)] += depth as u32 * depth as u32;
+                }
+                break;
+            }
+        }
+
+        if self.config.transposition_table {
+            let bound = if best_score <= original_alpha {
+                Bound::Upper
+            } else if best_score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.shared.tt.store(TtEntry {
+                key: position.hash,
+                depth,
+                score: score_to_tt(best_score, ply),
+                bound,
+                best_move,
+            });
+        }
+        best_score
+    }
+
+    // only captures from here on, so the search doesn't stop halfway through an exchange
+    fn quiescence(&mut self, position: &Position, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        self.count_node();
+        if let Some(score) = self.probe_tablebases(position, ply) {
+            return score;
+        }
+
+        let stand_pat = evaluate(position, &self.config.weights) + self.eval_noise(position.hash);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let captures = position.legal_moves().into_iter()
+            .filter(|&mv| position.is_capture(mv))
+            .collect();
+        for (mv, _) in self.order_moves(position, captures, None, ply) {
+            let mut child = position.clone();
+            child.make_move(mv);
+            let score = -self.quiescence(&child, ply + 1, -beta, -alpha);
+            if self.stopped() {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    // hash move, then captures by growth-adjusted MVV-LVA, then killers, then quiet moves by
+    // history. Also says which moves are captures.
+    fn order_moves(
+        &self,
+        position: &Position,
+        moves: Vec<Move>,
+        hash_move: Option<Move>,
+        ply: usize,
+    ) -> Vec<(Move, bool)> {
+        let mut scored: Vec<(Move, bool, i32)> = moves.into_iter().map(|mv| {
+            let captured = position.captured_by(mv);
+            let is_capture = !captured.is_empty();
+            if !self.config.move_ordering {
+                return (mv, is_capture, 0);
+            }
+            let score = if Some(mv) == hash_move {
+                i32::MAX
+            } else if is_capture {
+                2_000_000 + capture_order_score(position, mv, &captured, &self.config.weights)
+            } else if self.killers[ply][0] == Some(mv) {
+                1_500_000
+            } else if self.killers[ply][1] == Some(mv) {
+                1_400_000
+            } else {
+                self.history[square_index(mv.from)][square_index(mv.to)].min(1_000_000) as i32
+            };
+            (mv, is_capture, score)
+        }).collect();
+        if self.config.move_ordering {
+            scored.sort_by_key(|&(_, _, score)| std::cmp::Reverse(score));
+        }
+        scored.into_iter().map(|(mv, is_capture, _)| (mv, is_capture)).collect()
+    }
+}
+
+/// Looks for the best move for the side to move.
+pub fn best_move(position: &Position, limits: SearchLimits) -> SearchResult {
+    Searcher::new(SearchConfig::default()).search(position, limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // few enough pieces to search quickly, with captures to grow into
+    fn middlegame() -> Position {
+        Position::from_setup("Kg1 Rf1 Pf2 Pg2 Ph2 Nc3 Pe4 kg8 rf8 pf7 pg7 ph7 nc6 pd5 b").unwrap()
+    }
+
+    fn search(threads: usize) -> SearchResult {
+        let config = SearchConfig { threads, tablebases: false, ..SearchConfig::default() };
+        Searcher::new(config).search(&middlegame(), SearchLimits::depth(4))
+    }
+
+    #[test]
+    fn single_threaded_search_is_deterministic() {
+        let first = search(1);
+        let second = search(1);
+        assert!(first.best_move.is_some());
+        assert_eq!(first.best_move, second.best_move);
+        assert_eq!(first.score, second.score);
+        assert_eq!(first.pv, second.pv);
+    }
+
+    #[test]
+    fn multithreaded_search_finds_a_legal_move() {
+        let result = search(4);
+        let best_move = result.best_move.expect("no move found");
+        assert!(middlegame().is_legal(best_move), "{best_move} isn't legal");
+    }
+}
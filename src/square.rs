@@ -1,6 +1,11 @@
 use bevy::prelude::*;
 use bevy_mod_picking::prelude::*;
-use crate::piece::{is_colour_in_checkmate, Piece, PieceColour, PieceType};
+use crate::ai::Players;
+use crate::clock::ChessClock;
+use crate::history::GameHistory;
+use crate::piece::{is_colour_in_checkmate, spawn_pieces, Piece, PieceColour, PieceType};
+use crate::position::{self, Position};
+use crate::zobrist::{castling_hash, en_passant_file_hash, en_passant_hash, piece_hash, turn_hash, PositionHash};
 
 #[derive(Event)]
 pub struct CheckmateEvent(pub PieceColour);
@@ -11,6 +16,15 @@ pub struct ConsumeEvent { pub piece_entity: Entity }
 #[derive(Event)]
 pub struct MoveEvent;
 
+/// Asks for a move to be played on the board. Sent for clicks as well as computer moves.
+#[derive(Event)]
+pub struct PlayMoveEvent(pub position::Move);
+
+/// Puts a whole position on the board in place of the one there, e.g. one from earlier in
+/// the game. Unlike `PlayMoveEvent` nothing is recorded in the game's history.
+#[derive(Event)]
+pub struct SetPositionEvent(pub Position);
+
 #[derive(Default, Resource)]
 pub struct SelectedSquare {
     pub entity: Option<Entity>,
@@ -38,6 +52,19 @@ impl Default for PlayerTurn {
     }
 }
 
+/// A move suggested to the player, drawn on the board until the position changes.
+#[derive(Resource, Default)]
+pub struct Hint {
+    // squares the piece to move covers now
+    pub from: Vec<(u8, u8)>,
+    // and the ones it will cover after the move, more of them if it grows
+    pub to: Vec<(u8, u8)>,
+}
+
+/// The board as a headless `Position`, kept in step with the pieces by `play_move`.
+#[derive(Resource, Default)]
+pub struct CurrentPosition(pub Position);
+
 impl Square {
     fn is_white(&self) -> bool {
         (self.x + self.y) % 2 == 0
@@ -45,18 +72,13 @@ impl Square {
 }
 
 fn select_square(
-    mut commands: Commands,
     mut selected_square: ResMut<SelectedSquare>,
     mut selected_piece: ResMut<SelectedPiece>,
     mut click_event: EventReader<Pointer<Click>>,
-    mut turn: ResMut<PlayerTurn>,
+    turn: Res<PlayerTurn>,
     squares_query: Query<(Entity, &Square)>,
-    mut pieces_query: Query<(Entity, &mut Piece)>,
-    mut checkmate_writer: EventWriter<CheckmateEvent>,
-    mut consume_writer: EventWriter<ConsumeEvent>,
-    mut castle_writer: EventWriter<CastleEvent>,
-    mut move_writer: EventWriter<MoveEvent>,
-    asset_server: Res<AssetServer>,
+    pieces_query: Query<(Entity, &Piece)>,
+    mut play_move_writer: EventWriter<PlayMoveEvent>,
 ) {
 
     for event in click_event.read() {
@@ -64,84 +86,12 @@ fn select_square(
             selected_square.entity = Some(square_entity);
 
             if let Some(selected_piece_entity) = selected_piece.entity {
-            	let pieces_entity_vec: Vec<(Entity, Piece)> = pieces_query
-                    .iter_mut()
-                    .map(|(entity, piece)| {
-                        (
-                            entity,
-                            piece.clone(),
-                        )
-                    })
-                    .collect();
-            	let pieces_vec = pieces_query.iter_mut().map(|(_, piece)| piece.clone()).collect();
+            	let pieces_vec = pieces_query.iter().map(|(_, piece)| piece.clone()).collect();
 
                 // Move the selected piece to the selected square
-                if let Ok((_piece_entity, mut piece)) = pieces_query.get_mut(selected_piece_entity) {
+                if let Ok((_piece_entity, piece)) = pieces_query.get(selected_piece_entity) {
                 	if piece.is_move_playable((square.x, square.y), &pieces_vec) {
-                		let mut captured_piece = false;
-                		for (dx, dy) in piece.squares_occupied.clone() {
-                			let square_x = square.x.checked_add_signed(dx).expect("x < 0");
-                			let square_y = square.y.checked_add_signed(dy).expect("y < 0");
-	                        // Check if a piece of the opposite color exists in this square and despawn it
-	                        for (other_entity, other_piece) in &pieces_entity_vec {
-                                if other_piece.colour == piece.colour.opposite() {
-                                    if other_piece.occupies_square((square_x, square_y)) {
-    	                                // Despawn piece
-                                        consume_writer.send(ConsumeEvent { piece_entity: *other_entity });
-    	                                captured_piece = true;
-                                    } else if other_piece.can_en_passant && (
-                                        (piece.colour == PieceColour::White && other_piece.occupies_square((square_x, square_y - 1)))
-                                        || (piece.colour == PieceColour::Black && other_piece.occupies_square((square_x, square_y + 1)))
-                                    ) {
-                                        // Despawn piece
-                                        consume_writer.send(ConsumeEvent { piece_entity: *other_entity });
-                                        captured_piece = true;
-                                    }
-                                }
-	                        }
-	                    }
-	                    if captured_piece {
-                            piece.consume_piece(square.x, square.y);
-                        }
-	                    piece.transform.translation = Vec3::new(square.x as f32, 0., square.y as f32) + piece.offset;
-
-                        // check if move is a castle, and if so, move rook
-                        if piece.piece_type == PieceType::King && piece.x.abs_diff(square.x) == 2 {
-                            if square.x < piece.x {
-                                castle_writer.send(CastleEvent::Queenside(piece.colour));
-                            } else {
-                                castle_writer.send(CastleEvent::Kingside(piece.colour));
-                            }
-                        }
-
-                        // check if move is a pawn 2 move
-                        if piece.piece_type == PieceType::Pawn && piece.y.abs_diff(square.y) == 2 {
-                            piece.can_en_passant = true;
-                        }
-
-                        // move piece
-	                    piece.x = square.x;
-	                    piece.y = square.y;
-                        piece.has_moved = true;
-                        piece.just_moved = true;
-
-	                    // switch turns
-	                    turn.0 = match turn.0 {
-                            PieceColour::White => PieceColour::Black,
-                            PieceColour::Black => PieceColour::White,
-                        };
-
-                        let new_pieces_vec = pieces_query.iter().map(|(_, piece)| piece.clone()).collect();
-                        if is_colour_in_checkmate(turn.0, &new_pieces_vec) {
-                            checkmate_writer.send(CheckmateEvent(turn.0));
-                        }
-
-                        commands.spawn(AudioBundle {
-                            source: asset_server.load("audio/click.wav"),
-                            ..default()
-                        });
-                        
-                        move_writer.send(MoveEvent);
+                        play_move_writer.send(PlayMoveEvent(position::Move { from: (piece.x, piece.y), to: (square.x, square.y) }));
 
                         // deselect square and piece
                         selected_square.entity = None;
@@ -149,11 +99,11 @@ fn select_square(
 	                } else {
                         selected_piece.entity = None;
                         // Select the piece in the currently selected square
-                        for (piece_entity, piece) in &pieces_entity_vec {
+                        for (piece_entity, piece) in pieces_query.iter() {
                             for (dx, dy) in &piece.squares_occupied {
                                 if piece.x as i8 + dx == square.x as i8 && piece.y as i8 + dy == square.y as i8 && piece.colour == turn.0 {
                                     // piece_entity is now the entity in the same square
-                                    selected_piece.entity = Some(*piece_entity);
+                                    selected_piece.entity = Some(piece_entity);
                                     break;
                                 }
                             }
@@ -165,7 +115,7 @@ fn select_square(
                 }
             } else {
                 // Select the piece in the currently selected square
-                for (piece_entity, piece) in pieces_query.iter_mut() {
+                for (piece_entity, piece) in pieces_query.iter() {
 					for (dx, dy) in &piece.squares_occupied {
 	                    if piece.x as i8 + dx == square.x as i8 && piece.y as i8 + dy == square.y as i8 && piece.colour == turn.0 {
 	                        // piece_entity is now the entity in the same square
@@ -179,6 +129,176 @@ fn select_square(
     }
 }
 
+// the computer moves for itself
+fn human_to_move(turn: Res<PlayerTurn>, players: Res<Players>) -> bool {
+    players.is_human(turn.0)
+}
+
+// Plays moves from clicks and from anything else that can move (the computer, replays, the
+// network). Moves are checked against the rules first, so nothing can sneak in an illegal one,
+// and nothing gets played once a side has run out of time.
+fn play_move(
+    mut commands: Commands,
+    mut play_move_event: EventReader<PlayMoveEvent>,
+    mut turn: ResMut<PlayerTurn>,
+    mut current_position: ResMut<CurrentPosition>,
+    mut pieces_query: Query<(Entity, &mut Piece)>,
+    mut checkmate_writer: EventWriter<CheckmateEvent>,
+    mut consume_writer: EventWriter<ConsumeEvent>,
+    mut castle_writer: EventWriter<CastleEvent>,
+    mut move_writer: EventWriter<MoveEvent>,
+    mut position_hash: ResMut<PositionHash>,
+    mut history: ResMut<GameHistory>,
+    asset_server: Res<AssetServer>,
+    clock: Res<ChessClock>,
+) {
+    for &PlayMoveEvent(mv) in play_move_event.read() {
+        if let Some(colour) = clock.flagged() {
+            warn!("ignoring move {mv}, {colour:?} ran out of time");
+            continue;
+        }
+        if history.ended_here() {
+            warn!("ignoring move {mv}, the game is over");
+            continue;
+        }
+        if !current_position.0.is_legal(mv) {
+            warn!("ignoring illegal move {mv}");
+            continue;
+        }
+        let pieces_entity_vec: Vec<(Entity, Piece)> = pieces_query
+            .iter()
+            .map(|(entity, piece)| (entity, piece.clone()))
+            .collect();
+        let pieces_vec = pieces_entity_vec.iter().map(|(_, piece)| piece.clone()).collect();
+        let Some(piece_entity) = pieces_entity_vec.iter()
+            .find(|(_, piece)| (piece.x, piece.y) == mv.from)
+            .map(|(entity, _)| *entity) else {
+            continue;
+        };
+        let (_, mut piece) = pieces_query.get_mut(piece_entity).unwrap();
+        let (to_x, to_y) = mv.to;
+
+        // take the moving piece and the old castling/en passant state out of the
+        // hash, they get added back once the move is done
+        position_hash.0 ^= piece_hash(&piece)
+            ^ castling_hash(&pieces_vec) ^ en_passant_hash(&pieces_vec);
+        let mut captured_piece = false;
+        let mut captured_entities = Vec::new();
+        for (dx, dy) in piece.squares_occupied.clone() {
+            let square_x = to_x.checked_add_signed(dx).expect("x < 0");
+            let square_y = to_y.checked_add_signed(dy).expect("y < 0");
+            // Check if a piece of the opposite color exists in this square and despawn it
+            for (other_entity, other_piece) in &pieces_entity_vec {
+                if other_piece.colour == piece.colour.opposite() {
+                    if other_piece.occupies_square((square_x, square_y)) {
+                        // Despawn piece
+                        consume_writer.send(ConsumeEvent { piece_entity: *other_entity });
+                        captured_piece = true;
+                    } else if other_piece.can_en_passant && (
+                        (piece.colour == PieceColour::White && other_piece.occupies_square((square_x, square_y - 1)))
+                        || (piece.colour == PieceColour::Black && other_piece.occupies_square((square_x, square_y + 1)))
+                    ) {
+                        // Despawn piece
+                        consume_writer.send(ConsumeEvent { piece_entity: *other_entity });
+                        captured_piece = true;
+                    } else {
+                        continue;
+                    }
+                    // a big piece can be captured on more than one square
+                    if !captured_entities.contains(other_entity) {
+                        position_hash.0 ^= piece_hash(other_piece);
+                        captured_entities.push(*other_entity);
+                    }
+                }
+            }
+        }
+        if captured_piece {
+            piece.consume_piece(to_x, to_y);
+        }
+        piece.transform.translation = Vec3::new(to_x as f32, 0., to_y as f32) + piece.offset;
+
+        // check if move is a castle, and if so, move rook
+        if piece.piece_type == PieceType::King && piece.x.abs_diff(to_x) == 2 {
+            if to_x < piece.x {
+                castle_writer.send(CastleEvent::Queenside(piece.colour));
+            } else {
+                castle_writer.send(CastleEvent::Kingside(piece.colour));
+            }
+        }
+
+        // check if move is a pawn 2 move
+        if piece.piece_type == PieceType::Pawn && piece.y.abs_diff(to_y) == 2 {
+            piece.can_en_passant = true;
+        }
+
+        // move piece
+        piece.x = to_x;
+        piece.y = to_y;
+        piece.has_moved = true;
+        piece.just_moved = true;
+
+        // add the piece back to the hash with its new position and footprint.
+        // every other pawn loses en passant in disable_en_passant, so only this
+        // one can have it now
+        position_hash.0 ^= piece_hash(&piece);
+        if piece.can_en_passant {
+            position_hash.0 ^= en_passant_file_hash(piece.x);
+        }
+
+        // switch turns
+        position_hash.0 ^= turn_hash(turn.0);
+        turn.0 = match turn.0 {
+            PieceColour::White => PieceColour::Black,
+            PieceColour::Black => PieceColour::White,
+        };
+        position_hash.0 ^= turn_hash(turn.0);
+
+        let remaining_pieces = pieces_query.iter()
+            .filter(|(entity, _)| !captured_entities.contains(entity))
+            .map(|(_, piece)| piece.clone())
+            .collect();
+        position_hash.0 ^= castling_hash(&remaining_pieces);
+
+        current_position.0.make_move(mv);
+        history.play(mv);
+
+        let new_pieces_vec = pieces_query.iter().map(|(_, piece)| piece.clone()).collect();
+        if is_colour_in_checkmate(turn.0, &new_pieces_vec) {
+            checkmate_writer.send(CheckmateEvent(turn.0));
+        }
+
+        commands.spawn(AudioBundle {
+            source: asset_server.load("audio/click.wav"),
+            ..default()
+        });
+
+        move_writer.send(MoveEvent);
+    }
+}
+
+fn set_position(
+    mut commands: Commands,
+    mut set_position_event: EventReader<SetPositionEvent>,
+    pieces_query: Query<Entity, With<Piece>>,
+    mut current_position: ResMut<CurrentPosition>,
+    (mut turn, mut position_hash): (ResMut<PlayerTurn>, ResMut<PositionHash>),
+    mut selected_piece: ResMut<SelectedPiece>,
+    asset_server: Res<AssetServer>,
+) {
+    // only the last one matters
+    let Some(SetPositionEvent(position)) = set_position_event.read().last() else {
+        return;
+    };
+    for piece_entity in pieces_query.iter() {
+        commands.entity(piece_entity).despawn_recursive();
+    }
+    spawn_pieces(&mut commands, &asset_server, &position.pieces);
+    turn.0 = position.turn;
+    position_hash.0 = position.hash;
+    current_position.0 = position.clone();
+    // the selected piece is gone
+    selected_piece.entity = None;
+}
 
 fn highlight_selected_squares(
 	squares_query: Query<(Entity, &Square, &Handle<StandardMaterial>)>,
@@ -186,16 +306,19 @@ fn highlight_selected_squares(
     mut materials: ResMut<Assets<StandardMaterial>>,
     selected_square: Res<SelectedSquare>,
     selected_piece: Res<SelectedPiece>,
+    hint: Res<Hint>,
     mut move_event: EventReader<Pointer<Move>>,
 ) {
     let select_piece = selected_piece.entity.map(|entity| pieces_query.get(entity).unwrap().1);
 
-    for event in move_event.read() {
-        let hover_square = squares_query.get(event.target);
+    let hover_target = move_event.read().last().map(|event| event.target);
+    // a new hint is drawn straight away, not when the mouse next moves
+    if hover_target.is_some() || hint.is_changed() {
+        let hover_square = hover_target.and_then(|target| squares_query.get(target).ok());
         for (entity, square, material_handle) in squares_query.iter() {
             let material = materials.get_mut(material_handle).unwrap();
             material.base_color = if {
-                if let Ok((hover_entity, hover_square, _)) = hover_square {
+                if let Some((hover_entity, hover_square, _)) = hover_square {
                     if let Some(piece) = select_piece {
                         let mut squares_occupied_contains_hovered_square = false;
                         for (dx, dy) in &piece.squares_occupied {
@@ -227,6 +350,12 @@ fn highlight_selected_squares(
                 } else { false }
             } {
                 Color::srgb(0.5, 0.1, 0.3)
+            } else if hint.to.contains(&(square.x, square.y)) {
+                // where the hinted piece ends up, like hovering there with it selected
+                Color::srgb(0.6, 0.5, 0.5)
+            } else if hint.from.contains(&(square.x, square.y)) {
+                // the hinted piece, like selecting it
+                Color::srgb(0.5, 0.1, 0.3)
             } else if square.is_white() {
                 // square is deselected and white
                 Color::srgb(0.9, 0.9, 1.0)
@@ -274,11 +403,16 @@ impl Plugin for SquaresPlugin {
     fn build(&self, app: &mut App) {
         app
             .insert_resource(PlayerTurn::default())
+            .insert_resource(CurrentPosition::default())
+            .insert_resource(Hint::default())
+            .insert_resource(GameHistory::default())
             .add_systems(Startup, setup_squares)
-            .add_systems(Update, (select_square, highlight_selected_squares))
+            .add_systems(Update, ((select_square.run_if(human_to_move), play_move, set_position).chain(), highlight_selected_squares))
             .add_event::<CheckmateEvent>()
             .add_event::<CastleEvent>()
             .add_event::<MoveEvent>()
+            .add_event::<PlayMoveEvent>()
+            .add_event::<SetPositionEvent>()
             .add_event::<ConsumeEvent>();
     }
 }
@@ -0,0 +1,156 @@
+use crate::eval::{type_index, PIECE_TYPES};
+use crate::piece::{PieceColour, PieceType};
+use crate::record::{GameRecord, GameResult};
+
+/// Numbers from one finished game, for working out offline whether growing pieces unbalance
+/// the game.
+#[derive(Clone, Debug, Default)]
+pub struct GameStats {
+    pub white: String,
+    pub black: String,
+    pub result: GameResult,
+    pub termination: String,
+    pub plies: usize,
+    // biggest footprint any piece of each type reached, in squares (indexed like PIECE_TYPES)
+    pub max_footprint: [usize; 6],
+    // captures made by each type of piece
+    pub captures: [u32; 6],
+    // how many times each king got bigger
+    pub white_king_growths: u32,
+    pub black_king_growths: u32,
+}
+
+impl GameStats {
+    /// Replays a game to collect its stats.
+    pub fn from_record(record: &GameRecord) -> Result<Self, String> {
+        let mut stats = GameStats {
+            white: record.tag("White").unwrap_or("?").to_string(),
+            black: record.tag("Black").unwrap_or("?").to_string(),
+            result: record.result,
+            termination: record.tag("Termination").unwrap_or("").to_string(),
+            plies: record.moves.len(),
+            max_footprint: [1; 6],
+            ..Default::default()
+        };
+
+        let mut position = record.start_position()?;
+        for (ply, &mv) in record.moves.iter().enumerate() {
+            if !position.is_legal(mv) {
+                return Err(format!("illegal move {mv} at ply {}", ply + 1));
+            }
+            let piece = position.piece_at(mv.from).expect("legal move without a piece").clone();
+            if position.is_capture(mv) {
+                stats.captures[type_index(piece.piece_type)] += 1;
+            }
+            position.make_move(mv);
+
+            let moved = position.pieces.iter().find(|p| (p.x, p.y) == mv.to).expect("moved piece is gone");
+            if piece.piece_type == PieceType::King && moved.squares_occupied.len() > piece.squares_occupied.len() {
+                match piece.colour {
+                    PieceColour::White => stats.white_king_growths += 1,
+                    PieceColour::Black => stats.black_king_growths += 1,
+                }
+            }
+            for p in &position.pieces {
+                let max = &mut stats.max_footprint[type_index(p.piece_type)];
+                *max = (*max).max(p.squares_occupied.len());
+            }
+        }
+        Ok(stats)
+    }
+
+    fn winner(&self) -> &'static str {
+        match self.result {
+            GameResult::WhiteWins => "white",
+            GameResult::BlackWins => "black",
+            GameResult::Draw => "draw",
+            GameResult::Unfinished => "none",
+        }
+    }
+
+    // column names and values, in the same order for CSV and JSON
+    fn fields(&self) -> Vec<(String, Field)> {
+        let mut fields = vec![
+            ("white".to_string(), Field::Text(self.white.clone())),
+            ("black".to_string(), Field::Text(self.black.clone())),
+            ("winner".to_string(), Field::Text(self.winner().to_string())),
+            ("termination".to_string(), Field::Text(self.termination.clone())),
+            ("plies".to_string(), Field::Number(self.plies as u64)),
+        ];
+        for (i, (_, name)) in PIECE_TYPES.iter().enumerate() {
+            fields.push((format!("max_footprint_{name}"), Field::Number(self.max_footprint[i] as u64)));
+        }
+        for (i, (_, name)) in PIECE_TYPES.iter().enumerate() {
+            fields.push((format!("captures_{name}"), Field::Number(self.captures[i] as u64)));
+        }
+        fields.push(("white_king_growths".to_string(), Field::Number(self.white_king_growths as u64)));
+        fields.push(("black_king_growths".to_string(), Field::Number(self.black_king_growths as u64)));
+        fields
+    }
+
+    pub fn csv_header() -> String {
+        let names: Vec<String> = GameStats::default().fields().into_iter().map(|(name, _)| name).collect();
+        names.join(",")
+    }
+
+    pub fn to_csv_row(&self) -> String {
+        let values: Vec<String> = self.fields().into_iter().map(|(_, value)| value.to_csv()).collect();
+        values.join(",")
+    }
+
+    pub fn to_json(&self) -> String {
+        let fields: Vec<String> = self.fields().into_iter()
+            .map(|(name, value)| format!("\"{name}\": {}", value.to_json()))
+            .collect();
+        format!("{{{}}}", fields.join(", "))
+    }
+}
+
+enum Field {
+    Text(String),
+    Number(u64),
+}
+
+impl Field {
+    fn to_csv(&self) -> String {
+        match self {
+            Field::Text(text) if text.contains([',', '"', '\n']) => format!("\"{}\"", text.replace('"', "\"\"")),
+            Field::Text(text) => text.clone(),
+            Field::Number(number) => number.to_string(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Field::Text(text) => {
+                let mut escaped = String::new();
+                for c in text.chars() {
+                    match c {
+                        '"' => escaped.push_str("\\\""),
+                        '\\' => escaped.push_str("\\\\"),
+                        c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => escaped.push(c),
+                    }
+                }
+                format!("\"{escaped}\"")
+            }
+            Field::Number(number) => number.to_string(),
+        }
+    }
+}
+
+/// Writes stats as CSV, or as a JSON array if the path ends in `.json`.
+pub fn write_stats(path: &str, stats: &[GameStats]) -> Result<(), String> {
+    let text = if path.ends_with(".json") {
+        let games: Vec<String> = stats.iter().map(|game| format!("  {}", game.to_json())).collect();
+        format!("[\n{}\n]\n", games.join(",\n"))
+    } else {
+        let mut text = GameStats::csv_header() + "\n";
+        for game in stats {
+            text += &game.to_csv_row();
+            text += "\n";
+        }
+        text
+    };
+    std::fs::write(path, text).map_err(|err| format!("can't write {path}: {err}"))
+}
@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use gmtk2024::clock::TimeoutEvent;
+use gmtk2024::eval::{type_index, PIECE_TYPES};
+use gmtk2024::history::GameHistory;
+use gmtk2024::piece::PieceColour;
+use gmtk2024::position::Move;
+use gmtk2024::record::{first_ply, move_text};
+use gmtk2024::report::{find_blunders, Blunder, GameReport, SideReport};
+use gmtk2024::search::SearchLimits;
+use gmtk2024::square::CheckmateEvent;
+
+use crate::analysis::{analysing, AnalyseEvent};
+use crate::new_game::spawn_button;
+use crate::puzzle_mode::in_puzzle;
+use crate::replay::in_replay;
+use crate::resign::AgreedResultEvent;
+
+// blunders past this many only get counted, not listed
+const MAX_BLUNDERS_SHOWN: usize = 4;
+
+/// Filled in with the report once a game is over, on the game over screen.
+#[derive(Component)]
+pub struct ReportPanel;
+
+// jumps into analysis this many plies into the game
+#[derive(Component)]
+struct KeyMomentButton(usize);
+
+/// The report on the game that just finished.
+#[derive(Resource, Default)]
+struct Report {
+    report: GameReport,
+    moves: Vec<Move>,
+    // where the move numbers start, a ply in if black moved first
+    first_ply: usize,
+    // None until the engine has been through the game
+    blunders: Option<Vec<Blunder>>,
+    task: Option<Task<Vec<Blunder>>>,
+}
+
+fn side_text(colour: PieceColour, side: &SideReport) -> String {
+    let captures = match side.captures {
+        1 => "1 capture".to_string(),
+        n => format!("{n} captures"),
+    };
+    match side.biggest {
+        Some((piece_type, squares, _)) => format!(
+            "{}: {captures}, biggest piece a {} of {squares} squares",
+            colour.name(),
+            PIECE_TYPES[type_index(piece_type)].1,
+        ),
+        None => format!("{}: {captures}, nothing grew", colour.name()),
+    }
+}
+
+// the moments worth jumping to, in the order they happened: the biggest growth, the worst
+// blunders and the last move
+fn key_moments(report: &Report) -> Vec<(usize, String)> {
+    let mut moments = Vec::new();
+    if let Some(growth) = report.report.largest_growth {
+        moments.push((growth.ply, format!(
+            "{} grows a {} by {}",
+            move_text(report.first_ply + growth.ply, &[growth.mv]),
+            PIECE_TYPES[type_index(growth.piece_type)].1,
+            growth.squares,
+        )));
+    }
+    let mut blunders = report.blunders.clone().unwrap_or_default();
+    blunders.sort_by_key(|blunder| -blunder.swing);
+    for blunder in blunders.iter().take(MAX_BLUNDERS_SHOWN) {
+        moments.push((blunder.ply, format!(
+            "{}?? drops {:.1}",
+            move_text(report.first_ply + blunder.ply, &[blunder.mv]),
+            blunder.swing as f32 / 100.0,
+        )));
+    }
+    if let Some(&last) = report.moves.last() {
+        let ply = report.moves.len() - 1;
+        moments.push((ply, format!("{} ends it", move_text(report.first_ply + ply, &[last]))));
+    }
+    moments.sort_by_key(|&(ply, _)| ply);
+    moments.dedup_by_key(|(ply, _)| *ply);
+    moments
+}
+
+fn start_report(
+    mut checkmate_events: EventReader<CheckmateEvent>,
+    mut timeout_events: EventReader<TimeoutEvent>,
+    mut agreed_result_events: EventReader<AgreedResultEvent>,
+    history: Res<GameHistory>,
+    mut report: ResMut<Report>,
+) {
+    if checkmate_events.read().count() + timeout_events.read().count() + agreed_result_events.read().count() == 0 {
+        return;
+    }
+    let start = history.start_position();
+    let moves = history.main_line();
+    let game_report = match GameReport::from_moves(&start, &moves) {
+        Ok(game_report) => game_report,
+        Err(err) => {
+            warn!("can't report on the game: {err}");
+            *report = Report::default();
+            return;
+        }
+    };
+    *report = Report { report: game_report, moves: moves.clone(), first_ply: first_ply(&start), ..default() };
+    // on the web it would have to share the one thread with the game
+    if cfg!(target_arch = "wasm32") {
+        return;
+    }
+    report.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        find_blunders(&start, &moves, SearchLimits { depth: 3, nodes: Some(5_000), time: None })
+    }));
+}
+
+fn finish_report(mut report: ResMut<Report>) {
+    // polling isn't a change, the panel only needs redoing once it's done
+    let Some(task) = report.bypass_change_detection().task.as_mut() else {
+        return;
+    };
+    if let Some(blunders) = block_on(future::poll_once(task)) {
+        report.task = None;
+        report.blunders = Some(blunders);
+    }
+}
+
+fn update_report_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    report: Res<Report>,
+    panel_query: Query<Entity, With<ReportPanel>>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/IBMPlexSerif-SemiBold.ttf"),
+        font_size: 22.0,
+        color: Color::srgb(0.9, 0.9, 0.9),
+    };
+    let panel = panel_query.single();
+    commands.entity(panel).despawn_descendants();
+
+    let mut lines = vec![
+        format!("{} moves", (report.first_ply + report.report.plies).div_ceil(2)),
+        side_text(PieceColour::White, &report.report.sides[0]),
+        side_text(PieceColour::Black, &report.report.sides[1]),
+    ];
+    lines.push(match &report.blunders {
+        Some(blunders) if blunders.is_empty() => "No blunders".to_string(),
+        Some(blunders) => format!("{} blunders", blunders.len()),
+        None if report.task.is_some() => "Looking for blunders...".to_string(),
+        None => String::new(),
+    });
+
+    commands.entity(panel).with_children(|column| {
+        for line in lines.into_iter().filter(|line| !line.is_empty()) {
+            column.spawn(TextBundle::from_section(line, text_style.clone()));
+        }
+        for (ply, text) in key_moments(&report) {
+            spawn_button(column, &asset_server, 420.0, &text, KeyMomentButton(ply));
+        }
+    });
+}
+
+fn key_moment_buttons(
+    interaction_query: Query<(&Interaction, &KeyMomentButton), Changed<Interaction>>,
+    mut analyse_writer: EventWriter<AnalyseEvent>,
+) {
+    for (interaction, button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            analyse_writer.send(AnalyseEvent(button.0));
+        }
+    }
+}
+
+pub struct SummaryPlugin;
+impl Plugin for SummaryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Report::default())
+            .add_systems(Update, (
+                start_report.run_if(not(analysing).and_then(not(in_puzzle)).and_then(not(in_replay))),
+                finish_report,
+                update_report_panel.run_if(resource_changed::<Report>),
+                key_moment_buttons,
+            ).chain());
+    }
+}
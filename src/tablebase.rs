@@ -0,0 +1,535 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::piece::{is_colour_in_check, Piece, PieceColour, PieceType};
+use crate::position::{Move, Position};
+use crate::search::MATE_SCORE;
+use crate::zobrist::castling_hash;
+
+// Endgame tables for a king and one other piece against a lone king.
+//
+// Footprints only change when something is captured, and the only capture left in these
+// endings is the lone king taking the piece, which is a dead draw. So a table covers one set
+// of footprint shapes and every position in it keeps those shapes; tables are generated for
+// every combination of shapes up to a size.
+
+const POSITIONS: usize = 2 * 64 * 64 * 64;
+
+// how a position is stored in a table: 0 can't happen, 1 is a draw, and anything else is
+// 2 + plies to mate. The side to move wins if that is odd (it delivers mate) and loses if it
+// is even (0 plies is being checkmated right now)
+const INVALID: u16 = 0;
+const DRAW: u16 = 1;
+
+const FILE_MAGIC: &[u8; 4] = b"GMTB";
+const FILE_VERSION: u8 = 1;
+
+/// What a table says about a position, from the point of view of the side to move.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Probe {
+    // plies until the side to move checkmates
+    Win(u16),
+    Draw,
+    // plies until the side to move is checkmated
+    Loss(u16),
+}
+
+impl Probe {
+    fn decode(value: u16) -> Option<Self> {
+        match value {
+            INVALID => None,
+            DRAW => Some(Probe::Draw),
+            value if !(value - 2).is_multiple_of(2) => Some(Probe::Win(value - 2)),
+            value => Some(Probe::Loss(value - 2)),
+        }
+    }
+
+    /// Search score for this result `ply` plies into the search.
+    pub fn score(&self, ply: usize) -> i32 {
+        match *self {
+            Probe::Win(plies) => MATE_SCORE - ply as i32 - plies as i32,
+            Probe::Draw => 0,
+            Probe::Loss(plies) => -MATE_SCORE + ply as i32 + plies as i32,
+        }
+    }
+
+    // the same result from the other side, a move earlier
+    fn before_move(&self) -> Self {
+        match *self {
+            Probe::Win(plies) => Probe::Loss(plies + 1),
+            Probe::Draw => Probe::Draw,
+            Probe::Loss(plies) => Probe::Win(plies + 1),
+        }
+    }
+
+    // higher is better for the side to move
+    fn rank(&self) -> i32 {
+        match *self {
+            Probe::Win(plies) => 100_000 - plies as i32,
+            Probe::Draw => 0,
+            Probe::Loss(plies) => -100_000 + plies as i32,
+        }
+    }
+}
+
+/// The pieces in a table: the stronger side's king and extra piece, and the lone king, each
+/// with its footprint as offsets from its anchor. The stronger side is white in the table;
+/// positions where it is black are looked up with the colours swapped.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Material {
+    pub piece_type: PieceType,
+    pub footprints: [Vec<(i8, i8)>; 3],
+}
+
+fn type_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::King => 'K',
+        PieceType::Queen => 'Q',
+        PieceType::Bishop => 'B',
+        PieceType::Knight => 'N',
+        PieceType::Rook => 'R',
+        PieceType::Pawn => 'P',
+    }
+}
+
+// every footprint with at most `max_size` squares that a piece can grow into. Growing adds
+// squares next to the ones already covered, so two squares is the anchor and a neighbour
+fn footprints(max_size: usize) -> Vec<Vec<(i8, i8)>> {
+    let mut shapes = vec![vec![(0, 0)]];
+    if max_size >= 2 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx, dy) != (0, 0) {
+                    shapes.push(vec![(0, 0), (dx, dy)]);
+                }
+            }
+        }
+    }
+    shapes
+}
+
+fn sorted_footprint(piece: &Piece) -> Vec<(i8, i8)> {
+    let mut footprint: Vec<(i8, i8)> = piece.squares_occupied.iter().copied().collect();
+    footprint.sort();
+    footprint
+}
+
+impl Material {
+    /// Every table for a king and `piece_type` against a king, with footprints up to
+    /// `max_footprint` squares.
+    pub fn all(piece_type: PieceType, max_footprint: usize) -> Result<Vec<Self>, String> {
+        if matches!(piece_type, PieceType::King | PieceType::Pawn) {
+            return Err("tables are for a queen, rook, bishop or knight".to_string());
+        }
+        if !(1..=2).contains(&max_footprint) {
+            return Err("footprints can be 1 or 2 squares".to_string());
+        }
+        let shapes = footprints(max_footprint);
+        let mut materials = Vec::new();
+        for strong_king in &shapes {
+            for piece in &shapes {
+                for weak_king in &shapes {
+                    materials.push(Material {
+                        piece_type,
+                        footprints: [strong_king.clone(), piece.clone(), weak_king.clone()],
+                    });
+                }
+            }
+        }
+        Ok(materials)
+    }
+
+    /// The table a position belongs in, and whether its colours have to be swapped to look it
+    /// up. None if the position has other material, or could still castle.
+    pub fn of(position: &Position) -> Option<(Self, bool)> {
+        if position.pieces.len() != 3 || castling_hash(&position.pieces) != 0 {
+            return None;
+        }
+        let piece = position.pieces.iter().find(|piece| piece.piece_type != PieceType::King)?;
+        if piece.piece_type == PieceType::Pawn {
+            return None;
+        }
+        let strong = piece.colour;
+        let king = |colour| position.pieces.iter()
+            .find(|piece| piece.piece_type == PieceType::King && piece.colour == colour);
+        let (strong_king, weak_king) = (king(strong)?, king(strong.opposite())?);
+        let material = Material {
+            piece_type: piece.piece_type,
+            footprints: [sorted_footprint(strong_king), sorted_footprint(piece), sorted_footprint(weak_king)],
+        };
+        Some((material, strong == PieceColour::Black))
+    }
+
+    /// Name for the table, e.g. `KRvK`, with the extra squares of any grown footprint after
+    /// the piece: `KR(1,0)vK`.
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+        for (i, letter) in ['K', type_letter(self.piece_type), 'K'].into_iter().enumerate() {
+            if i == 2 {
+                name.push('v');
+            }
+            name.push(letter);
+            for &(dx, dy) in &self.footprints[i] {
+                if (dx, dy) != (0, 0) {
+                    name += &format!("({dx},{dy})");
+                }
+            }
+        }
+        name
+    }
+
+    fn piece(&self, i: usize, square: usize) -> Option<Piece> {
+        let (colour, piece_type) = match i {
+            0 => (PieceColour::White, PieceType::King),
+            1 => (PieceColour::White, self.piece_type),
+            _ => (PieceColour::Black, PieceType::King),
+        };
+        let (x, y) = ((square % 8) as u8, (square / 8) as u8);
+        let mut piece = Piece::new(colour, piece_type, x, y);
+        // none of them can castle
+        piece.has_moved = true;
+        piece.squares_occupied = HashSet::new();
+        for &(dx, dy) in &self.footprints[i] {
+            // the whole footprint has to be on the board
+            x.checked_add_signed(dx).filter(|&x| x < 8)?;
+            y.checked_add_signed(dy).filter(|&y| y < 8)?;
+            piece.squares_occupied.insert((dx, dy));
+        }
+        Some(piece)
+    }
+
+    // the position stored at an index, if it is one that can come up in a game
+    fn position(&self, index: usize) -> Option<Position> {
+        let turn = if index < POSITIONS / 2 { PieceColour::White } else { PieceColour::Black };
+        let squares = [(index / 4096) % 64, (index / 64) % 64, index % 64];
+        let mut pieces = Vec::new();
+        for (i, &square) in squares.iter().enumerate() {
+            let piece = self.piece(i, square)?;
+            for other in &pieces {
+                for &(dx, dy) in &piece.squares_occupied {
+                    let square = (piece.x.checked_add_signed(dx)?, piece.y.checked_add_signed(dy)?);
+                    if Piece::occupies_square(other, square) {
+                        return None;
+                    }
+                }
+            }
+            pieces.push(piece);
+        }
+        // the side that just moved can't have left its king in check
+        if is_colour_in_check(turn.opposite(), &pieces) {
+            return None;
+        }
+        Some(Position::from_pieces(pieces, turn))
+    }
+}
+
+// index of a position in its table, with white as the stronger side
+fn index(position: &Position, swap_colours: bool) -> usize {
+    let relative = |colour: PieceColour| if swap_colours { colour.opposite() } else { colour };
+    let mut squares = [0; 3];
+    for piece in &position.pieces {
+        let i = match (relative(piece.colour), piece.piece_type) {
+            (PieceColour::White, PieceType::King) => 0,
+            (PieceColour::White, _) => 1,
+            (PieceColour::Black, _) => 2,
+        };
+        squares[i] = piece.y as usize * 8 + piece.x as usize;
+    }
+    let turn = match relative(position.turn) {
+        PieceColour::White => 0,
+        PieceColour::Black => POSITIONS / 2,
+    };
+    turn + squares[0] * 4096 + squares[1] * 64 + squares[2]
+}
+
+/// Win, draw or loss and distance to mate for every position with one set of material.
+pub struct Table {
+    pub material: Material,
+    values: Vec<u16>,
+}
+
+impl Table {
+    /// Works out every position by retrograde analysis: starting from the checkmates, walks
+    /// back through the moves that lead to them, a ply at a time.
+    pub fn generate(material: Material) -> Self {
+        let mut values = vec![INVALID; POSITIONS];
+        // moves out of each position that stay in the table, and whether it can capture into
+        // the drawn king against king
+        let mut successors: Vec<Vec<u32>> = vec![Vec::new(); POSITIONS];
+        let mut can_escape = vec![false; POSITIONS];
+        let mut queue = VecDeque::new();
+
+        for (i, value) in values.iter_mut().enumerate() {
+            let Some(position) = material.position(i) else {
+                continue;
+            };
+            let moves = position.legal_moves();
+            if moves.is_empty() {
+                if position.in_check() {
+                    *value = 2;
+                    queue.push_back(i);
+                } else {
+                    *value = DRAW;
+                }
+                continue;
+            }
+            // unknown until something below says otherwise
+            *value = u16::MAX;
+            for mv in moves {
+                if position.is_capture(mv) {
+                    can_escape[i] = true;
+                    continue;
+                }
+                let mut child = position.clone();
+                child.make_move(mv);
+                successors[i].push(index(&child, false) as u32);
+            }
+        }
+
+        let mut predecessors: Vec<Vec<u32>> = vec![Vec::new(); POSITIONS];
+        let mut unresolved_moves = vec![0u16; POSITIONS];
+        for (i, children) in successors.into_iter().enumerate() {
+            unresolved_moves[i] = children.len() as u16;
+            for child in children {
+                predecessors[child as usize].push(i as u32);
+            }
+        }
+
+        // breadth first, so the first time a position is reached is its shortest mate
+        while let Some(i) = queue.pop_front() {
+            let plies = values[i] - 2;
+            let lost = plies.is_multiple_of(2);
+            for &parent in &predecessors[i] {
+                let parent = parent as usize;
+                if values[parent] != u16::MAX {
+                    continue;
+                }
+                if lost {
+                    // moving here wins
+                    values[parent] = plies + 3;
+                    queue.push_back(parent);
+                } else {
+                    unresolved_moves[parent] -= 1;
+                    if unresolved_moves[parent] == 0 && !can_escape[parent] {
+                        // every move loses, and this was the slowest one
+                        values[parent] = plies + 3;
+                        queue.push_back(parent);
+                    }
+                }
+            }
+        }
+
+        for value in values.iter_mut() {
+            if *value == u16::MAX {
+                *value = DRAW;
+            }
+        }
+        Self { material, values }
+    }
+
+    pub fn probe(&self, position: &Position) -> Option<Probe> {
+        let (material, swap_colours) = Material::of(position)?;
+        if material != self.material {
+            return None;
+        }
+        Probe::decode(self.values[index(position, swap_colours)])
+    }
+
+    /// How many positions are wins, draws and losses for the side to move.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let (mut wins, mut draws, mut losses) = (0, 0, 0);
+        for &value in &self.values {
+            match Probe::decode(value) {
+                Some(Probe::Win(_)) => wins += 1,
+                Some(Probe::Draw) => draws += 1,
+                Some(Probe::Loss(_)) => losses += 1,
+                None => {}
+            }
+        }
+        (wins, draws, losses)
+    }
+
+    /// The longest mate in the table, in plies.
+    pub fn longest_mate(&self) -> u16 {
+        self.values.iter().filter(|&&value| value > DRAW).map(|&value| value - 2).max().unwrap_or(0)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let name = self.material.name();
+        let mut bytes = Vec::with_capacity(6 + name.len() + POSITIONS * 2);
+        bytes.extend_from_slice(FILE_MAGIC);
+        bytes.push(FILE_VERSION);
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        for value in &self.values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        std::fs::write(path, bytes).map_err(|err| format!("can't write {}: {err}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let error = |message: &str| format!("{}: {message}", path.display());
+        let bytes = std::fs::read(path).map_err(|err| error(&err.to_string()))?;
+        if bytes.len() < 6 || &bytes[..4] != FILE_MAGIC {
+            return Err(error("not a table"));
+        }
+        if bytes[4] != FILE_VERSION {
+            return Err(error(&format!("table version {} but this reads version {FILE_VERSION}", bytes[4])));
+        }
+        let name_end = 6 + bytes[5] as usize;
+        let name = std::str::from_utf8(bytes.get(6..name_end).ok_or_else(|| error("cut short"))?)
+            .map_err(|_| error("bad name"))?;
+        let material = parse_name(name).ok_or_else(|| error(&format!("bad material {name}")))?;
+        let data = &bytes[name_end..];
+        if data.len() != POSITIONS * 2 {
+            return Err(error("wrong size"));
+        }
+        let values = data.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        Ok(Self { material, values })
+    }
+}
+
+// reads what Material::name writes
+fn parse_name(name: &str) -> Option<Material> {
+    let mut pieces: Vec<(char, Vec<(i8, i8)>)> = Vec::new();
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            'v' => {}
+            '(' => {
+                let offset: String = chars.by_ref().take_while(|&c| c != ')').collect();
+                let (dx, dy) = offset.split_once(',')?;
+                pieces.last_mut()?.1.push((dx.parse().ok()?, dy.parse().ok()?));
+            }
+            letter => pieces.push((letter, vec![(0, 0)])),
+        }
+    }
+    if pieces.len() != 3 || pieces[0].0 != 'K' || pieces[2].0 != 'K' {
+        return None;
+    }
+    let piece_type = match pieces[1].0 {
+        'Q' => PieceType::Queen,
+        'B' => PieceType::Bishop,
+        'N' => PieceType::Knight,
+        'R' => PieceType::Rook,
+        _ => return None,
+    };
+    let mut footprints = pieces.into_iter().map(|(_, mut footprint)| {
+        footprint.sort();
+        footprint
+    });
+    Some(Material {
+        piece_type,
+        footprints: [footprints.next()?, footprints.next()?, footprints.next()?],
+    })
+}
+
+/// Every table that has been loaded or generated, shared by the engine and the game.
+#[derive(Default)]
+pub struct Tablebases {
+    tables: RwLock<HashMap<Material, Arc<Table>>>,
+}
+
+pub fn tablebases() -> &'static Tablebases {
+    static TABLEBASES: OnceLock<Tablebases> = OnceLock::new();
+    TABLEBASES.get_or_init(Tablebases::default)
+}
+
+impl Tablebases {
+    pub fn add(&self, table: Table) {
+        self.tables.write().unwrap().insert(table.material.clone(), Arc::new(table));
+    }
+
+    pub fn contains(&self, material: &Material) -> bool {
+        self.tables.read().unwrap().contains_key(material)
+    }
+
+    /// Loads every `.tb` file in a directory, returning how many there were.
+    pub fn load_dir(&self, dir: impl AsRef<Path>) -> Result<usize, String> {
+        let dir = dir.as_ref();
+        let entries = std::fs::read_dir(dir).map_err(|err| format!("can't read {}: {err}", dir.display()))?;
+        let mut count = 0;
+        for entry in entries {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            if path.extension().is_some_and(|extension| extension == "tb") {
+                self.add(Table::load(&path)?);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn probe(&self, position: &Position) -> Option<Probe> {
+        // cheap enough to call at every node of a search
+        if position.pieces.len() != 3 {
+            return None;
+        }
+        let (material, _) = Material::of(position)?;
+        let table = self.tables.read().unwrap().get(&material)?.clone();
+        table.probe(position)
+    }
+
+    /// The best move by the tables: the fastest win, else a draw, else the slowest loss.
+    pub fn best_move(&self, position: &Position) -> Option<(Move, Probe)> {
+        self.probe(position)?;
+        let mut best: Option<(Move, Probe)> = None;
+        for mv in position.legal_moves() {
+            let result = if position.is_capture(mv) {
+                // only the lone king can capture, into king against king
+                Probe::Draw
+            } else {
+                let mut child = position.clone();
+                child.make_move(mv);
+                self.probe(&child)?.before_move()
+            };
+            if best.is_none_or(|(_, best_result)| result.rank() > best_result.rank()) {
+                best = Some((mv, result));
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // generating it takes a while, so both tests share one
+    fn krk() -> &'static Table {
+        static TABLE: OnceLock<Table> = OnceLock::new();
+        TABLE.get_or_init(|| {
+            let position = Position::from_setup("Kg6 Ra1 kg8 w").unwrap();
+            Table::generate(Material::of(&position).unwrap().0)
+        })
+    }
+
+    #[test]
+    fn rook_mate_in_one() {
+        let table = krk();
+        assert_eq!(table.material.name(), "KRvK");
+        let position = Position::from_setup("Kg6 Ra1 kg8 w").unwrap();
+        assert_eq!(table.probe(&position), Some(Probe::Win(1)));
+        let mut mated = position.clone();
+        mated.make_move(Move::from_notation("a1a8").unwrap());
+        assert_eq!(table.probe(&mated), Some(Probe::Loss(0)));
+        // the same with the colours the other way round
+        let swapped = Position::from_setup("kg6 ra1 Kg8 b").unwrap();
+        assert_eq!(table.probe(&swapped), Some(Probe::Win(1)));
+    }
+
+    #[test]
+    fn save_then_load_gives_the_same_table() {
+        let table = krk();
+        let path = std::env::temp_dir().join(format!("gmtb-test-{}.gmtb", std::process::id()));
+        table.save(&path).unwrap();
+        let loaded = Table::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.material, table.material);
+        // not assert_eq, which would print every value on failure
+        assert!(loaded.values == table.values);
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::net::DEFAULT_PORT;
+use crate::sha1::sha1;
+
+#[cfg(target_arch = "wasm32")]
+pub use browser::BrowserSocket;
+
+// even the whole of a long game is nowhere near this long, so a line that is isn't from a game
+const MAX_LINE: usize = 64 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// what every WebSocket server adds to the key it's sent to show it understood
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const TEXT: u8 = 1;
+const CLOSE: u8 = 8;
+const PING: u8 = 9;
+const PONG: u8 = 10;
+
+/// Carries lines of text to the other side and back, however it does it. Never blocks, so it
+/// can be checked every frame.
+pub trait Transport: Send + Sync {
+    /// Sends a line, without its newline, as soon as it can.
+    fn send_line(&mut self, line: &str) -> Result<(), String>;
+
+    /// The next line that has come in, if there is one. An error once the connection is closed
+    /// or broken, after the lines that came before that.
+    fn receive_line(&mut self) -> Result<Option<String>, String>;
+
+    /// Who's on the other end.
+    fn peer(&self) -> String;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Framing {
+    // a connection that has been accepted, but hasn't said anything yet
+    Unknown,
+    // a line of text at a time, each ending in a newline
+    Lines,
+    // a line to a WebSocket frame. Only a client masks what it sends
+    WebSocket { masked: bool },
+}
+
+/// A connection over TCP, sending either plain lines or WebSocket frames. Connections that
+/// are accepted work out which from the first thing the other side sends, so a browser can
+/// join the same host or server as the desktop version.
+pub struct TcpTransport {
+    stream: TcpStream,
+    framing: Framing,
+    // what came off the socket, then the text in it once it's out of its frames
+    incoming: Vec<u8>,
+    text: Vec<u8>,
+    // a message split over several frames, so far
+    fragment: Vec<u8>,
+    outgoing: Vec<u8>,
+    // sent before it was known how to frame them
+    unsent: Vec<String>,
+    // the other side has closed it, there's nothing more coming after what's in `incoming`
+    closed: bool,
+}
+
+// adds the default port if `address` doesn't have one
+fn connect_tcp(address: &str) -> Result<TcpStream, String> {
+    let with_port = if address.contains(':') { address.to_string() } else { format!("{address}:{DEFAULT_PORT}") };
+    let socket_address = with_port.to_socket_addrs()
+        .map_err(|err| format!("can't find {address}: {err}"))?
+        .next()
+        .ok_or(format!("can't find {address}"))?;
+    TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)
+        .map_err(|err| format!("can't connect to {address}: {err}"))
+}
+
+impl TcpTransport {
+    fn new(stream: TcpStream, framing: Framing) -> Result<Self, String> {
+        stream.set_nonblocking(true).map_err(|err| format!("can't set up the connection: {err}"))?;
+        // moves are tiny and someone is waiting on each one
+        stream.set_nodelay(true).map_err(|err| format!("can't set up the connection: {err}"))?;
+        Ok(Self {
+            stream,
+            framing,
+            incoming: Vec::new(),
+            text: Vec::new(),
+            fragment: Vec::new(),
+            outgoing: Vec::new(),
+            unsent: Vec::new(),
+            closed: false,
+        })
+    }
+
+    /// A connection someone else made, in plain lines or WebSocket frames.
+    pub fn accepted(stream: TcpStream) -> Result<Self, String> {
+        Self::new(stream, Framing::Unknown)
+    }
+
+    /// Connects to `address` and sends plain lines. Blocks until it connects or gives up.
+    pub fn connect(address: &str) -> Result<Self, String> {
+        Self::new(connect_tcp(address)?, Framing::Lines)
+    }
+
+    /// Connects to a `ws://` URL. Blocks until the other side agrees to talk WebSocket or it
+    /// gives up.
+    pub fn connect_websocket(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("ws://").ok_or(format!("{url} isn't a ws:// address"))?;
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, path)) => (authority, format!("/{path}")),
+            None => (rest, "/".to_string()),
+        };
+        let mut stream = connect_tcp(authority)?;
+        let key = base64(&random_bytes());
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {authority}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        );
+        let lost = |err: std::io::Error| format!("can't connect to {url}: {err}");
+        stream.write_all(request.as_bytes()).map_err(lost)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT)).map_err(lost)?;
+        let mut response = Vec::new();
+        let mut chunk = [0; 256];
+        let end = loop {
+            if let Some(end) = find(&response, b"\r\n\r\n") {
+                break end + 4;
+            }
+            match stream.read(&mut chunk).map_err(lost)? {
+                0 => return Err(format!("{url} closed the connection")),
+                n => response.extend_from_slice(&chunk[..n]),
+            }
+            if response.len() > MAX_LINE {
+                return Err(format!("{url} isn't a WebSocket server"));
+            }
+        };
+        let head = String::from_utf8_lossy(&response[..end]);
+        let switching = head.lines().next().is_some_and(|status| status.split_whitespace().nth(1) == Some("101"));
+        if !switching || header(&head, "sec-websocket-accept") != Some(accept_key(&key)) {
+            return Err(format!("{url} isn't a WebSocket server"));
+        }
+        let mut transport = Self::new(stream, Framing::WebSocket { masked: true })?;
+        // frames that came in right behind the answer
+        transport.incoming.extend_from_slice(&response[end..]);
+        Ok(transport)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err("connection lost".to_string()),
+                Ok(n) => { self.outgoing.drain(..n); },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(format!("connection lost: {err}")),
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<(), String> {
+        let mut chunk = [0; 256];
+        while !self.closed && self.incoming.len() <= MAX_LINE {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.closed = true,
+                Ok(n) => self.incoming.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(format!("connection lost: {err}")),
+            }
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, line: &str) {
+        match self.framing {
+            Framing::Unknown => self.unsent.push(line.to_string()),
+            Framing::Lines => self.outgoing.extend_from_slice(format!("{line}\n").as_bytes()),
+            Framing::WebSocket { masked } => self.outgoing.extend(frame(TEXT, line.as_bytes(), masked)),
+        }
+    }
+
+    // works out what an accepted connection is speaking, answering a browser's request to
+    // talk WebSocket. Nothing is sent before the other side's first line, so it's never long
+    fn pick_framing(&mut self) -> Result<(), String> {
+        if self.incoming.len() < 4 {
+            return Ok(());
+        }
+        if !self.incoming.starts_with(b"GET ") {
+            self.framing = Framing::Lines;
+        } else {
+            let Some(end) = find(&self.incoming, b"\r\n\r\n") else {
+                return Ok(());
+            };
+            let request: Vec<u8> = self.incoming.drain(..end + 4).collect();
+            let request = String::from_utf8_lossy(&request);
+            let key = header(&request, "sec-websocket-key").ok_or("the other side isn't sending messages")?;
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key),
+            );
+            self.outgoing.extend_from_slice(response.as_bytes());
+            self.framing = Framing::WebSocket { masked: false };
+        }
+        for line in std::mem::take(&mut self.unsent) {
+            self.queue(&line);
+        }
+        Ok(())
+    }
+
+    // takes the text out of whatever frames have come in whole
+    fn unframe(&mut self, masked: bool) -> Result<(), String> {
+        while let Some((last, opcode, payload, length)) = parse_frame(&self.incoming) {
+            self.incoming.drain(..length);
+            match opcode {
+                0 | TEXT => {
+                    self.fragment.extend_from_slice(&payload);
+                    if last {
+                        self.text.append(&mut self.fragment);
+                        self.text.push(b'\n');
+                    }
+                }
+                CLOSE => {
+                    self.outgoing.extend(frame(CLOSE, &payload, masked));
+                    self.closed = true;
+                }
+                PING => self.outgoing.extend(frame(PONG, &payload, masked)),
+                PONG => {},
+                _ => return Err("the other side isn't sending messages".to_string()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_line(&mut self, line: &str) -> Result<(), String> {
+        self.queue(line);
+        self.flush()
+    }
+
+    fn receive_line(&mut self) -> Result<Option<String>, String> {
+        self.flush()?;
+        self.read()?;
+        if self.framing == Framing::Unknown {
+            self.pick_framing()?;
+        }
+        match self.framing {
+            Framing::Unknown => {},
+            Framing::Lines => self.text.append(&mut self.incoming),
+            Framing::WebSocket { masked } => self.unframe(masked)?,
+        }
+        // a close, a pong or the answer to a browser
+        self.flush()?;
+        while let Some(end) = self.text.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.text.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if !line.trim().is_empty() {
+                return Ok(Some(line.trim().to_string()));
+            }
+        }
+        if self.text.len() + self.incoming.len() > MAX_LINE {
+            return Err("the other side isn't sending messages".to_string());
+        }
+        if self.closed {
+            return Err("the other side closed the connection".to_string());
+        }
+        Ok(None)
+    }
+
+    fn peer(&self) -> String {
+        self.stream.peer_addr().map_or("?".to_string(), |address| address.ip().to_string())
+    }
+}
+
+impl Drop for TcpTransport {
+    // says goodbye properly, which browsers otherwise report as an error
+    fn drop(&mut self) {
+        if let Framing::WebSocket { masked } = self.framing {
+            if !self.closed {
+                let _ = self.stream.write(&frame(CLOSE, &[], masked));
+            }
+        }
+    }
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes.windows(pattern.len()).position(|window| window == pattern)
+}
+
+// the value of the header `name` in an HTTP request or response
+fn header(head: &str, name: &str) -> Option<String> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+    })
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{WEBSOCKET_GUID}").as_bytes()))
+}
+
+fn frame(opcode: u8, payload: &[u8], masked: bool) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    let mask_bit = if masked { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => frame.push(mask_bit | length as u8),
+        length @ 126..=0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    if masked {
+        let mask = &random_bytes()[..4];
+        frame.extend_from_slice(mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    frame
+}
+
+// the first whole frame in `bytes`: whether it ends a message, its opcode, what's in it and
+// how many bytes it took up
+fn parse_frame(bytes: &[u8]) -> Option<(bool, u8, Vec<u8>, usize)> {
+    let &[first, second, ..] = bytes else {
+        return None;
+    };
+    let (length, mut start) = match second & 0x7f {
+        126 => (u16::from_be_bytes(bytes.get(2..4)?.try_into().ok()?) as usize, 4),
+        127 => (usize::try_from(u64::from_be_bytes(bytes.get(2..10)?.try_into().ok()?)).ok()?, 10),
+        length => (length as usize, 2),
+    };
+    let mask = if second & 0x80 != 0 {
+        start += 4;
+        bytes.get(start - 4..start)?
+    } else {
+        &[0; 4]
+    };
+    let end = start.checked_add(length)?;
+    let payload = bytes.get(start..end)?.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]).collect();
+    Some((first & 0x80 != 0, first & 0x0f, payload, end))
+}
+
+// not for secrets, just so keys and masks change from one to the next
+fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0; 16];
+    for half in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            text.push(if i <= chunk.len() { ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+    text
+}
+
+/// The address a browser connects to for `address`, which can leave out the `ws://` and the
+/// port.
+pub fn websocket_url(address: &str) -> String {
+    if address.contains("://") {
+        return address.to_string();
+    }
+    let (authority, path) = address.split_once('/').map_or((address, ""), |(authority, path)| (authority, path));
+    if authority.contains(':') {
+        format!("ws://{address}")
+    } else {
+        format!("ws://{authority}:{DEFAULT_PORT}/{path}")
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+    use web_sys::{CloseEvent, Event, MessageEvent, WebSocket};
+
+    use super::Transport;
+
+    #[derive(Default)]
+    struct Inbox {
+        lines: VecDeque<String>,
+        // why the socket closed, once it has
+        closed: Option<String>,
+    }
+
+    /// A WebSocket the browser makes, the only kind of connection a web page can.
+    pub struct BrowserSocket {
+        socket: WebSocket,
+        inbox: Rc<RefCell<Inbox>>,
+        // sent before the socket opened
+        unsent: Vec<String>,
+        // the socket calls these until it's dropped
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+        _on_close: Closure<dyn FnMut(CloseEvent)>,
+        _on_error: Closure<dyn FnMut(Event)>,
+    }
+
+    // SAFETY: the wasm build has no atomics or threads (it isn't built with the `atomics`
+    // target feature), so there's never another thread to send it to or share it with. Only
+    // needed because `Transport` is `Send + Sync`, so connections can be kept in resources
+    unsafe impl Send for BrowserSocket {}
+    unsafe impl Sync for BrowserSocket {}
+
+    impl BrowserSocket {
+        /// Starts connecting to `url` without waiting for it, anything sent meanwhile goes out
+        /// once it opens.
+        pub fn connect(url: &str) -> Result<Self, String> {
+            let socket = WebSocket::new(url).map_err(|err| format!("can't connect to {url}: {err:?}"))?;
+            let inbox = Rc::new(RefCell::new(Inbox::default()));
+            let on_message = {
+                let inbox = inbox.clone();
+                Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                    if let Some(text) = event.data().as_string() {
+                        let lines = text.lines().filter(|line| !line.trim().is_empty());
+                        inbox.borrow_mut().lines.extend(lines.map(|line| line.trim().to_string()));
+                    }
+                })
+            };
+            let on_close = {
+                let inbox = inbox.clone();
+                Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+                    inbox.borrow_mut().closed.get_or_insert(if event.was_clean() {
+                        "the other side closed the connection".to_string()
+                    } else {
+                        format!("connection lost ({})", event.code())
+                    });
+                })
+            };
+            let on_error = {
+                let inbox = inbox.clone();
+                let url = url.to_string();
+                Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                    inbox.borrow_mut().closed.get_or_insert(format!("can't connect to {url}"));
+                })
+            };
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+            socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            Ok(Self { socket, inbox, unsent: Vec::new(), _on_message: on_message, _on_close: on_close, _on_error: on_error })
+        }
+
+        fn flush(&mut self) -> Result<(), String> {
+            // a socket that closed says so through the inbox
+            if self.socket.ready_state() != WebSocket::OPEN {
+                return Ok(());
+            }
+            for line in self.unsent.drain(..) {
+                self.socket.send_with_str(&line).map_err(|err| format!("connection lost: {err:?}"))?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Transport for BrowserSocket {
+        fn send_line(&mut self, line: &str) -> Result<(), String> {
+            self.unsent.push(line.to_string());
+            self.flush()
+        }
+
+        fn receive_line(&mut self) -> Result<Option<String>, String> {
+            self.flush()?;
+            let mut inbox = self.inbox.borrow_mut();
+            if let Some(line) = inbox.lines.pop_front() {
+                return Ok(Some(line));
+            }
+            match &inbox.closed {
+                Some(reason) => Err(reason.clone()),
+                None => Ok(None),
+            }
+        }
+
+        fn peer(&self) -> String {
+            self.socket.url()
+        }
+    }
+
+    impl Drop for BrowserSocket {
+        fn drop(&mut self) {
+            let _ = self.socket.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // an accepted connection, and the other end of it
+    fn accepted_pair() -> (TcpTransport, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(CONNECT_TIMEOUT)).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (TcpTransport::accepted(stream).unwrap(), client)
+    }
+
+    // what comes in until the framing has been picked
+    fn pick(transport: &mut TcpTransport) -> Option<String> {
+        for _ in 0..500 {
+            let line = transport.receive_line().unwrap();
+            if transport.framing != Framing::Unknown {
+                return line;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("never worked out the framing");
+    }
+
+    fn next_line(transport: &mut TcpTransport) -> String {
+        for _ in 0..500 {
+            if let Some(line) = transport.receive_line().unwrap() {
+                return line;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("nothing came in");
+    }
+
+    // reads from `stream` until `bytes` holds something `done` accepts
+    fn read_until(stream: &mut TcpStream, mut done: impl FnMut(&[u8]) -> bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut chunk = [0; 256];
+        while !done(&bytes) {
+            let n = stream.read(&mut chunk).unwrap();
+            assert_ne!(n, 0, "closed early");
+            bytes.extend_from_slice(&chunk[..n]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for length in [0, 5, 125, 126, 1000, 0xffff, 0x10000] {
+            let payload: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+            let (length_byte, extra) = match length {
+                0..=125 => (length as u8, 0),
+                126..=0xffff => (126, 2),
+                _ => (127, 8),
+            };
+            for masked in [false, true] {
+                let framed = frame(TEXT, &payload, masked);
+                assert_eq!(framed[1] & 0x7f, length_byte);
+                assert_eq!(framed[1] & 0x80 != 0, masked);
+                assert_eq!(framed.len(), 2 + extra + if masked { 4 } else { 0 } + length);
+                assert_eq!(parse_frame(&framed), Some((true, TEXT, payload.clone(), framed.len())));
+                // nothing until the whole frame has come in
+                assert_eq!(parse_frame(&framed[..framed.len() - 1]), None);
+                // and only the first when there's more behind it
+                let mut two = framed.clone();
+                two.extend(frame(PING, b"ping", masked));
+                assert_eq!(parse_frame(&two), Some((true, TEXT, payload.clone(), framed.len())));
+            }
+        }
+    }
+
+    #[test]
+    fn accept_key_matches_the_rfc() {
+        // the example in RFC 6455, section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn raw_hello_picks_lines() {
+        let (mut transport, mut client) = accepted_pair();
+        // held back until it's known how to send it
+        transport.send_line("seat 1 abc").unwrap();
+        client.write_all(b"hello 1\n").unwrap();
+        assert_eq!(pick(&mut transport).as_deref(), Some("hello 1"));
+        assert_eq!(transport.framing, Framing::Lines);
+        let sent = read_until(&mut client, |bytes| bytes.ends_with(b"\n"));
+        assert_eq!(sent, b"seat 1 abc\n");
+    }
+
+    #[test]
+    fn get_upgrade_picks_websocket() {
+        let (mut transport, mut client) = accepted_pair();
+        transport.send_line("seat 1 abc").unwrap();
+        client.write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        ).unwrap();
+        assert_eq!(pick(&mut transport), None);
+        assert_eq!(transport.framing, Framing::WebSocket { masked: false });
+
+        // the answer, then the line that was waiting for it
+        let answer = read_until(&mut client, |bytes| {
+            find(bytes, b"\r\n\r\n").is_some_and(|end| parse_frame(&bytes[end + 4..]).is_some())
+        });
+        let end = find(&answer, b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&answer[..end]);
+        assert!(head.starts_with("HTTP/1.1 101 "));
+        assert_eq!(header(&head, "sec-websocket-accept").as_deref(), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        // the server doesn't mask what it sends
+        assert_eq!(answer[end + 1] & 0x80, 0);
+        assert_eq!(parse_frame(&answer[end..]).map(|(_, _, payload, _)| payload), Some(b"seat 1 abc".to_vec()));
+
+        client.write_all(&frame(TEXT, b"move e2e4", true)).unwrap();
+        assert_eq!(next_line(&mut transport), "move e2e4");
+    }
+}
@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+
+use crate::piece::{Piece, PieceColour, PieceType};
+
+// Zobrist keys. A piece is hashed by its colour and type together with its anchor square
+// and every square it covers, so two pieces on the same anchor with different shapes hash
+// differently.
+struct ZobristKeys {
+    anchors: [[[u64; 64]; 6]; 2],
+    squares: [[[u64; 64]; 6]; 2],
+    castling: [u64; 4],
+    en_passant: [u64; 8],
+    black_to_move: u64,
+}
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (state, z ^ (z >> 31))
+}
+
+impl ZobristKeys {
+    // generated at compile time from a fixed seed so hashes are stable between runs (opening
+    // books and recorded games store them)
+    const fn generate() -> Self {
+        let mut keys = Self {
+            anchors: [[[0; 64]; 6]; 2],
+            squares: [[[0; 64]; 6]; 2],
+            castling: [0; 4],
+            en_passant: [0; 8],
+            black_to_move: 0,
+        };
+        let mut state = 0x6368657373_u64;
+        let mut key;
+
+        let mut colour = 0;
+        while colour < 2 {
+            let mut piece_type = 0;
+            while piece_type < 6 {
+                let mut square = 0;
+                while square < 64 {
+                    (state, key) = splitmix64(state);
+                    keys.anchors[colour][piece_type][square] = key;
+                    (state, key) = splitmix64(state);
+                    keys.squares[colour][piece_type][square] = key;
+                    square += 1;
+                }
+                piece_type += 1;
+            }
+            colour += 1;
+        }
+
+        let mut i = 0;
+        while i < 4 {
+            (state, key) = splitmix64(state);
+            keys.castling[i] = key;
+            i += 1;
+        }
+        let mut i = 0;
+        while i < 8 {
+            (state, key) = splitmix64(state);
+            keys.en_passant[i] = key;
+            i += 1;
+        }
+        (_, key) = splitmix64(state);
+        keys.black_to_move = key;
+        keys
+    }
+}
+
+const KEYS: ZobristKeys = ZobristKeys::generate();
+
+/// Zobrist hash of the position on the board, updated incrementally as moves are made.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PositionHash(pub u64);
+
+fn square_index(x: u8, y: u8) -> usize {
+    y as usize * 8 + x as usize
+}
+
+fn colour_index(colour: PieceColour) -> usize {
+    match colour {
+        PieceColour::White => 0,
+        PieceColour::Black => 1,
+    }
+}
+
+fn piece_type_index(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::Bishop => 2,
+        PieceType::Knight => 3,
+        PieceType::Rook => 4,
+        PieceType::Pawn => 5,
+    }
+}
+
+/// Hash of a single piece: its colour, type, anchor square and footprint.
+pub fn piece_hash(piece: &Piece) -> u64 {
+    let colour = colour_index(piece.colour);
+    let piece_type = piece_type_index(piece.piece_type);
+    let mut hash = KEYS.anchors[colour][piece_type][square_index(piece.x, piece.y)];
+    for &(dx, dy) in &piece.squares_occupied {
+        let (Some(x), Some(y)) = (piece.x.checked_add_signed(dx), piece.y.checked_add_signed(dy)) else {
+            continue;
+        };
+        if x < 8 && y < 8 {
+            hash ^= KEYS.squares[colour][piece_type][square_index(x, y)];
+        }
+    }
+    hash
+}
+
+/// Hash of the castling rights. A side keeps a right while its king and the rook in that
+/// corner have not moved.
+pub fn castling_hash(pieces: &Vec<Piece>) -> u64 {
+    let mut hash = 0;
+    for colour in [PieceColour::White, PieceColour::Black] {
+        let king_unmoved = pieces.iter()
+            .any(|p| p.colour == colour && p.piece_type == PieceType::King && !p.has_moved);
+        if !king_unmoved {
+            continue;
+        }
+        for piece in pieces {
+            if piece.colour == colour && piece.piece_type == PieceType::Rook && !piece.has_moved {
+                if piece.x == 0 {
+                    hash ^= KEYS.castling[colour_index(colour) * 2];
+                } else if piece.x == 7 {
+                    hash ^= KEYS.castling[colour_index(colour) * 2 + 1];
+                }
+            }
+        }
+    }
+    hash
+}
+
+/// Hash of the en passant state, keyed by the file of any pawn that can be taken en passant.
+pub fn en_passant_hash(pieces: &Vec<Piece>) -> u64 {
+    let mut hash = 0;
+    for piece in pieces {
+        if piece.piece_type == PieceType::Pawn && piece.can_en_passant {
+            hash ^= en_passant_file_hash(piece.x);
+        }
+    }
+    hash
+}
+
+pub fn en_passant_file_hash(x: u8) -> u64 {
+    KEYS.en_passant[x as usize]
+}
+
+pub fn turn_hash(turn: PieceColour) -> u64 {
+    match turn {
+        PieceColour::White => 0,
+        PieceColour::Black => KEYS.black_to_move,
+    }
+}
+
+/// Hashes a position from scratch. Everything else should update the hash incrementally;
+/// this is for setting up a new board and for checking the incremental hash.
+pub fn position_hash(pieces: &Vec<Piece>, turn: PieceColour) -> u64 {
+    let mut hash = castling_hash(pieces) ^ en_passant_hash(pieces) ^ turn_hash(turn);
+    for piece in pieces {
+        hash ^= piece_hash(piece);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{Move, Position};
+
+    // plays the moves, checking the incrementally updated hash after each one
+    fn play_checking_hash(mut position: Position, moves: &[&str]) -> Position {
+        for notation in moves {
+            let mv = Move::from_notation(notation).unwrap();
+            assert!(position.is_legal(mv), "{notation} isn't legal in {}", position.setup());
+            position.make_move(mv);
+            assert_eq!(position.hash, position_hash(&position.pieces, position.turn), "after {notation}");
+        }
+        position
+    }
+
+    #[test]
+    fn hash_after_castling() {
+        let position = play_checking_hash(Position::default(), &["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "f8c5", "e1g1"]);
+        assert!(position.piece_at((2, 0)).is_some_and(|piece| piece.piece_type == PieceType::Rook));
+    }
+
+    #[test]
+    fn hash_after_en_passant() {
+        let position = play_checking_hash(Position::default(), &["e2e4", "a7a6", "e4e5", "d7d5", "e5d6"]);
+        assert!(position.piece_at((4, 4)).is_none());
+    }
+
+    #[test]
+    fn hash_after_growth_captures() {
+        // the knight grows taking the pawn, then the grown knight is taken and grows the queen
+        let position = play_checking_hash(Position::default(), &["e2e4", "d7d5", "b1c3", "d5e4", "c3e4", "d8d5", "d1f3", "d5e4"]);
+        assert!(position.pieces.iter().any(|piece| piece.squares_occupied.len() > 1));
+        // a grown rook taking two knights at once
+        let grown = Position::from_setup("Rh1+g1 Ke1 nh4 ng4 ke8 w").unwrap();
+        let position = play_checking_hash(grown, &["h1h4", "e8d8"]);
+        assert_eq!(position.pieces.len(), 3);
+    }
+
+    #[test]
+    fn hash_after_promotion() {
+        let position = Position::from_setup("Pa7 Pc7 Ke1 nb8 kh8 w").unwrap();
+        let position = play_checking_hash(position, &["a7a8", "h8g8", "c7b8"]);
+        assert_eq!(position.pieces.iter().filter(|piece| piece.piece_type == PieceType::Queen).count(), 2);
+    }
+}
@@ -0,0 +1,188 @@
+// Plays games through the server binary, one player sending plain lines and the other WebSocket
+// frames, the way the desktop and web versions do.
+
+use gmtk2024::clock::TimeControl;
+use gmtk2024::net::{Connection, Message, PROTOCOL_VERSION};
+use gmtk2024::piece::PieceColour;
+use gmtk2024::position::{Move, Position};
+use gmtk2024::record::GameResult;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+// stops the server however the test ends. Its output is kept open, as it stops if it can't
+// write to it
+struct Server {
+    child: Child,
+    _output: BufReader<ChildStdout>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// a server on a port nobody's using, started with `options`, and that port
+fn start_server(options: &[&str]) -> (Server, u16) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(["--port", "0"])
+        .args(options)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    output.read_line(&mut line).unwrap();
+    // "listening on 192.168.1.20:40123, no clock"
+    let port = line.split([':', ',']).nth(1).and_then(|port| port.parse().ok());
+    let server = Server { child, _output: output };
+    (server, port.unwrap_or_else(|| panic!("can't find the port in {line:?}")))
+}
+
+fn next_message(connection: &mut Connection) -> Message {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if let Some(message) = connection.receive().unwrap() {
+            return message;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("nothing came from the server");
+}
+
+// checks nothing more comes from the server for a while
+fn nothing_more(connection: &mut Connection) {
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(connection.receive().unwrap(), None);
+}
+
+// checks the server seats `connection` on `colour`'s side, and gives back the game and the
+// token for coming back to it
+fn seated(connection: &mut Connection, colour: PieceColour, time_control: Option<TimeControl>) -> (u32, String) {
+    let Message::Seat { game, token } = next_message(connection) else {
+        panic!("expected a seat");
+    };
+    assert_eq!(next_message(connection), Message::Start { colour, time_control });
+    (game, token)
+}
+
+struct Players {
+    white: Connection,
+    black: Connection,
+    game: u32,
+    white_token: String,
+}
+
+// white on plain lines and black on WebSocket, paired into a game
+fn start_game(port: u16, time_control: Option<TimeControl>) -> Players {
+    let hello = Message::Hello { version: PROTOCOL_VERSION };
+    // the first to say hello plays white, once someone else comes to play black
+    let mut white = Connection::connect(&format!("127.0.0.1:{port}")).unwrap();
+    white.send(&hello).unwrap();
+    // nothing says the server has read it, so give it time to
+    std::thread::sleep(Duration::from_millis(200));
+    let mut black = Connection::connect(&format!("ws://127.0.0.1:{port}/")).unwrap();
+    black.send(&hello).unwrap();
+    let (game, white_token) = seated(&mut white, PieceColour::White, time_control);
+    assert_eq!(seated(&mut black, PieceColour::Black, time_control).0, game);
+    Players { white, black, game, white_token }
+}
+
+// plays `moves` from the start, checking each reaches the other player
+fn play(players: &mut Players, moves: &[&str]) {
+    let mut position = Position::default();
+    for mv in moves {
+        let mv = Move::from_notation(mv).unwrap();
+        let (mover, other) = match position.turn {
+            PieceColour::White => (&mut players.white, &mut players.black),
+            PieceColour::Black => (&mut players.black, &mut players.white),
+        };
+        mover.send(&Message::Move(mv)).unwrap();
+        assert_eq!(next_message(other), Message::Move(mv));
+        position.make_move(mv);
+    }
+}
+
+#[test]
+fn lines_and_websocket_play_each_other() {
+    let (_server, port) = start_server(&[]);
+    let mut players = start_game(port, None);
+    play(&mut players, &["e2e4", "e7e5", "g1f3"]);
+
+    // a move out of turn is turned down, and not passed on
+    let mv = Move::from_notation("d2d4").unwrap();
+    players.white.send(&Message::Move(mv)).unwrap();
+    assert!(matches!(next_message(&mut players.white), Message::Reject(_)));
+    nothing_more(&mut players.black);
+}
+
+#[test]
+fn illegal_moves_are_rejected() {
+    let (_server, port) = start_server(&[]);
+    let mut players = start_game(port, None);
+    let mv = Move::from_notation("e2e5").unwrap();
+    players.white.send(&Message::Move(mv)).unwrap();
+    assert!(matches!(next_message(&mut players.white), Message::Reject(_)));
+    nothing_more(&mut players.black);
+    // and the player can still make a proper one
+    play(&mut players, &["e2e4"]);
+}
+
+#[test]
+fn a_player_can_come_back_with_their_token() {
+    let (_server, port) = start_server(&[]);
+    let mut players = start_game(port, None);
+    play(&mut players, &["e2e4", "e7e5"]);
+
+    drop(std::mem::replace(&mut players.white, Connection::connect(&format!("127.0.0.1:{port}")).unwrap()));
+    let resume = Message::Resume { version: PROTOCOL_VERSION, game: players.game, token: players.white_token.clone() };
+    players.white.send(&resume).unwrap();
+    assert_eq!(next_message(&mut players.white), Message::Start { colour: PieceColour::White, time_control: None });
+    let moves = ["e2e4", "e7e5"].map(|mv| Move::from_notation(mv).unwrap());
+    assert_eq!(next_message(&mut players.white), Message::State(moves.to_vec()));
+
+    // and the game carries on
+    let mv = Move::from_notation("g1f3").unwrap();
+    players.white.send(&Message::Move(mv)).unwrap();
+    assert_eq!(next_message(&mut players.black), Message::Move(mv));
+}
+
+#[test]
+fn a_player_who_doesnt_come_back_loses() {
+    let (_server, port) = start_server(&["--grace", "1"]);
+    let mut players = start_game(port, None);
+    play(&mut players, &["e2e4"]);
+    drop(players.white);
+    let over = Message::Over { result: GameResult::BlackWins, reason: "abandoned".to_string() };
+    assert_eq!(next_message(&mut players.black), over);
+}
+
+#[test]
+fn the_server_clock_flags_a_player() {
+    // no time at all, so white runs out before they can move
+    let (_server, port) = start_server(&["--clock", "0+1"]);
+    let time_control = Some(TimeControl::new(0, 1));
+    let mut players = start_game(port, time_control);
+    let over = Message::Over { result: GameResult::BlackWins, reason: "timeout".to_string() };
+    assert_eq!(next_message(&mut players.white), over);
+    assert_eq!(next_message(&mut players.black), over);
+}
+
+#[test]
+fn spectators_catch_up_then_follow_the_game() {
+    let (_server, port) = start_server(&[]);
+    let mut players = start_game(port, None);
+    play(&mut players, &["e2e4", "e7e5"]);
+
+    let mut spectator = Connection::connect(&format!("127.0.0.1:{port}")).unwrap();
+    spectator.send(&Message::Watch { version: PROTOCOL_VERSION, game: Some(players.game) }).unwrap();
+    assert_eq!(next_message(&mut spectator), Message::Watching { time_control: None });
+    let moves = ["e2e4", "e7e5"].map(|mv| Move::from_notation(mv).unwrap());
+    assert_eq!(next_message(&mut spectator), Message::State(moves.to_vec()));
+
+    let mv = Move::from_notation("g1f3").unwrap();
+    players.white.send(&Message::Move(mv)).unwrap();
+    assert_eq!(next_message(&mut spectator), Message::Move(mv));
+}